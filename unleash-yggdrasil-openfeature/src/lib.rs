@@ -0,0 +1,616 @@
+#![cfg_attr(not(test), deny(clippy::expect_used, clippy::unwrap_used))]
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use open_feature::provider::{FeatureProvider, ProviderMetadata, ResolutionDetails};
+use open_feature::{
+    async_trait, EvaluationContext, EvaluationContextFieldValue, EvaluationError,
+    EvaluationErrorCode, EvaluationReason as OpenFeatureReason, EvaluationResult, StructValue,
+    Value,
+};
+use time::format_description::well_known::Rfc3339;
+use unleash_yggdrasil::payload::{PayloadError, PayloadType, TypedPayload};
+use unleash_yggdrasil::shared_engine::SharedEngine;
+use unleash_yggdrasil::state::PropertyValue;
+use unleash_yggdrasil::{
+    Context, EngineState, EvaluationReason, ExtendedVariantDef, StructuredContext,
+};
+
+const PROVIDER_NAME: &str = "unleash-yggdrasil";
+
+/// An OpenFeature provider that evaluates toggles locally against a [`SharedEngine`].
+///
+/// Boolean flags resolve to whether the toggle is enabled, every other flag type
+/// resolves to the payload of the variant the toggle selects. The engine can keep
+/// receiving updates through a clone of the handle passed in here after the provider
+/// has been handed to OpenFeature. Every evaluation is counted in the engine's metrics
+pub struct UnleashProvider {
+    engine: Arc<SharedEngine>,
+    metadata: ProviderMetadata,
+}
+
+impl UnleashProvider {
+    pub fn new(engine: Arc<SharedEngine>) -> Self {
+        Self {
+            engine,
+            metadata: ProviderMetadata::new(PROVIDER_NAME),
+        }
+    }
+
+    pub fn engine(&self) -> &Arc<SharedEngine> {
+        &self.engine
+    }
+
+    fn resolve_payload<T>(
+        &self,
+        flag_key: &str,
+        evaluation_context: &EvaluationContext,
+        read: impl FnOnce(&ExtendedVariantDef) -> EvaluationResult<T>,
+    ) -> EvaluationResult<ResolutionDetails<T>> {
+        let variant = self
+            .engine
+            .get_variant(flag_key, &to_context(evaluation_context), &None);
+        if variant.reason == EvaluationReason::FlagNotFound {
+            return Err(flag_not_found(flag_key));
+        }
+        self.engine.count_toggle(flag_key, variant.feature_enabled);
+        self.engine.count_variant(flag_key, &variant.name);
+
+        Ok(ResolutionDetails {
+            value: read(&variant)?,
+            variant: Some(variant.name),
+            reason: Some(to_open_feature_reason(variant.reason)),
+            flag_metadata: None,
+        })
+    }
+}
+
+impl From<EngineState> for UnleashProvider {
+    fn from(engine: EngineState) -> Self {
+        UnleashProvider::new(Arc::new(engine.into()))
+    }
+}
+
+#[async_trait]
+impl FeatureProvider for UnleashProvider {
+    fn metadata(&self) -> &ProviderMetadata {
+        &self.metadata
+    }
+
+    async fn resolve_bool_value(
+        &self,
+        flag_key: &str,
+        evaluation_context: &EvaluationContext,
+    ) -> EvaluationResult<ResolutionDetails<bool>> {
+        let resolved = self
+            .engine
+            .resolve(flag_key, &to_context(evaluation_context), &None)
+            .ok_or_else(|| flag_not_found(flag_key))?;
+        self.engine.count_toggle(flag_key, resolved.enabled);
+
+        Ok(ResolutionDetails {
+            value: resolved.enabled,
+            variant: None,
+            reason: Some(to_open_feature_reason(resolved.reason)),
+            flag_metadata: None,
+        })
+    }
+
+    async fn resolve_int_value(
+        &self,
+        flag_key: &str,
+        evaluation_context: &EvaluationContext,
+    ) -> EvaluationResult<ResolutionDetails<i64>> {
+        self.resolve_payload(flag_key, evaluation_context, |variant| {
+            let number = variant.payload_number().map_err(payload_error)?;
+            // Integral floats within range convert exactly, anything else isn't an int
+            if number.fract() != 0.0 || number < i64::MIN as f64 || number >= i64::MAX as f64 {
+                return Err(error(
+                    EvaluationErrorCode::TypeMismatch,
+                    format!("Payload {number} is not an integer"),
+                ));
+            }
+            Ok(number as i64)
+        })
+    }
+
+    async fn resolve_float_value(
+        &self,
+        flag_key: &str,
+        evaluation_context: &EvaluationContext,
+    ) -> EvaluationResult<ResolutionDetails<f64>> {
+        self.resolve_payload(flag_key, evaluation_context, |variant| {
+            variant.payload_number().map_err(payload_error)
+        })
+    }
+
+    async fn resolve_string_value(
+        &self,
+        flag_key: &str,
+        evaluation_context: &EvaluationContext,
+    ) -> EvaluationResult<ResolutionDetails<String>> {
+        self.resolve_payload(flag_key, evaluation_context, |variant| {
+            // A csv payload is a string as far as OpenFeature is concerned
+            let csv = variant
+                .payload
+                .as_ref()
+                .filter(|payload| payload.payload_type.parse() == Ok(PayloadType::Csv));
+            match csv {
+                Some(payload) => Ok(payload.value.clone()),
+                None => variant
+                    .payload_string()
+                    .map(str::to_string)
+                    .map_err(payload_error),
+            }
+        })
+    }
+
+    async fn resolve_struct_value(
+        &self,
+        flag_key: &str,
+        evaluation_context: &EvaluationContext,
+    ) -> EvaluationResult<ResolutionDetails<StructValue>> {
+        self.resolve_payload(flag_key, evaluation_context, |variant| {
+            let json: serde_json::Value = variant.payload_json().map_err(payload_error)?;
+            match Value::try_from(json)? {
+                Value::Struct(value) => Ok(value),
+                _ => Err(error(
+                    EvaluationErrorCode::TypeMismatch,
+                    "Payload is JSON but not an object",
+                )),
+            }
+        })
+    }
+}
+
+/// Translates an OpenFeature context into an Unleash one. The targeting key becomes
+/// the user id and the fields that match a built in context field by name are set on
+/// that field, everything else becomes a property. Struct fields holding a list of
+/// strings, an OpenFeature `Value` or JSON become structured properties, so constraints
+/// can match against lists and nested objects. Any other struct is left out
+pub fn to_context(evaluation_context: &EvaluationContext) -> StructuredContext {
+    let mut context = Context {
+        user_id: evaluation_context.targeting_key.clone(),
+        ..Context::default()
+    };
+    let mut properties = HashMap::new();
+    let mut structured_properties = HashMap::new();
+
+    for (name, value) in &evaluation_context.custom_fields {
+        let field = match name.as_str() {
+            "userId" => &mut context.user_id,
+            "sessionId" => &mut context.session_id,
+            "environment" => &mut context.environment,
+            "appName" => &mut context.app_name,
+            "currentTime" => &mut context.current_time,
+            "remoteAddress" => &mut context.remote_address,
+            _ => {
+                if let Some(value) = field_to_string(value) {
+                    properties.insert(name.clone(), value);
+                } else if let Some(value) = field_to_property(value) {
+                    structured_properties.insert(name.clone(), value);
+                }
+                continue;
+            }
+        };
+        // The targeting key wins over a userId field
+        if let Some(value) = field_to_string(value) {
+            field.get_or_insert(value);
+        }
+    }
+
+    if !properties.is_empty() {
+        context.properties = Some(properties);
+    }
+    StructuredContext {
+        context,
+        structured_properties,
+    }
+}
+
+fn field_to_string(value: &EvaluationContextFieldValue) -> Option<String> {
+    match value {
+        EvaluationContextFieldValue::Bool(value) => Some(value.to_string()),
+        EvaluationContextFieldValue::Int(value) => Some(value.to_string()),
+        EvaluationContextFieldValue::Float(value) => Some(value.to_string()),
+        EvaluationContextFieldValue::String(value) => Some(value.clone()),
+        EvaluationContextFieldValue::DateTime(value) => value.format(&Rfc3339).ok(),
+        EvaluationContextFieldValue::Struct(_) => None,
+    }
+}
+
+fn field_to_property(value: &EvaluationContextFieldValue) -> Option<PropertyValue> {
+    let EvaluationContextFieldValue::Struct(value) = value else {
+        return None;
+    };
+    if let Some(values) = value.downcast_ref::<Vec<String>>() {
+        return Some(PropertyValue::List(values.clone()));
+    }
+    if let Some(value) = value.downcast_ref::<Value>() {
+        return value_to_property(value);
+    }
+    if let Some(value) = value.downcast_ref::<StructValue>() {
+        return Some(struct_to_property(value));
+    }
+    if let Some(json) = value.downcast_ref::<serde_json::Value>() {
+        return value_to_property(&Value::try_from(json.clone()).ok()?);
+    }
+    value.downcast_ref::<PropertyValue>().cloned()
+}
+
+// Lists only hold strings, so anything nested inside a list is left out
+fn value_to_property(value: &Value) -> Option<PropertyValue> {
+    Some(match value {
+        Value::Array(values) => {
+            PropertyValue::List(values.iter().filter_map(value_to_string).collect())
+        }
+        Value::Struct(value) => struct_to_property(value),
+        value => PropertyValue::String(value_to_string(value)?),
+    })
+}
+
+fn struct_to_property(value: &StructValue) -> PropertyValue {
+    PropertyValue::Object(
+        value
+            .fields
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), value_to_property(value)?)))
+            .collect(),
+    )
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Bool(value) => Some(value.to_string()),
+        Value::Int(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        Value::String(value) => Some(value.clone()),
+        Value::Array(_) | Value::Struct(_) => None,
+    }
+}
+
+fn to_open_feature_reason(reason: EvaluationReason) -> OpenFeatureReason {
+    match reason {
+        EvaluationReason::Disabled | EvaluationReason::DependencyUnsatisfied => {
+            OpenFeatureReason::Disabled
+        }
+        EvaluationReason::Error => OpenFeatureReason::Error,
+        EvaluationReason::FlagNotFound | EvaluationReason::Default => OpenFeatureReason::Default,
+        EvaluationReason::TargetingMatch { .. } | EvaluationReason::VariantOverride { .. } => {
+            OpenFeatureReason::TargetingMatch
+        }
+        EvaluationReason::StrategyVariant { .. } | EvaluationReason::ToggleVariant { .. } => {
+            OpenFeatureReason::Split
+        }
+        EvaluationReason::Unknown => OpenFeatureReason::Unknown,
+    }
+}
+
+// A toggle that's off or has no matching variant has no payload, so the caller's
+// default is used
+fn payload_error(payload_error: PayloadError) -> EvaluationError {
+    let code = match payload_error {
+        PayloadError::Missing => EvaluationErrorCode::General("VARIANT_NOT_FOUND".into()),
+        PayloadError::UnknownType(_) | PayloadError::WrongType { .. } => {
+            EvaluationErrorCode::TypeMismatch
+        }
+        PayloadError::Invalid { .. } => EvaluationErrorCode::ParseError,
+    };
+    error(code, payload_error.to_string())
+}
+
+fn flag_not_found(flag_key: &str) -> EvaluationError {
+    error(
+        EvaluationErrorCode::FlagNotFound,
+        format!("Flag {flag_key} does not exist"),
+    )
+}
+
+fn error(code: EvaluationErrorCode, message: impl Into<String>) -> EvaluationError {
+    EvaluationError {
+        code,
+        message: Some(message.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+    use time::macros::datetime;
+    use unleash_types::client_features::ClientFeatures;
+    use unleash_yggdrasil::UpdateMessage;
+
+    fn variant_toggle(name: &str, payload_type: &str, value: &str) -> serde_json::Value {
+        json!({
+            "name": name,
+            "enabled": true,
+            "strategies": [{ "name": "default" }],
+            "variants": [{
+                "name": "only",
+                "weight": 1000,
+                "stickiness": "default",
+                "payload": { "type": payload_type, "value": value }
+            }]
+        })
+    }
+
+    fn provider() -> UnleashProvider {
+        let state: ClientFeatures = serde_json::from_value(json!({
+            "version": 2,
+            "features": [
+                {
+                    "name": "users",
+                    "enabled": true,
+                    "strategies": [{ "name": "userWithId", "parameters": { "userIds": "7" } }]
+                },
+                { "name": "off", "enabled": false, "strategies": [{ "name": "default" }] },
+                variant_toggle("string", "string", "hello"),
+                variant_toggle("csv", "csv", "a,b"),
+                variant_toggle("int", "number", "42"),
+                variant_toggle("float", "number", "1.5"),
+                variant_toggle("not-a-number", "number", "many"),
+                variant_toggle("object", "json", r#"{ "color": "blue", "size": 3 }"#),
+                variant_toggle("array", "json", "[1, 2]"),
+                variant_toggle("broken-json", "json", "{"),
+                {
+                    "name": "admins",
+                    "enabled": true,
+                    "strategies": [{
+                        "name": "default",
+                        "constraints": [
+                            { "contextName": "roles", "operator": "IN", "values": ["admin"] },
+                            { "contextName": "org.team", "operator": "IN", "values": ["core"] }
+                        ]
+                    }]
+                },
+            ]
+        }))
+        .unwrap();
+        let engine = SharedEngine::new(EngineState::default());
+        engine.take_state(UpdateMessage::FullResponse(state));
+        UnleashProvider::new(Arc::new(engine))
+    }
+
+    fn error_code<T: std::fmt::Debug>(result: EvaluationResult<T>) -> EvaluationErrorCode {
+        result.unwrap_err().code
+    }
+
+    #[tokio::test]
+    async fn boolean_flags_resolve_to_whether_the_toggle_is_enabled() {
+        let provider = provider();
+        let user = EvaluationContext::default().with_targeting_key("7");
+
+        let enabled = provider.resolve_bool_value("users", &user).await.unwrap();
+        let other_user = provider
+            .resolve_bool_value(
+                "users",
+                &EvaluationContext::default().with_targeting_key("8"),
+            )
+            .await
+            .unwrap();
+        let off = provider.resolve_bool_value("off", &user).await.unwrap();
+
+        assert!(enabled.value);
+        assert_eq!(enabled.reason, Some(OpenFeatureReason::TargetingMatch));
+        assert!(!other_user.value);
+        assert_eq!(other_user.reason, Some(OpenFeatureReason::Default));
+        assert!(!off.value);
+        assert_eq!(off.reason, Some(OpenFeatureReason::Disabled));
+    }
+
+    #[tokio::test]
+    async fn typed_flags_resolve_to_the_variant_payload() {
+        let provider = provider();
+        let context = EvaluationContext::default();
+
+        let string = provider
+            .resolve_string_value("string", &context)
+            .await
+            .unwrap();
+        assert_eq!(string.value, "hello");
+        assert_eq!(string.variant.as_deref(), Some("only"));
+        assert_eq!(string.reason, Some(OpenFeatureReason::Split));
+        assert_eq!(
+            provider
+                .resolve_string_value("csv", &context)
+                .await
+                .unwrap()
+                .value,
+            "a,b"
+        );
+        assert_eq!(
+            provider
+                .resolve_int_value("int", &context)
+                .await
+                .unwrap()
+                .value,
+            42
+        );
+        assert_eq!(
+            provider
+                .resolve_float_value("float", &context)
+                .await
+                .unwrap()
+                .value,
+            1.5
+        );
+
+        let object = provider
+            .resolve_struct_value("object", &context)
+            .await
+            .unwrap()
+            .value;
+        assert_eq!(object.fields["color"].as_str(), Some("blue"));
+        assert_eq!(object.fields["size"].as_i64(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn failures_are_reported_with_open_feature_error_codes() {
+        let provider = provider();
+        let context = EvaluationContext::default();
+
+        assert_eq!(
+            error_code(provider.resolve_bool_value("missing", &context).await),
+            EvaluationErrorCode::FlagNotFound
+        );
+        assert_eq!(
+            error_code(provider.resolve_string_value("missing", &context).await),
+            EvaluationErrorCode::FlagNotFound
+        );
+        assert_eq!(
+            error_code(provider.resolve_int_value("string", &context).await),
+            EvaluationErrorCode::TypeMismatch
+        );
+        assert_eq!(
+            error_code(provider.resolve_int_value("float", &context).await),
+            EvaluationErrorCode::TypeMismatch
+        );
+        assert_eq!(
+            error_code(provider.resolve_struct_value("array", &context).await),
+            EvaluationErrorCode::TypeMismatch
+        );
+        assert_eq!(
+            error_code(provider.resolve_float_value("not-a-number", &context).await),
+            EvaluationErrorCode::ParseError
+        );
+        assert_eq!(
+            error_code(provider.resolve_struct_value("broken-json", &context).await),
+            EvaluationErrorCode::ParseError
+        );
+        assert_eq!(
+            error_code(provider.resolve_string_value("off", &context).await),
+            EvaluationErrorCode::General("VARIANT_NOT_FOUND".into())
+        );
+    }
+
+    #[test]
+    fn evaluation_contexts_are_translated_field_by_field() {
+        let context = to_context(
+            &EvaluationContext::default()
+                .with_targeting_key("7")
+                .with_custom_field("userId", "8")
+                .with_custom_field("sessionId", "session")
+                .with_custom_field("environment", "production")
+                .with_custom_field("currentTime", datetime!(2026-01-02 03:04:05 UTC))
+                .with_custom_field("tier", "gold")
+                .with_custom_field("seats", 12)
+                .with_custom_field("beta", true)
+                .with_custom_field("nested", EvaluationContextFieldValue::new_struct(1)),
+        );
+
+        assert!(context.structured_properties.is_empty());
+        let context = context.context;
+        assert_eq!(context.user_id.as_deref(), Some("7"));
+        assert_eq!(context.session_id.as_deref(), Some("session"));
+        assert_eq!(context.environment.as_deref(), Some("production"));
+        assert_eq!(
+            context.current_time.as_deref(),
+            Some("2026-01-02T03:04:05Z")
+        );
+        assert_eq!(
+            context.properties,
+            Some(HashMap::from([
+                ("tier".to_string(), "gold".to_string()),
+                ("seats".to_string(), "12".to_string()),
+                ("beta".to_string(), "true".to_string()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn lists_and_structs_become_structured_properties() {
+        let context = to_context(
+            &EvaluationContext::default()
+                .with_custom_field(
+                    "roles",
+                    EvaluationContextFieldValue::new_struct(vec!["admin".to_string()]),
+                )
+                .with_custom_field(
+                    "org",
+                    EvaluationContextFieldValue::new_struct(json!({ "team": "core", "size": 3 })),
+                )
+                .with_custom_field(
+                    "tags",
+                    EvaluationContextFieldValue::new_struct(Value::Array(vec![
+                        Value::String("a".into()),
+                        Value::Int(2),
+                        Value::Array(vec![]),
+                    ])),
+                ),
+        );
+
+        assert_eq!(
+            context.structured_properties,
+            HashMap::from([
+                (
+                    "roles".to_string(),
+                    PropertyValue::List(vec!["admin".into()])
+                ),
+                (
+                    "org".to_string(),
+                    PropertyValue::Object(HashMap::from([
+                        ("team".to_string(), PropertyValue::String("core".into())),
+                        ("size".to_string(), PropertyValue::String("3".into())),
+                    ]))
+                ),
+                (
+                    "tags".to_string(),
+                    PropertyValue::List(vec!["a".into(), "2".into()])
+                ),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn constraints_match_against_lists_and_nested_fields() {
+        let provider = provider();
+        let context = |roles: &[&str]| {
+            EvaluationContext::default()
+                .with_custom_field(
+                    "roles",
+                    EvaluationContextFieldValue::new_struct(
+                        roles
+                            .iter()
+                            .map(|role| role.to_string())
+                            .collect::<Vec<_>>(),
+                    ),
+                )
+                .with_custom_field(
+                    "org",
+                    EvaluationContextFieldValue::new_struct(json!({ "team": "core" })),
+                )
+        };
+
+        let admin = provider
+            .resolve_bool_value("admins", &context(&["viewer", "admin"]))
+            .await
+            .unwrap();
+        let viewer = provider
+            .resolve_bool_value("admins", &context(&["viewer"]))
+            .await
+            .unwrap();
+
+        assert!(admin.value);
+        assert!(!viewer.value);
+    }
+
+    #[tokio::test]
+    async fn evaluations_are_counted_in_the_engine_metrics() {
+        let provider = provider();
+        let context = EvaluationContext::default();
+
+        provider.resolve_bool_value("off", &context).await.unwrap();
+        provider
+            .resolve_string_value("string", &context)
+            .await
+            .unwrap();
+
+        let metrics = provider.engine().get_metrics(Utc::now()).unwrap();
+        assert_eq!(metrics.toggles["off"].no, 1);
+        assert_eq!(metrics.toggles["string"].yes, 1);
+        assert_eq!(metrics.toggles["string"].variants["only"], 1);
+    }
+}
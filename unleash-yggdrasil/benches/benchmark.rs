@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::hint::black_box;
 
 use criterion::{criterion_group, criterion_main, Criterion};
 use unleash_types::client_features::{
     ClientFeature, ClientFeatures, Constraint, Operator, Strategy,
 };
+use unleash_yggdrasil::state::EnrichedContext;
 use unleash_yggdrasil::{Context, EngineState};
 
 fn is_enabled(engine: &EngineState, toggle_name: &str, context: &Context) {
@@ -174,11 +176,79 @@ fn benchmark_engine_ingestion(c: &mut Criterion) {
     });
 }
 
+// Many toggles comparing against the same typed context fields, which resolve_all
+// only parses once where evaluating toggle by toggle parses them for every toggle
+fn typed_constraint_state(toggle_count: usize) -> ClientFeatures {
+    let constraint = |context_name: &str, operator: Operator, value: &str| Constraint {
+        context_name: context_name.into(),
+        operator,
+        case_insensitive: false,
+        inverted: false,
+        values: None,
+        value: Some(value.into()),
+    };
+    ClientFeatures {
+        version: 2,
+        features: (0..toggle_count)
+            .map(|index| ClientFeature {
+                name: format!("toggle-{index}"),
+                enabled: true,
+                strategies: Some(vec![Strategy {
+                    name: "default".into(),
+                    segments: None,
+                    constraints: Some(vec![
+                        constraint("currentTime", Operator::DateAfter, "2024-01-01T00:00:00Z"),
+                        constraint("appVersion", Operator::SemverGte, "1.0.0"),
+                        constraint("userId", Operator::NumGt, "5"),
+                    ]),
+                    parameters: None,
+                    sort_order: None,
+                    variants: None,
+                }]),
+                ..ClientFeature::default()
+            })
+            .collect(),
+        segments: None,
+        query: None,
+        meta: None,
+    }
+}
+
+fn benchmark_typed_constraints(c: &mut Criterion) {
+    let mut engine = EngineState::default();
+    engine.apply_client_features(typed_constraint_state(1000));
+    let toggle_names: Vec<String> = (0..1000).map(|index| format!("toggle-{index}")).collect();
+    let context = Context {
+        user_id: Some("7".into()),
+        session_id: None,
+        environment: None,
+        app_name: None,
+        current_time: Some("2025-06-01T12:00:00.000Z".into()),
+        remote_address: None,
+        properties: Some(HashMap::from([("appVersion".into(), "1.2.3".into())])),
+    };
+    // Evaluating toggle by toggle parses each toggle's values for itself, resolve_all
+    // parses them once and shares them between toggles
+    c.bench_function("typed constraints without parsed values", |b| {
+        b.iter(|| {
+            for toggle_name in &toggle_names {
+                let enriched_context =
+                    EnrichedContext::from(black_box(&context), toggle_name, None);
+                engine.check_enabled(&enriched_context);
+            }
+        })
+    });
+    c.bench_function("typed constraints through resolve_all", |b| {
+        b.iter(|| engine.resolve_all(black_box(&context), &None))
+    });
+}
+
 criterion_group!(
     benches,
     benchmark_with_no_strategy,
     benchmark_with_single_constraint,
     benchmark_with_two_constraints,
-    benchmark_engine_ingestion
+    benchmark_engine_ingestion,
+    benchmark_typed_constraints
 );
 criterion_main!(benches);
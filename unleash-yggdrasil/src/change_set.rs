@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use unleash_types::client_features::{ClientFeature, ClientFeatures, Segment, Strategy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Enabled,
+    Strategies,
+    Variants,
+    Dependencies,
+    ImpressionData,
+    Segments,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModifiedToggle {
+    pub name: String,
+    pub changes: Vec<ChangeKind>,
+}
+
+/// The toggles affected by a state update. Names are sorted so that the same update
+/// always produces the same change set
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeSet {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<ModifiedToggle>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    pub(crate) fn between(old: &ClientFeatures, new: &ClientFeatures) -> ChangeSet {
+        let old_segments = segments_by_id(&old.segments);
+        let new_segments = segments_by_id(&new.segments);
+        let changed_segments = old_segments
+            .keys()
+            .chain(new_segments.keys())
+            .filter(|id| {
+                segment_changed(
+                    old_segments.get(*id).copied(),
+                    new_segments.get(*id).copied(),
+                )
+            })
+            .copied()
+            .collect();
+
+        let old_features: HashMap<&str, &ClientFeature> = old
+            .features
+            .iter()
+            .map(|feature| (feature.name.as_str(), feature))
+            .collect();
+
+        let mut change_set = ChangeSet::default();
+        for feature in &new.features {
+            change_set.record(
+                old_features.get(feature.name.as_str()).copied(),
+                Some(feature),
+                &changed_segments,
+            );
+        }
+
+        let new_features: HashSet<&str> = new
+            .features
+            .iter()
+            .map(|feature| feature.name.as_str())
+            .collect();
+        for feature in &old.features {
+            if !new_features.contains(feature.name.as_str()) {
+                change_set.record(Some(feature), None, &changed_segments);
+            }
+        }
+
+        change_set.sort();
+        change_set
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        old: Option<&ClientFeature>,
+        new: Option<&ClientFeature>,
+        changed_segments: &HashSet<i32>,
+    ) {
+        match (old, new) {
+            (None, Some(new)) => self.added.push(new.name.clone()),
+            (Some(old), None) => self.removed.push(old.name.clone()),
+            (Some(old), Some(new)) => {
+                let changes = modifications(old, new, changed_segments);
+                if !changes.is_empty() {
+                    self.modified.push(ModifiedToggle {
+                        name: new.name.clone(),
+                        changes,
+                    });
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    pub(crate) fn sort(&mut self) {
+        self.added.sort();
+        self.removed.sort();
+        self.modified.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+}
+
+pub(crate) fn referenced_segments(toggle: &ClientFeature) -> impl Iterator<Item = &i32> {
+    toggle
+        .strategies
+        .iter()
+        .flatten()
+        .flat_map(|strategy| strategy.segments.iter().flatten())
+}
+
+// Segments only compare their ids, so the constraints need comparing explicitly
+pub(crate) fn segment_changed(old: Option<&Segment>, new: Option<&Segment>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => old.constraints != new.constraints,
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+fn segments_by_id(segments: &Option<Vec<Segment>>) -> HashMap<i32, &Segment> {
+    segments
+        .iter()
+        .flatten()
+        .map(|segment| (segment.id, segment))
+        .collect()
+}
+
+// Strategies leave their variants out of equality, those matter here
+fn strategies_changed(old: &[Strategy], new: &[Strategy]) -> bool {
+    old.len() != new.len()
+        || old
+            .iter()
+            .zip(new)
+            .any(|(old, new)| old != new || old.variants != new.variants)
+}
+
+fn modifications(
+    old: &ClientFeature,
+    new: &ClientFeature,
+    changed_segments: &HashSet<i32>,
+) -> Vec<ChangeKind> {
+    let mut changes = vec![];
+
+    if old.enabled != new.enabled {
+        changes.push(ChangeKind::Enabled);
+    }
+    if strategies_changed(
+        old.strategies.as_deref().unwrap_or_default(),
+        new.strategies.as_deref().unwrap_or_default(),
+    ) {
+        changes.push(ChangeKind::Strategies);
+    }
+    if old.variants.as_deref().unwrap_or_default() != new.variants.as_deref().unwrap_or_default() {
+        changes.push(ChangeKind::Variants);
+    }
+    if old.dependencies.as_deref().unwrap_or_default()
+        != new.dependencies.as_deref().unwrap_or_default()
+    {
+        changes.push(ChangeKind::Dependencies);
+    }
+    if old.impression_data.unwrap_or_default() != new.impression_data.unwrap_or_default() {
+        changes.push(ChangeKind::ImpressionData);
+    }
+    if referenced_segments(old)
+        .chain(referenced_segments(new))
+        .any(|id| changed_segments.contains(id))
+    {
+        changes.push(ChangeKind::Segments);
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn state(features: serde_json::Value, segments: serde_json::Value) -> ClientFeatures {
+        serde_json::from_value(json!({
+            "version": 2,
+            "features": features,
+            "segments": segments
+        }))
+        .unwrap()
+    }
+
+    fn base_feature() -> serde_json::Value {
+        json!({
+            "name": "toggle",
+            "enabled": true,
+            "impressionData": false,
+            "strategies": [{
+                "name": "default",
+                "segments": [1],
+                "variants": [{ "name": "a", "weight": 1000, "stickiness": "default" }]
+            }],
+            "variants": [{ "name": "b", "weight": 1000, "stickiness": "default" }],
+            "dependencies": [{ "feature": "parent", "enabled": true }]
+        })
+    }
+
+    fn base_segments() -> serde_json::Value {
+        json!([{
+            "id": 1,
+            "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["1"] }]
+        }])
+    }
+
+    #[test_case("/enabled", json!(false), ChangeKind::Enabled; "enabled")]
+    #[test_case("/strategies/0/name", json!("userWithId"), ChangeKind::Strategies; "strategies")]
+    #[test_case("/strategies/0/variants/0/name", json!("c"), ChangeKind::Strategies; "strategy variants")]
+    #[test_case("/variants/0/weight", json!(500), ChangeKind::Variants; "variants")]
+    #[test_case("/dependencies/0/enabled", json!(false), ChangeKind::Dependencies; "dependencies")]
+    #[test_case("/impressionData", json!(true), ChangeKind::ImpressionData; "impression data")]
+    fn reports_the_kind_of_modification(path: &str, value: serde_json::Value, kind: ChangeKind) {
+        let mut modified = base_feature();
+        *modified.pointer_mut(path).unwrap() = value;
+
+        let changes = ChangeSet::between(
+            &state(json!([base_feature()]), base_segments()),
+            &state(json!([modified]), base_segments()),
+        );
+
+        assert_eq!(
+            changes,
+            ChangeSet {
+                modified: vec![ModifiedToggle {
+                    name: "toggle".into(),
+                    changes: vec![kind],
+                }],
+                ..ChangeSet::default()
+            }
+        );
+    }
+
+    #[test]
+    fn reports_segment_changes_on_toggles_referencing_the_segment() {
+        let segments = json!([{
+            "id": 1,
+            "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["2"] }]
+        }]);
+        let unrelated = json!({ "name": "unrelated", "enabled": true, "strategies": [] });
+
+        let changes = ChangeSet::between(
+            &state(json!([base_feature(), unrelated]), base_segments()),
+            &state(json!([base_feature(), unrelated]), segments),
+        );
+
+        assert_eq!(
+            changes.modified,
+            vec![ModifiedToggle {
+                name: "toggle".into(),
+                changes: vec![ChangeKind::Segments],
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_added_and_removed_toggles_in_name_order() {
+        let changes = ChangeSet::between(
+            &state(
+                json!([
+                    { "name": "b-removed", "enabled": true },
+                    { "name": "a-removed", "enabled": true },
+                    { "name": "kept", "enabled": true }
+                ]),
+                json!([]),
+            ),
+            &state(
+                json!([
+                    { "name": "kept", "enabled": true },
+                    { "name": "z-added", "enabled": true },
+                    { "name": "c-added", "enabled": true }
+                ]),
+                json!([]),
+            ),
+        );
+
+        assert_eq!(changes.added, vec!["c-added", "z-added"]);
+        assert_eq!(changes.removed, vec!["a-removed", "b-removed"]);
+        assert!(changes.modified.is_empty());
+    }
+
+    #[test]
+    fn identical_states_produce_an_empty_change_set() {
+        let old = state(json!([base_feature()]), base_segments());
+        let new = state(json!([base_feature()]), base_segments());
+
+        assert!(ChangeSet::between(&old, &new).is_empty());
+    }
+}
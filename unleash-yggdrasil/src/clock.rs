@@ -0,0 +1,98 @@
+use std::sync::{Mutex, PoisonError};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Where date constraints get the current time from when the context doesn't carry
+/// one. Engines read the system clock unless they're given another
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The time as the system reports it
+#[cfg(feature = "wall-clock")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[cfg(feature = "wall-clock")]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when it's told to, so tests can put time boxed
+/// constraints on either side of their boundaries
+#[derive(Debug)]
+pub struct FixedClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl FixedClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, EngineState, UpdateMessage};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn date_constraints_without_a_current_time_read_the_engine_clock() {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_value(json!({
+                "version": 2,
+                "features": [{
+                    "name": "launch",
+                    "enabled": true,
+                    "strategies": [{
+                        "name": "default",
+                        "constraints": [{
+                            "contextName": "currentTime",
+                            "operator": "DATE_AFTER",
+                            "value": "2024-06-01T12:00:00.000Z"
+                        }]
+                    }]
+                }]
+            }))
+            .unwrap(),
+        ));
+        let clock = Arc::new(FixedClock::new(time("2024-06-01T11:59:59Z")));
+        engine.set_clock(clock.clone());
+
+        assert!(!engine.is_enabled("launch", &Context::default(), &None));
+
+        clock.advance(Duration::seconds(2));
+        assert!(engine.is_enabled("launch", &Context::default(), &None));
+
+        let context = Context {
+            current_time: Some("2024-01-01T00:00:00Z".into()),
+            ..Context::default()
+        };
+        assert!(!engine.is_enabled("launch", &context, &None));
+    }
+}
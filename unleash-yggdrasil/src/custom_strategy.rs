@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+use serde::Serialize;
+use unleash_types::client_features::{Constraint, Segment, Strategy};
+
+use crate::state::{EnrichedContext, SdkError};
+use crate::strategy_upgrade::{
+    custom_strategy_key, number_custom_strategies, resolve_constraints, IsCustom,
+};
+
+/// A strategy implemented in Rust by the host application. Registered strategies are
+/// bound by name when toggles are compiled and are evaluated as part of the compiled
+/// rule, so their results don't have to be computed up front and passed in as
+/// external values
+pub trait CustomStrategy: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn is_enabled(&self, parameters: &HashMap<String, String>, context: &EnrichedContext) -> bool;
+}
+
+// Strategies are bound by name, so that's all there is to compare or print
+impl PartialEq for dyn CustomStrategy {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Debug for dyn CustomStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CustomStrategy").field(&self.name()).finish()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CustomStrategies {
+    strategies: HashMap<String, Arc<dyn CustomStrategy>>,
+}
+
+impl CustomStrategies {
+    /// Registers a strategy under its name, replacing any strategy already registered
+    /// under that name
+    pub fn register(&mut self, strategy: Arc<dyn CustomStrategy>) {
+        self.strategies
+            .insert(strategy.name().to_string(), strategy);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn CustomStrategy>> {
+        self.strategies.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strategies.is_empty()
+    }
+}
+
+/// A custom strategy that has to be evaluated by the caller and passed in as an
+/// external value under `key`. The constraints include those of the strategy's segments
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalStrategy {
+    pub key: String,
+    pub name: String,
+    pub parameters: HashMap<String, String>,
+    pub constraints: Vec<Constraint>,
+}
+
+// A toggle with an unresolvable segment can't compile its own rules, so what it's
+// evaluated with is decided by its failure policy instead
+pub(crate) fn external_strategies(
+    strategies: &[Strategy],
+    segment_map: &HashMap<i32, Segment>,
+    custom_strategies: &CustomStrategies,
+) -> Vec<ExternalStrategy> {
+    number_custom_strategies(strategies)
+        .filter(|(strategy, _)| {
+            strategy.is_custom() && custom_strategies.get(&strategy.name).is_none()
+        })
+        .map(|(strategy, custom_count)| {
+            Ok(ExternalStrategy {
+                key: custom_strategy_key(custom_count),
+                name: strategy.name.clone(),
+                parameters: strategy.parameters.clone().unwrap_or_default(),
+                constraints: resolve_constraints(strategy, segment_map)?,
+            })
+        })
+        .collect::<Result<_, SdkError>>()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure_policy::{FailurePolicies, FailurePolicy};
+    use crate::multi_tenant::MultiTenantEngine;
+    use crate::strategy_parsing::parse_rule;
+    use crate::{Context, EngineState, UpdateMessage};
+    use serde_json::json;
+    use unleash_types::client_features::ClientFeatures;
+
+    // Enabled for users listed in the "users" parameter
+    struct UserList;
+
+    impl CustomStrategy for UserList {
+        fn name(&self) -> &str {
+            "userList"
+        }
+
+        fn is_enabled(
+            &self,
+            parameters: &HashMap<String, String>,
+            context: &EnrichedContext,
+        ) -> bool {
+            let users = parameters
+                .get("users")
+                .map(String::as_str)
+                .unwrap_or_default();
+            context
+                .user_id
+                .is_some_and(|user_id| users.split(',').any(|user| user == user_id))
+        }
+    }
+
+    fn state() -> ClientFeatures {
+        serde_json::from_value(json!({
+            "version": 2,
+            "features": [{
+                "name": "toggle",
+                "enabled": true,
+                "strategies": [
+                    { "name": "userList", "parameters": { "users": "1,2" } },
+                    { "name": "unregistered", "parameters": {} },
+                    {
+                        "name": "userList",
+                        "parameters": { "users": "3" },
+                        "variants": [{ "name": "three", "weight": 1000, "stickiness": "default" }]
+                    }
+                ]
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn user(user_id: &str) -> Context {
+        Context {
+            user_id: Some(user_id.into()),
+            ..Context::default()
+        }
+    }
+
+    fn engine() -> EngineState {
+        let mut engine = EngineState::default();
+        engine.register_custom_strategy(UserList);
+        engine.take_state(UpdateMessage::FullResponse(state()));
+        engine
+    }
+
+    #[test]
+    fn registered_strategies_are_evaluated_with_their_parameters() {
+        let engine = engine();
+
+        assert!(engine.is_enabled("toggle", &user("2"), &None));
+        assert!(engine.is_enabled("toggle", &user("3"), &None));
+        assert!(!engine.is_enabled("toggle", &user("4"), &None));
+    }
+
+    #[test]
+    fn registered_strategies_select_strategy_variants() {
+        let engine = engine();
+
+        assert_eq!(
+            engine.get_variant("toggle", &user("3"), &None).name,
+            "three"
+        );
+        assert_eq!(
+            engine.get_variant("toggle", &user("1"), &None).name,
+            "disabled"
+        );
+    }
+
+    #[test]
+    fn unregistered_strategies_keep_their_external_value_numbering() {
+        let engine = engine();
+        let external_values = Some(HashMap::from([("customStrategy2".to_string(), true)]));
+
+        assert!(engine.is_enabled("toggle", &user("4"), &external_values));
+    }
+
+    #[test]
+    fn registering_recompiles_the_current_state() {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(state()));
+        assert!(!engine.is_enabled("toggle", &user("1"), &None));
+
+        engine.register_custom_strategy(UserList);
+
+        assert!(engine.is_enabled("toggle", &user("1"), &None));
+    }
+
+    #[test]
+    fn lists_the_strategies_left_for_the_caller_to_evaluate() {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_value(json!({
+                "version": 2,
+                "features": [{
+                    "name": "toggle",
+                    "enabled": true,
+                    "strategies": [
+                        { "name": "default" },
+                        { "name": "userList", "parameters": { "users": "1" } },
+                        {
+                            "name": "custom",
+                            "parameters": { "key": "value" },
+                            "constraints": [{ "contextName": "environment", "operator": "IN", "values": ["prod"] }],
+                            "segments": [1]
+                        }
+                    ]
+                }],
+                "segments": [{
+                    "id": 1,
+                    "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["7"] }]
+                }]
+            }))
+            .unwrap(),
+        ));
+        engine.register_custom_strategy(UserList);
+
+        let strategies = engine.list_external_strategies("toggle").unwrap();
+
+        assert_eq!(strategies.len(), 1);
+        assert_eq!(strategies[0].key, "customStrategy2");
+        assert_eq!(strategies[0].name, "custom");
+        assert_eq!(strategies[0].parameters["key"], "value");
+        let context_names: Vec<_> = strategies[0]
+            .constraints
+            .iter()
+            .map(|constraint| constraint.context_name.as_str())
+            .collect();
+        assert_eq!(context_names, vec!["environment", "userId"]);
+    }
+
+    #[test]
+    fn listed_keys_match_the_external_values_the_rule_reads() {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(state()));
+
+        let strategies = engine.list_external_strategies("toggle").unwrap();
+        let keys: Vec<_> = strategies
+            .iter()
+            .map(|strategy| strategy.key.as_str())
+            .collect();
+        assert_eq!(
+            keys,
+            vec!["customStrategy1", "customStrategy2", "customStrategy3"]
+        );
+
+        for key in keys {
+            let external_values = Some(HashMap::from([(key.to_string(), true)]));
+            assert!(engine.is_enabled("toggle", &user("4"), &external_values));
+        }
+        assert!(engine.list_external_strategies("missing").is_none());
+    }
+
+    #[test]
+    fn registered_strategies_are_explained_as_rules_that_parse() {
+        let mut engine = EngineState::default();
+        engine.register_custom_strategy(UserList);
+        engine.take_state(UpdateMessage::FullResponse(state()));
+
+        let explanation = engine.explain("toggle", &user("1"), &None).unwrap();
+        let rules: Vec<_> = explanation
+            .strategies
+            .iter()
+            .map(|strategy| strategy.rule.clone().unwrap())
+            .collect();
+
+        assert_eq!(rules[0], "external_value[\"customStrategy1\"]");
+        for rule in rules {
+            assert_eq!(parse_rule(&rule).unwrap().to_string(), rule);
+        }
+    }
+
+    #[test]
+    fn kept_toggles_list_the_strategies_of_their_last_rules() {
+        let state = |strategies: serde_json::Value| {
+            UpdateMessage::FullResponse(
+                serde_json::from_value(json!({
+                    "version": 2,
+                    "features": [
+                        { "name": "kept", "enabled": true, "strategies": strategies },
+                        { "name": "always-on", "enabled": true, "strategies": strategies }
+                    ]
+                }))
+                .unwrap(),
+            )
+        };
+        let mut policies = FailurePolicies::default();
+        policies.set_for_toggle("kept", FailurePolicy::KeepLast);
+        policies.set_for_toggle("always-on", FailurePolicy::AlwaysOn);
+        let mut engine = EngineState::default();
+        engine.set_failure_policies(policies);
+        engine.take_state(state(json!([{ "name": "old", "parameters": {} }])));
+
+        engine.take_state(state(json!([
+            { "name": "new", "parameters": {} },
+            { "name": "old", "parameters": {}, "segments": [99] }
+        ])));
+
+        let strategies = engine.list_external_strategies("kept").unwrap();
+        assert_eq!(strategies.len(), 1);
+        assert_eq!(strategies[0].name, "old");
+        assert_eq!(strategies[0].key, "customStrategy1");
+        let external_values = Some(HashMap::from([("customStrategy1".to_string(), true)]));
+        assert!(engine.is_enabled("kept", &user("1"), &external_values));
+
+        // Falling back to always on leaves nothing to evaluate
+        assert!(engine
+            .list_external_strategies("always-on")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn external_values_are_numbered_in_sort_order() {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_value(json!({
+                "version": 2,
+                "features": [{
+                    "name": "toggle",
+                    "enabled": true,
+                    "strategies": [
+                        { "name": "last", "sortOrder": 2 },
+                        {
+                            "name": "first",
+                            "sortOrder": 1,
+                            "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["1"] }]
+                        }
+                    ]
+                }]
+            }))
+            .unwrap(),
+        ));
+
+        let strategies = engine.list_external_strategies("toggle").unwrap();
+        assert_eq!(strategies[0].name, "first");
+        assert_eq!(strategies[0].key, "customStrategy1");
+
+        let external_values = Some(HashMap::from([("customStrategy1".to_string(), true)]));
+        assert!(engine.is_enabled("toggle", &user("1"), &external_values));
+        assert!(!engine.is_enabled("toggle", &user("2"), &external_values));
+    }
+
+    #[test]
+    fn registered_strategies_apply_to_every_tenant() {
+        let mut engine = MultiTenantEngine::default();
+        engine.take_state("production", UpdateMessage::FullResponse(state()));
+        engine.register_custom_strategy(UserList);
+        engine.take_state("development", UpdateMessage::FullResponse(state()));
+
+        for tenant in ["production", "development"] {
+            let tenant = engine.tenant(tenant).unwrap();
+            assert!(tenant.is_enabled("toggle", &user("1"), &None));
+        }
+    }
+}
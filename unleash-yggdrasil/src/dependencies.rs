@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+
+use unleash_types::client_features::ClientFeature;
+
+use crate::EvalWarning;
+
+/// Checks the dependency graph of a whole state. Returns the names of toggles that sit on
+/// a dependency cycle, which can never be resolved and are always off, along with
+/// warnings for every cycle and every dependency on a toggle that doesn't exist
+pub(crate) fn check_dependencies(
+    features: &[ClientFeature],
+) -> (HashSet<String>, Vec<EvalWarning>) {
+    let known: HashSet<&str> = features.iter().map(|toggle| toggle.name.as_str()).collect();
+    let mut warnings = vec![];
+    let mut graph = HashMap::new();
+
+    for toggle in features {
+        let mut parents = vec![];
+        for dependency in toggle.dependencies.iter().flatten() {
+            if known.contains(dependency.feature.as_str()) {
+                parents.push(dependency.feature.as_str());
+            } else {
+                warnings.push(EvalWarning {
+                    toggle_name: toggle.name.clone(),
+                    message: format!(
+                        "Parent feature {} does not exist, this dependency will never be satisfied",
+                        dependency.feature
+                    ),
+                });
+            }
+        }
+        graph.insert(toggle.name.as_str(), parents);
+    }
+
+    let mut components = StronglyConnected::new(&graph);
+    for toggle in features {
+        components.visit(&toggle.name);
+    }
+
+    let mut cyclic = HashSet::new();
+    for mut component in components.components {
+        let is_cycle = match component.as_slice() {
+            [toggle] => graph[toggle].contains(toggle),
+            _ => true,
+        };
+        if !is_cycle {
+            continue;
+        }
+
+        component.sort_unstable();
+        for toggle in &component {
+            let others: Vec<&str> = component
+                .iter()
+                .filter(|other| *other != toggle)
+                .copied()
+                .collect();
+            let message = if others.is_empty() {
+                "Toggle depends on itself, it will always be off".to_string()
+            } else {
+                format!(
+                    "Toggle is part of a dependency cycle with {}, it will always be off",
+                    others.join(", ")
+                )
+            };
+            warnings.push(EvalWarning {
+                toggle_name: toggle.to_string(),
+                message,
+            });
+            cyclic.insert(toggle.to_string());
+        }
+    }
+
+    (cyclic, warnings)
+}
+
+// Tarjan's algorithm, every cycle in the graph ends up inside a single component
+struct StronglyConnected<'a> {
+    graph: &'a HashMap<&'a str, Vec<&'a str>>,
+    index: HashMap<&'a str, usize>,
+    low_link: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: HashSet<&'a str>,
+    components: Vec<Vec<&'a str>>,
+}
+
+impl<'a> StronglyConnected<'a> {
+    fn new(graph: &'a HashMap<&'a str, Vec<&'a str>>) -> Self {
+        Self {
+            graph,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: vec![],
+            on_stack: HashSet::new(),
+            components: vec![],
+        }
+    }
+
+    fn visit(&mut self, toggle: &'a str) {
+        if self.index.contains_key(toggle) {
+            return;
+        }
+
+        let index = self.index.len();
+        self.index.insert(toggle, index);
+        self.low_link.insert(toggle, index);
+        self.stack.push(toggle);
+        self.on_stack.insert(toggle);
+
+        for parent in self.graph.get(toggle).into_iter().flatten() {
+            if !self.index.contains_key(parent) {
+                self.visit(parent);
+                let low_link = self.low_link[toggle].min(self.low_link[parent]);
+                self.low_link.insert(toggle, low_link);
+            } else if self.on_stack.contains(parent) {
+                let low_link = self.low_link[toggle].min(self.index[parent]);
+                self.low_link.insert(toggle, low_link);
+            }
+        }
+
+        if self.low_link[toggle] == index {
+            let mut component = vec![];
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(member);
+                component.push(member);
+                if member == toggle {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn features(dependencies: &[(&str, &[&str])]) -> Vec<ClientFeature> {
+        dependencies
+            .iter()
+            .map(|(name, parents)| {
+                let parents: Vec<_> = parents
+                    .iter()
+                    .map(|parent| json!({ "feature": parent }))
+                    .collect();
+                serde_json::from_value(json!({
+                    "name": name,
+                    "enabled": true,
+                    "dependencies": parents
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test_case(&[("a", &["b"]), ("b", &["c"]), ("c", &[])], &[]; "chain")]
+    #[test_case(&[("a", &["b", "c"]), ("b", &["d"]), ("c", &["d"]), ("d", &[])], &[]; "diamond")]
+    #[test_case(&[("a", &["a"])], &["a"]; "self dependency")]
+    #[test_case(&[("a", &["b"]), ("b", &["a"])], &["a", "b"]; "two toggle cycle")]
+    #[test_case(&[("a", &["b"]), ("b", &["c"]), ("c", &["b"]), ("d", &["a"])], &["b", "c"]; "only cycle members")]
+    fn finds_toggles_on_dependency_cycles(graph: &[(&str, &[&str])], expected: &[&str]) {
+        let (cyclic, warnings) = check_dependencies(&features(graph));
+
+        let mut cyclic: Vec<_> = cyclic.iter().map(String::as_str).collect();
+        cyclic.sort();
+        assert_eq!(cyclic, expected);
+        assert_eq!(warnings.len(), expected.len());
+    }
+
+    #[test]
+    fn warns_about_missing_parents() {
+        let (cyclic, warnings) = check_dependencies(&features(&[("a", &["missing"])]));
+
+        assert!(cyclic.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].toggle_name, "a");
+        assert!(warnings[0].message.contains("missing"));
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use unleash_types::client_features::ClientFeature;
+
+/// What a toggle does when its strategies can't be compiled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    #[default]
+    AlwaysOff,
+    AlwaysOn,
+    /// Keeps the rules from the last version of the toggle that compiled. The rest of
+    /// the toggle, including whether it's enabled, still comes from the new version.
+    /// Toggles that have never compiled are always off
+    KeepLast,
+}
+
+impl FailurePolicy {
+    pub(crate) fn describe(&self) -> &'static str {
+        match self {
+            FailurePolicy::AlwaysOff => "this will always be off",
+            FailurePolicy::AlwaysOn => "this will always be on",
+            FailurePolicy::KeepLast => "keeping the rules from the last version that compiled",
+        }
+    }
+}
+
+/// Failure policies by feature type, with overrides for individual toggles. Anything
+/// not covered is always off
+#[derive(Clone, Debug, Default)]
+pub struct FailurePolicies {
+    by_feature_type: HashMap<String, FailurePolicy>,
+    by_toggle: HashMap<String, FailurePolicy>,
+}
+
+impl FailurePolicies {
+    pub fn set_for_feature_type(&mut self, feature_type: impl Into<String>, policy: FailurePolicy) {
+        self.by_feature_type.insert(feature_type.into(), policy);
+    }
+
+    pub fn set_for_toggle(&mut self, toggle_name: impl Into<String>, policy: FailurePolicy) {
+        self.by_toggle.insert(toggle_name.into(), policy);
+    }
+
+    pub fn policy_for(&self, toggle: &ClientFeature) -> FailurePolicy {
+        self.by_toggle
+            .get(&toggle.name)
+            .or_else(|| {
+                toggle
+                    .feature_type
+                    .as_ref()
+                    .and_then(|feature_type| self.by_feature_type.get(feature_type))
+            })
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, EngineState, EvaluationReason, UpdateMessage};
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn toggle(name: &str, feature_type: &str, operator: &str) -> serde_json::Value {
+        json!({
+            "name": name,
+            "type": feature_type,
+            "enabled": true,
+            "strategies": [{
+                "name": "default",
+                "constraints": [{ "contextName": "userId", "operator": operator, "values": ["1"] }]
+            }]
+        })
+    }
+
+    fn state(operator: &str) -> UpdateMessage {
+        UpdateMessage::FullResponse(
+            serde_json::from_value(json!({
+                "version": 2,
+                "features": [
+                    toggle("release", "release", operator),
+                    toggle("kill-switch", "kill-switch", operator),
+                    toggle("overridden", "kill-switch", operator),
+                ]
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn policies() -> FailurePolicies {
+        let mut policies = FailurePolicies::default();
+        policies.set_for_feature_type("kill-switch", FailurePolicy::AlwaysOn);
+        policies.set_for_toggle("overridden", FailurePolicy::KeepLast);
+        policies
+    }
+
+    fn user(user_id: &str) -> Context {
+        Context {
+            user_id: Some(user_id.into()),
+            ..Context::default()
+        }
+    }
+
+    #[test]
+    fn toggle_overrides_win_over_feature_types() {
+        let policies = policies();
+        let policy_for = |name, feature_type| {
+            let toggle = serde_json::from_value(toggle(name, feature_type, "IN")).unwrap();
+            policies.policy_for(&toggle)
+        };
+
+        assert_eq!(policy_for("release", "release"), FailurePolicy::AlwaysOff);
+        assert_eq!(
+            policy_for("kill-switch", "kill-switch"),
+            FailurePolicy::AlwaysOn
+        );
+        assert_eq!(
+            policy_for("overridden", "kill-switch"),
+            FailurePolicy::KeepLast
+        );
+    }
+
+    #[test_case("release", false; "always off")]
+    #[test_case("kill-switch", true; "always on")]
+    #[test_case("overridden", true; "keep last")]
+    fn failed_toggles_follow_their_policy(name: &str, expected: bool) {
+        let mut engine = EngineState::default();
+        engine.set_failure_policies(policies());
+        engine.take_state(state("IN"));
+
+        let update = engine.take_state(state("NOT_AN_OPERATOR"));
+
+        assert_eq!(engine.is_enabled(name, &user("1"), &None), expected);
+        let warning = update
+            .warnings
+            .unwrap()
+            .into_iter()
+            .find(|warning| warning.toggle_name == name)
+            .unwrap();
+        assert!(warning.message.contains(match name {
+            "release" => "always be off",
+            "kill-switch" => "always be on",
+            _ => "last version that compiled",
+        }));
+    }
+
+    #[test]
+    fn kept_toggles_evaluate_their_last_rules() {
+        let mut engine = EngineState::default();
+        engine.set_failure_policies(policies());
+        engine.take_state(state("IN"));
+        engine.take_state(state("NOT_AN_OPERATOR"));
+        // Still kept through further failed updates
+        engine.take_state(state("NOT_AN_OPERATOR"));
+
+        let resolved = engine.resolve("overridden", &user("1"), &None).unwrap();
+        assert!(resolved.enabled);
+        assert_eq!(
+            resolved.reason,
+            EvaluationReason::TargetingMatch {
+                strategy_index: Some(0)
+            }
+        );
+        assert!(!engine.is_enabled("overridden", &user("2"), &None));
+    }
+
+    #[test]
+    fn kept_toggles_are_explained_by_their_last_rules() {
+        let mut engine = EngineState::default();
+        engine.set_failure_policies(policies());
+        engine.take_state(state("IN"));
+        engine.take_state(state("NOT_AN_OPERATOR"));
+
+        let explanation = engine.explain("overridden", &user("1"), &None).unwrap();
+        assert!(explanation.enabled);
+        assert_eq!(explanation.strategies.len(), 1);
+        let strategy = &explanation.strategies[0];
+        assert!(strategy.result);
+        assert!(strategy.error.is_none());
+        assert!(strategy.rule.as_ref().unwrap().contains("user_id in"));
+
+        let explanation = engine.explain("overridden", &user("2"), &None).unwrap();
+        assert!(!explanation.enabled);
+        assert!(!explanation.strategies[0].result);
+
+        // Nothing is kept for the other policies, so the failure itself is reported
+        let explanation = engine.explain("kill-switch", &user("2"), &None).unwrap();
+        assert!(explanation.enabled);
+        assert_eq!(explanation.reason, EvaluationReason::Error);
+        assert!(explanation.strategies[0].error.is_some());
+    }
+
+    #[test]
+    fn toggles_that_never_compiled_have_nothing_to_keep() {
+        let mut engine = EngineState::default();
+        engine.set_failure_policies(policies());
+
+        let update = engine.take_state(state("NOT_AN_OPERATOR"));
+
+        assert!(!engine.is_enabled("overridden", &user("1"), &None));
+        assert!(update.warnings.unwrap().iter().any(|warning| {
+            warning.toggle_name == "overridden" && warning.message.contains("always be off")
+        }));
+    }
+
+    #[test]
+    fn deltas_keep_the_last_version_but_can_still_disable_it() {
+        let mut engine = EngineState::default();
+        engine.set_failure_policies(policies());
+        engine.take_state(state("IN"));
+        let delta = |enabled| {
+            let mut feature = toggle("overridden", "kill-switch", "NOT_AN_OPERATOR");
+            feature["enabled"] = json!(enabled);
+            UpdateMessage::PartialUpdate(
+                serde_json::from_value(json!({
+                    "events": [{ "type": "feature-updated", "eventId": 2, "feature": feature }]
+                }))
+                .unwrap(),
+            )
+        };
+
+        engine.take_state(delta(true));
+        assert!(engine.is_enabled("overridden", &user("1"), &None));
+        assert!(!engine.is_enabled("overridden", &user("2"), &None));
+
+        engine.take_state(delta(false));
+        assert!(!engine.is_enabled("overridden", &user("1"), &None));
+    }
+}
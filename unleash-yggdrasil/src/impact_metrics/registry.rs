@@ -551,13 +551,8 @@ mod tests {
         assert_eq!(restored_collect.len(), 1);
         assert_eq!(restored_collect[0].name, "restore_histogram");
 
-        let mut restored_samples: Vec<_> = restored_collect[0]
-            .bucket_samples()
-            .iter()
-            .cloned()
-            .collect();
-        let mut original_samples: Vec<_> =
-            first_collect[0].bucket_samples().iter().cloned().collect();
+        let mut restored_samples: Vec<_> = restored_collect[0].bucket_samples().to_vec();
+        let mut original_samples: Vec<_> = first_collect[0].bucket_samples().to_vec();
         restored_samples.sort_by(|a, b| a.sum.total_cmp(&b.sum));
         original_samples.sort_by(|a, b| a.sum.total_cmp(&b.sum));
 
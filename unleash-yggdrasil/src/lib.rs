@@ -1,49 +1,79 @@
 #![cfg_attr(not(test), deny(clippy::expect_used, clippy::unwrap_used))]
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
 
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate pest_derive;
 
+pub mod change_set;
+pub mod clock;
+pub mod custom_strategy;
+mod dependencies;
+pub mod failure_policy;
 pub mod impact_metrics;
+pub mod multi_tenant;
+pub mod payload;
+pub mod random_source;
 mod sendable_closures;
+pub mod shared_engine;
 pub mod state;
+pub mod strategy_ast;
+pub mod strategy_lowering;
 pub mod strategy_parsing;
 pub mod strategy_upgrade;
+pub mod validation;
 
 use ahash::AHashMap;
+use change_set::{referenced_segments, segment_changed, ChangeSet};
 use chrono::{DateTime, Utc};
+use clock::Clock;
+use custom_strategy::{external_strategies, CustomStrategies, CustomStrategy, ExternalStrategy};
 use dashmap::DashMap;
+use dependencies::check_dependencies;
+use failure_policy::{FailurePolicies, FailurePolicy};
 use impact_metrics::{
     BucketMetricOptions, CollectedMetric, ImpactMetricRegistry, ImpactMetricsDataSource,
     MetricLabels, MetricOptions,
 };
-use rand::Rng;
+use payload::validate_payload;
+use random_source::{RandomSource, ThreadRandom};
 use serde::{de, Deserialize, Serialize};
-use state::EnrichedContext;
+pub use state::{ContextSource, StructuredContext};
+use state::{EnrichedContext, ParsedValues};
 use std::sync::atomic::Ordering;
-use strategy_parsing::{compile_rule, normalized_hash, RuleFragment};
-use strategy_upgrade::{build_variant_rules, upgrade};
+use strategy_ast::{ContextField, RuleAst};
+use strategy_lowering::{lower_stickiness, lower_strategies, lower_variant_rules};
+use strategy_parsing::{
+    compile_rule_ast_with, first_field_value, normalized_hash, ConstraintCache, RuleFragment,
+    RuleNode, RuleTrace,
+};
+use strategy_upgrade::sorted_strategies;
 pub use unleash_types::client_features::Context;
 use unleash_types::client_features::{
-    ClientFeature, ClientFeatures, ClientFeaturesDelta, FeatureDependency, Override, Payload,
-    Segment, Variant,
+    ClientFeature, ClientFeatures, ClientFeaturesDelta, DeltaEvent, FeatureDependency, Override,
+    Payload, Segment, Variant,
 };
 use unleash_types::client_metrics::{MetricBucket, ToggleStats};
 
 use crate::state::SdkError;
 
-pub type CompiledState = AHashMap<String, CompiledToggle>;
+// Toggles are shared between successive states, so applying a delta only copies the
+// toggles it recompiles
+pub type CompiledState = AHashMap<String, Arc<CompiledToggle>>;
 
 pub const SUPPORTED_SPEC_VERSION: &str = "6.1.0";
 const VARIANT_NORMALIZATION_SEED: u32 = 86028157;
 pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
 type VariantRuleSet = Vec<(RuleFragment, Vec<CompiledVariant>, String)>;
 struct MatchedStrategyVariants<'a> {
+    index: usize,
     variants: &'a Vec<CompiledVariant>,
     group_id: &'a String,
 }
@@ -65,16 +95,29 @@ pub const KNOWN_STRATEGIES: [&str; 8] = [
     "flexibleRollout",
 ];
 
+#[derive(Clone)]
 pub struct CompiledToggle {
     pub name: String,
     pub enabled: bool,
     pub feature_type: Option<String>,
     pub compiled_strategy: RuleFragment,
+    pub compiled_strategies: Vec<CompiledStrategy>,
+    pub external_strategies: Vec<ExternalStrategy>,
     pub compiled_variant_strategy: Option<Vec<(RuleFragment, Vec<CompiledVariant>, String)>>,
     pub variants: Vec<CompiledVariant>,
     pub impression_data: bool,
     pub project: String,
     pub dependencies: Vec<FeatureDependency>,
+    pub in_dependency_cycle: bool,
+    pub compile_failed: bool,
+}
+
+// Each strategy's rule is kept whole, alongside the combined rule built from them, so
+// explaining a toggle reports on the rules that actually ran
+#[derive(Clone)]
+pub struct CompiledStrategy {
+    pub name: String,
+    pub rule: Result<(RuleAst, RuleNode), SdkError>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -103,11 +146,15 @@ impl Default for CompiledToggle {
             enabled: Default::default(),
             feature_type: None,
             compiled_strategy: Box::new(|_| true),
+            compiled_strategies: vec![],
+            external_strategies: vec![],
             compiled_variant_strategy: None,
             variants: Default::default(),
             impression_data: false,
             project: "default".to_string(),
             dependencies: Default::default(),
+            in_dependency_cycle: false,
+            compile_failed: false,
         }
     }
 }
@@ -139,35 +186,35 @@ fn build_segment_map(segments: &Option<Vec<Segment>>) -> HashMap<i32, Segment> {
 fn compile_variant_rule(
     toggle: &ClientFeature,
     segment_map: &HashMap<i32, Segment>,
+    custom_strategies: &CustomStrategies,
+    mut cache: Option<&mut ConstraintCache>,
 ) -> Result<Option<VariantRuleSet>, SdkError> {
-    let variant_rules: Option<VariantRuleSet> = build_variant_rules(
+    lower_variant_rules(
         &toggle.strategies.clone().unwrap_or_default(),
         segment_map,
+        custom_strategies,
         &toggle.name,
     )?
-    .iter()
-    .map(|(rule_string, strategy_variants, stickiness, group_id)| {
-        let compiled_rule: Option<RuleFragment> = compile_rule(rule_string).ok();
-        compiled_rule.map(|rule| {
-            (
-                rule,
-                strategy_variants
-                    .iter()
-                    .map(|strategy_variant| CompiledVariant {
-                        name: strategy_variant.name.clone(),
-                        weight: strategy_variant.weight,
-                        stickiness: Some(stickiness.clone()),
-                        payload: strategy_variant.payload.clone(),
-                        overrides: None,
-                    })
-                    .collect(),
-                group_id.clone(),
-            )
-        })
+    .into_iter()
+    .map(|(rule, strategy_variants, stickiness, group_id)| {
+        let compiled_rule = compile_rule_ast_with(&rule, cache.as_deref_mut())?;
+        Ok((
+            compiled_rule.into_fragment(),
+            strategy_variants
+                .iter()
+                .map(|strategy_variant| CompiledVariant {
+                    name: strategy_variant.name.clone(),
+                    weight: strategy_variant.weight,
+                    stickiness: Some(stickiness.clone()),
+                    payload: strategy_variant.payload.clone(),
+                    overrides: None,
+                })
+                .collect(),
+            group_id,
+        ))
     })
-    .collect();
-
-    Ok(variant_rules)
+    .collect::<Result<_, SdkError>>()
+    .map(Some)
 }
 
 #[derive(Debug, Serialize)]
@@ -176,9 +223,31 @@ pub struct EvalWarning {
     pub message: String,
 }
 
-pub fn compile_state(
+#[derive(Debug, Default, Serialize)]
+pub struct StateUpdate {
+    pub warnings: Option<Vec<EvalWarning>>,
+    pub changes: ChangeSet,
+}
+
+pub fn compile_state(state: &ClientFeatures) -> (CompiledState, Vec<EvalWarning>) {
+    compile_state_with(
+        state,
+        &CustomStrategies::default(),
+        &FailurePolicies::default(),
+        None,
+        None,
+    )
+}
+
+// Previous is the state being replaced, toggles that fail to compile can fall back to
+// their version in it
+fn compile_state_with(
     state: &ClientFeatures,
-) -> (AHashMap<String, CompiledToggle>, Vec<EvalWarning>) {
+    custom_strategies: &CustomStrategies,
+    failure_policies: &FailurePolicies,
+    previous: Option<&CompiledState>,
+    mut cache: Option<&mut ConstraintCache>,
+) -> (CompiledState, Vec<EvalWarning>) {
     let mut compiled_state = AHashMap::new();
     let segment_map = build_segment_map(&state.segments);
     let mut warnings = vec![];
@@ -186,42 +255,164 @@ pub fn compile_state(
     for toggle in &state.features {
         compiled_state.insert(
             toggle.name.clone(),
-            compile(toggle, &segment_map, &mut warnings),
+            Arc::new(compile_with(
+                toggle,
+                &segment_map,
+                custom_strategies,
+                failure_policies.policy_for(toggle),
+                previous
+                    .and_then(|previous| previous.get(&toggle.name))
+                    .map(Arc::as_ref),
+                &mut warnings,
+                cache.as_deref_mut(),
+            )),
         );
     }
+    warnings.extend(mark_dependency_cycles(state, &mut compiled_state));
 
     (compiled_state, warnings)
 }
 
+// Dependency problems span toggles, so they're checked against the whole state
+// rather than as each toggle compiles
+fn mark_dependency_cycles(
+    state: &ClientFeatures,
+    compiled_state: &mut CompiledState,
+) -> Vec<EvalWarning> {
+    let (cyclic, warnings) = check_dependencies(&state.features);
+    for (name, toggle) in compiled_state.iter_mut() {
+        let in_dependency_cycle = cyclic.contains(name);
+        // Only toggles whose status changed are copied
+        if toggle.in_dependency_cycle != in_dependency_cycle {
+            Arc::make_mut(toggle).in_dependency_cycle = in_dependency_cycle;
+        }
+    }
+    warnings
+}
+
 pub fn compile(
     toggle: &ClientFeature,
     segment_map: &HashMap<i32, Segment>,
     warnings: &mut Vec<EvalWarning>,
 ) -> CompiledToggle {
-    let enabled_rule = (|| {
-        let strategies = toggle.strategies.clone().unwrap_or_default();
-        let rule_text = upgrade(&strategies, segment_map)?;
-        compile_rule(rule_text.as_str())
-    })()
-    .unwrap_or_else(|e| {
-        warnings.push(EvalWarning {
-            toggle_name: toggle.name.clone(),
-            message: format!("Failed to compile toggle, this will always be off {e:?}"),
-        });
+    compile_with(
+        toggle,
+        segment_map,
+        &CustomStrategies::default(),
+        FailurePolicy::default(),
+        None,
+        warnings,
+        None,
+    )
+}
 
-        Box::new(|_| false)
-    });
+fn compile_with(
+    toggle: &ClientFeature,
+    segment_map: &HashMap<i32, Segment>,
+    custom_strategies: &CustomStrategies,
+    failure_policy: FailurePolicy,
+    previous: Option<&CompiledToggle>,
+    warnings: &mut Vec<EvalWarning>,
+    mut cache: Option<&mut ConstraintCache>,
+) -> CompiledToggle {
+    let strategies = toggle.strategies.clone().unwrap_or_default();
+    let compiled_strategies: Vec<CompiledStrategy> = sorted_strategies(&strategies)
+        .into_iter()
+        .zip(lower_strategies(
+            &strategies,
+            segment_map,
+            custom_strategies,
+        ))
+        .map(|(strategy, rule)| CompiledStrategy {
+            name: strategy.name.clone(),
+            rule: rule.and_then(|rule| {
+                let tree = compile_rule_ast_with(&rule, cache.as_deref_mut())?;
+                Ok((rule, tree))
+            }),
+        })
+        .collect();
+    // A toggle is enabled when any of its strategies are, or always when it has none
+    let enabled_rule = compiled_strategies
+        .iter()
+        .map(|strategy| strategy.rule.clone().map(|(_, tree)| tree))
+        .reduce(|rule, strategy_rule| Ok(RuleNode::Or(Box::new(rule?), Box::new(strategy_rule?))))
+        .map_or_else(
+            || Ok(Box::new(|_: &EnrichedContext| true) as RuleFragment),
+            |rule| rule.map(RuleNode::into_fragment),
+        );
+    let variant_rule = compile_variant_rule(toggle, segment_map, custom_strategies, cache);
+    let external_strategies = external_strategies(&strategies, segment_map, custom_strategies);
 
-    let get_variant_rule = compile_variant_rule(toggle, segment_map).unwrap_or_else(|e| {
+    let mut warn = |e: SdkError, fallback: &str| {
         warnings.push(EvalWarning {
             toggle_name: toggle.name.clone(),
-            message: format!(
-                "Failed to compile toggle, this will always resolve to the default variant {e:?}"
+            message: format!("Failed to compile toggle, {fallback} {e:?}"),
+        })
+    };
+    // Only a version whose own rules compiled is worth keeping
+    let kept = previous
+        .filter(|previous| failure_policy == FailurePolicy::KeepLast && !previous.compile_failed);
+    let nothing_kept = if failure_policy == FailurePolicy::KeepLast {
+        "there is no previous version to keep, "
+    } else {
+        ""
+    };
+
+    let mut compile_failed = false;
+    let (enabled_rule, compiled_strategies, external_strategies, get_variant_rule) =
+        match (enabled_rule, variant_rule, kept) {
+            (Ok(enabled_rule), Ok(variant_rule), _) => (
+                enabled_rule,
+                compiled_strategies,
+                external_strategies,
+                variant_rule,
             ),
-        });
+            (enabled_rule, variant_rule, Some(previous)) => {
+                for e in enabled_rule.err().into_iter().chain(variant_rule.err()) {
+                    warn(e, FailurePolicy::KeepLast.describe());
+                }
+                (
+                    previous.compiled_strategy.clone(),
+                    previous.compiled_strategies.clone(),
+                    previous.external_strategies.clone(),
+                    previous.compiled_variant_strategy.clone(),
+                )
+            }
+            (enabled_rule, variant_rule, None) => {
+                // A toggle that falls back to always on or off reads no external values
+                let external_strategies = if enabled_rule.is_ok() {
+                    external_strategies
+                } else {
+                    vec![]
+                };
+                let enabled_rule = enabled_rule.unwrap_or_else(|e| {
+                    compile_failed = true;
+                    let always_on = failure_policy == FailurePolicy::AlwaysOn;
+                    let fallback = if always_on {
+                        FailurePolicy::AlwaysOn
+                    } else {
+                        FailurePolicy::AlwaysOff
+                    };
+                    warn(e, &format!("{nothing_kept}{}", fallback.describe()));
+                    Box::new(move |_| always_on)
+                });
+                let variant_rule = variant_rule.unwrap_or_else(|e| {
+                    warn(
+                        e,
+                        &format!("{nothing_kept}this will always resolve to the default variant"),
+                    );
+                    None
+                });
+                (
+                    enabled_rule,
+                    compiled_strategies,
+                    external_strategies,
+                    variant_rule,
+                )
+            }
+        };
 
-        None
-    });
+    warnings.extend(payload_warnings(toggle));
 
     CompiledToggle {
         name: toggle.name.clone(),
@@ -230,12 +421,43 @@ pub fn compile(
         compiled_variant_strategy: get_variant_rule,
         variants: compile_variants(&toggle.variants),
         compiled_strategy: enabled_rule,
+        compiled_strategies,
+        external_strategies,
         impression_data: toggle.impression_data.unwrap_or_default(),
         project: toggle.project.clone().unwrap_or("default".to_string()),
         dependencies: toggle.dependencies.clone().unwrap_or_default(),
+        in_dependency_cycle: false,
+        compile_failed,
     }
 }
 
+// Payloads are handed back exactly as they came in, but any that can't be read as
+// their declared type are reported here rather than when something tries to read them
+fn payload_warnings(toggle: &ClientFeature) -> Vec<EvalWarning> {
+    let toggle_variants = toggle
+        .variants
+        .iter()
+        .flatten()
+        .map(|variant| (&variant.name, &variant.payload));
+    let strategy_variants = toggle
+        .strategies
+        .iter()
+        .flatten()
+        .flat_map(|strategy| strategy.variants.iter().flatten())
+        .map(|variant| (&variant.name, &variant.payload));
+
+    toggle_variants
+        .chain(strategy_variants)
+        .filter_map(|(name, payload)| {
+            let error = validate_payload(payload.as_ref()?).err()?;
+            Some(EvalWarning {
+                toggle_name: toggle.name.clone(),
+                message: format!("Variant {name} has a payload that can't be read, {error}"),
+            })
+        })
+        .collect()
+}
+
 fn compile_variants(variants: &Option<Vec<Variant>>) -> Vec<CompiledVariant> {
     if let Some(variants) = variants {
         variants.iter().map(CompiledVariant::from).collect()
@@ -272,10 +494,16 @@ struct Metric {
 pub struct EngineState {
     compiled_state: Option<CompiledState>,
     previous_state: ClientFeatures,
-    toggle_metrics: DashMap<String, Metric>,
+    toggle_metrics: Arc<DashMap<String, Metric>>,
     toggle_metrics_start: DateTime<Utc>,
     pub started: DateTime<Utc>,
-    impact_metrics: impact_metrics::InMemoryMetricRegistry,
+    impact_metrics: Arc<impact_metrics::InMemoryMetricRegistry>,
+    custom_strategies: CustomStrategies,
+    failure_policies: FailurePolicies,
+    // Evaluations fall back to the system clock and thread local randomness when these
+    // aren't set
+    clock: Option<Arc<dyn Clock>>,
+    random_source: Option<Arc<dyn RandomSource>>,
 }
 
 impl EngineState {
@@ -287,6 +515,30 @@ impl EngineState {
             previous_state: Default::default(),
             started,
             impact_metrics: Default::default(),
+            custom_strategies: Default::default(),
+            failure_policies: Default::default(),
+            clock: None,
+            random_source: None,
+        }
+    }
+
+    // A new state around the given toggles that records into the same metrics as this one
+    fn successor(
+        &self,
+        compiled_state: Option<CompiledState>,
+        previous_state: ClientFeatures,
+    ) -> Self {
+        Self {
+            compiled_state,
+            previous_state,
+            toggle_metrics: self.toggle_metrics.clone(),
+            toggle_metrics_start: self.toggle_metrics_start,
+            started: self.started,
+            impact_metrics: self.impact_metrics.clone(),
+            custom_strategies: self.custom_strategies.clone(),
+            failure_policies: self.failure_policies.clone(),
+            clock: self.clock.clone(),
+            random_source: self.random_source.clone(),
         }
     }
 }
@@ -301,6 +553,10 @@ impl Default for EngineState {
             previous_state: Default::default(),
             started: Utc::now(),
             impact_metrics: Default::default(),
+            custom_strategies: Default::default(),
+            failure_policies: Default::default(),
+            clock: None,
+            random_source: None,
         }
     }
 }
@@ -312,25 +568,252 @@ pub enum UpdateMessage {
     PartialUpdate(ClientFeaturesDelta),
 }
 
+/// Why a toggle or variant resolved the way it did, modelled on OpenFeature's resolution
+/// reasons. Strategy indexes are positions in the order strategies are evaluated, which
+/// is their sort order, and are missing when the toggle has no strategies to match
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    rename_all_fields = "camelCase"
+)]
+pub enum EvaluationReason {
+    FlagNotFound,
+    Disabled,
+    DependencyUnsatisfied,
+    // The toggle couldn't be compiled and resolved to its fallback
+    Error,
+    // The toggle is on but none of its strategies matched
+    Default,
+    TargetingMatch {
+        strategy_index: Option<usize>,
+    },
+    StrategyVariant {
+        strategy_index: usize,
+    },
+    ToggleVariant {
+        strategy_index: Option<usize>,
+    },
+    VariantOverride {
+        strategy_index: Option<usize>,
+    },
+    // Results recorded without a reason, such as variants serialized before reasons
+    // were reported
+    #[default]
+    Unknown,
+}
+
+struct Evaluation<'a> {
+    enabled: bool,
+    reason: EvaluationReason,
+    strategy_variants: Option<MatchedStrategyVariants<'a>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ResolvedToggle {
     pub enabled: bool,
+    pub reason: EvaluationReason,
     pub impression_data: bool,
     pub project: String,
     pub variant: ExtendedVariantDef,
 }
 
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToggleExplanation {
+    pub name: String,
+    pub toggle_enabled: bool,
+    pub dependencies: Vec<DependencyExplanation>,
+    pub strategies: Vec<StrategyExplanation>,
+    pub enabled: bool,
+    pub reason: EvaluationReason,
+    pub variant: ExtendedVariantDef,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyExplanation {
+    pub feature: String,
+    pub expected_enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_variants: Option<Vec<String>>,
+    pub parent_found: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_variant: Option<String>,
+    pub satisfied: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StrategyExplanation {
+    pub index: usize,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    pub result: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<RuleTrace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Parent evaluations made during a single call, keyed by child and parent name, so a
+// parent reached from the same child along several paths is only evaluated once
+#[derive(Default)]
+struct DependencyMemo<'a> {
+    parents: RefCell<HashMap<(&'a str, &'a str), ParentEvaluation>>,
+}
+
+#[derive(Clone)]
+struct ParentEvaluation {
+    enabled: bool,
+    variant: Option<VariantDef>,
+}
+
+struct ParentDependencyCheck {
+    parent_found: bool,
+    parent_enabled: Option<bool>,
+    parent_variant: Option<VariantDef>,
+    satisfied: bool,
+}
+
 impl EngineState {
-    pub fn apply_delta(&mut self, delta: &ClientFeaturesDelta) -> Option<Vec<EvalWarning>> {
-        let mut new_state = self.previous_state.clone();
-        new_state.apply_delta(delta);
-        self.apply_client_features(new_state)
+    /// Applies a delta on top of the current state. Only the features touched by the delta,
+    /// and toggles referencing a segment touched by the delta, are recompiled, so the
+    /// returned warnings only cover those toggles, plus any dependency problems in the
+    /// state. Hydration events replace the state wholesale and go through a full recompile
+    pub fn apply_delta(&mut self, delta: &ClientFeaturesDelta) -> StateUpdate {
+        self.apply_delta_with(delta, None)
+    }
+
+    // Compiles through the given cache when there is one, so that identical constraints
+    // are shared with whatever else was compiled through it
+    pub(crate) fn apply_delta_with(
+        &mut self,
+        delta: &ClientFeaturesDelta,
+        mut cache: Option<&mut ConstraintCache>,
+    ) -> StateUpdate {
+        let is_hydration = delta
+            .events
+            .iter()
+            .any(|event| matches!(event, DeltaEvent::Hydration { .. }));
+
+        let compiled_state = match self.compiled_state.as_mut() {
+            Some(compiled_state) if !is_hydration => compiled_state,
+            _ => {
+                let mut new_state = self.previous_state.clone();
+                new_state.apply_delta(delta);
+                return self.apply_client_features_with(new_state, cache);
+            }
+        };
+
+        let mut changed_features = HashSet::new();
+        let mut changed_segments = HashSet::new();
+        for event in &delta.events {
+            match event {
+                DeltaEvent::FeatureUpdated { feature, .. } => {
+                    changed_features.insert(feature.name.as_str());
+                }
+                DeltaEvent::FeatureRemoved { feature_name, .. } => {
+                    changed_features.insert(feature_name.as_str());
+                }
+                DeltaEvent::SegmentUpdated { segment, .. } => {
+                    changed_segments.insert(segment.id);
+                }
+                DeltaEvent::SegmentRemoved { segment_id, .. } => {
+                    changed_segments.insert(*segment_id);
+                }
+                DeltaEvent::Hydration { .. } => {}
+            }
+        }
+
+        let old_features: HashMap<String, ClientFeature> = self
+            .previous_state
+            .features
+            .iter()
+            .filter(|feature| changed_features.contains(feature.name.as_str()))
+            .map(|feature| (feature.name.clone(), feature.clone()))
+            .collect();
+        let old_segment_map = build_segment_map(&self.previous_state.segments);
+        let old_segments: HashMap<i32, Segment> = changed_segments
+            .iter()
+            .filter_map(|id| {
+                old_segment_map
+                    .get(id)
+                    .map(|segment| (*id, segment.clone()))
+            })
+            .collect();
+
+        self.previous_state.apply_delta(delta);
+
+        // Taken out rather than dropped, toggles that fail to compile may keep their
+        // previous version
+        let mut previous_toggles: HashMap<&str, Arc<CompiledToggle>> = changed_features
+            .iter()
+            .filter_map(|name| Some((*name, compiled_state.remove(*name)?)))
+            .collect();
+
+        let segment_map = build_segment_map(&self.previous_state.segments);
+        let modified_segments: HashSet<i32> = changed_segments
+            .iter()
+            .filter(|id| segment_changed(old_segments.get(id), segment_map.get(id)))
+            .copied()
+            .collect();
+
+        let mut warnings = vec![];
+        let mut changes = ChangeSet::default();
+        for toggle in &self.previous_state.features {
+            let references_changed_segment =
+                referenced_segments(toggle).any(|segment_id| changed_segments.contains(segment_id));
+            let is_changed_feature = changed_features.contains(toggle.name.as_str());
+
+            if references_changed_segment || is_changed_feature {
+                let previous = previous_toggles
+                    .remove(toggle.name.as_str())
+                    .or_else(|| compiled_state.remove(&toggle.name));
+                compiled_state.insert(
+                    toggle.name.clone(),
+                    Arc::new(compile_with(
+                        toggle,
+                        &segment_map,
+                        &self.custom_strategies,
+                        self.failure_policies.policy_for(toggle),
+                        previous.as_deref(),
+                        &mut warnings,
+                        cache.as_deref_mut(),
+                    )),
+                );
+
+                let old = if is_changed_feature {
+                    old_features.get(toggle.name.as_str())
+                } else {
+                    Some(toggle)
+                };
+                changes.record(old, Some(toggle), &modified_segments);
+            }
+        }
+
+        for (name, old) in &old_features {
+            if !compiled_state.contains_key(name) {
+                changes.record(Some(old), None, &modified_segments);
+            }
+        }
+        changes.sort();
+        warnings.extend(mark_dependency_cycles(&self.previous_state, compiled_state));
+
+        StateUpdate {
+            warnings: (!warnings.is_empty()).then_some(warnings),
+            changes,
+        }
     }
 
     fn get_toggle(&self, name: &str) -> Option<&CompiledToggle> {
         self.compiled_state
             .as_ref()
             .and_then(|state| state.get(name))
+            .map(Arc::as_ref)
     }
 
     pub fn count_toggle(&self, name: &str, enabled: bool) {
@@ -462,8 +945,23 @@ impl EngineState {
     }
 
     pub fn get_metrics(&mut self, close_time: DateTime<Utc>) -> Option<MetricBucket> {
-        let metrics: HashMap<String, ToggleStats> = self
-            .toggle_metrics
+        let metrics = self.drain_toggle_metrics();
+
+        if !metrics.is_empty() {
+            let start = self.toggle_metrics_start;
+            self.toggle_metrics_start = close_time;
+            Some(MetricBucket {
+                toggles: metrics,
+                start,
+                stop: close_time,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn drain_toggle_metrics(&self) -> HashMap<String, ToggleStats> {
+        self.toggle_metrics
             .iter()
             .filter_map(|metric_pair| {
                 let toggle_name = metric_pair.key();
@@ -494,85 +992,181 @@ impl EngineState {
                     None
                 }
             })
-            .collect();
+            .collect()
+    }
 
-        if !metrics.is_empty() {
-            let start = self.toggle_metrics_start;
-            self.toggle_metrics_start = close_time;
-            Some(MetricBucket {
-                toggles: metrics,
-                start,
-                stop: close_time,
+    // Toggles on a dependency cycle never get here with their parents, so the
+    // recursion through enabled always bottoms out
+    fn is_parent_dependency_satisfied<'a>(
+        &'a self,
+        toggle: &'a CompiledToggle,
+        context: &EnrichedContext,
+        memo: &DependencyMemo<'a>,
+    ) -> bool {
+        !toggle.in_dependency_cycle
+            && toggle.dependencies.iter().all(|parent_dependency| {
+                self.check_parent_dependency(toggle, parent_dependency, context, memo)
+                    .satisfied
             })
-        } else {
-            None
+    }
+
+    // Parents are evaluated under the name of the child that depends on them, so
+    // rollouts without a group id hash the same way they would for the child itself
+    fn evaluate_parent<'a>(
+        &'a self,
+        child: &'a CompiledToggle,
+        parent: &'a CompiledToggle,
+        context: &EnrichedContext,
+        memo: &DependencyMemo<'a>,
+    ) -> ParentEvaluation {
+        let key = (child.name.as_str(), parent.name.as_str());
+        if let Some(evaluation) = memo.parents.borrow().get(&key) {
+            return evaluation.clone();
         }
+
+        let context = context.with_toggle_name(child.name.as_str());
+        let evaluation = ParentEvaluation {
+            enabled: self.enabled(parent, &context, memo),
+            variant: self.check_variant_by_toggle(parent, &context),
+        };
+        memo.parents.borrow_mut().insert(key, evaluation.clone());
+        evaluation
     }
 
-    fn is_parent_dependency_satisfied(
-        &self,
-        toggle: &CompiledToggle,
-        enriched_context: &EnrichedContext,
-    ) -> bool {
-        toggle.dependencies.iter().all(|parent_dependency| {
-            let context = enriched_context.with_toggle_name(toggle.name.as_str());
-            let Some(compiled_parent) = self.get_toggle(&parent_dependency.feature) else {
-                return false;
+    fn check_parent_dependency<'a>(
+        &'a self,
+        child: &'a CompiledToggle,
+        parent_dependency: &FeatureDependency,
+        context: &EnrichedContext,
+        memo: &DependencyMemo<'a>,
+    ) -> ParentDependencyCheck {
+        let Some(compiled_parent) = self.get_toggle(&parent_dependency.feature) else {
+            return ParentDependencyCheck {
+                parent_found: false,
+                parent_enabled: None,
+                parent_variant: None,
+                satisfied: false,
             };
+        };
 
-            if !compiled_parent.dependencies.is_empty() {
-                return false;
+        let ParentEvaluation {
+            enabled: parent_enabled,
+            variant: parent_variant,
+        } = self.evaluate_parent(child, compiled_parent, context, memo);
+        let expected_parent_enabled_state = parent_dependency.enabled.unwrap_or(true);
+
+        let is_variant_dependency_satisfied = {
+            if let (Some(expected_variants), Some(actual_variant)) =
+                (&parent_dependency.variants, &parent_variant)
+            {
+                expected_variants.is_empty() || expected_variants.contains(&actual_variant.name)
+            } else {
+                true
             }
+        };
 
-            let parent_enabled = self.enabled(compiled_parent, &context);
-            let expected_parent_enabled_state = parent_dependency.enabled.unwrap_or(true);
-            let parent_variant = self.check_variant_by_toggle(compiled_parent, &context);
+        ParentDependencyCheck {
+            parent_found: true,
+            parent_enabled: Some(parent_enabled),
+            parent_variant,
+            satisfied: is_variant_dependency_satisfied
+                && parent_enabled == expected_parent_enabled_state,
+        }
+    }
 
-            let is_variant_dependency_satisfied = {
-                if let (Some(expected_variants), Some(actual_variant)) =
-                    (&parent_dependency.variants, parent_variant)
-                {
-                    expected_variants.is_empty() || expected_variants.contains(&actual_variant.name)
-                } else {
-                    true
-                }
-            };
+    fn enabled<'a>(
+        &'a self,
+        toggle: &'a CompiledToggle,
+        context: &EnrichedContext,
+        memo: &DependencyMemo<'a>,
+    ) -> bool {
+        self.evaluate(toggle, context, memo).enabled
+    }
 
-            if !is_variant_dependency_satisfied {
-                return false;
-            }
+    // Toggles compiled from a state carry one rule per strategy, so the first one to
+    // match both enables the toggle and picks the strategy variants. Toggles without
+    // those rules fall back to the combined rule
+    fn evaluate<'a>(
+        &'a self,
+        toggle: &'a CompiledToggle,
+        context: &EnrichedContext,
+        memo: &DependencyMemo<'a>,
+    ) -> Evaluation<'a> {
+        let outcome = |enabled, reason| Evaluation {
+            enabled,
+            reason,
+            strategy_variants: None,
+        };
 
-            parent_enabled == expected_parent_enabled_state
-        })
+        if !toggle.enabled {
+            return outcome(false, EvaluationReason::Disabled);
+        }
+        if !self.is_parent_dependency_satisfied(toggle, context, memo) {
+            return outcome(false, EvaluationReason::DependencyUnsatisfied);
+        }
+        if toggle.compile_failed {
+            return outcome((toggle.compiled_strategy)(context), EvaluationReason::Error);
+        }
+
+        match &toggle.compiled_variant_strategy {
+            Some(strategies) if !strategies.is_empty() => {
+                match self.compute_variant_strategies(toggle, context) {
+                    Some(matched) => Evaluation {
+                        enabled: true,
+                        reason: EvaluationReason::TargetingMatch {
+                            strategy_index: Some(matched.index),
+                        },
+                        strategy_variants: Some(matched),
+                    },
+                    None => outcome(false, EvaluationReason::Default),
+                }
+            }
+            _ if (toggle.compiled_strategy)(context) => outcome(
+                true,
+                EvaluationReason::TargetingMatch {
+                    strategy_index: None,
+                },
+            ),
+            _ => outcome(false, EvaluationReason::Default),
+        }
     }
 
-    fn enabled(&self, toggle: &CompiledToggle, context: &EnrichedContext) -> bool {
-        toggle.enabled
-            && self.is_parent_dependency_satisfied(toggle, context)
-            && (toggle.compiled_strategy)(context)
+    fn resolve_toggle<'a>(
+        &'a self,
+        toggle: &'a CompiledToggle,
+        context: &EnrichedContext,
+        memo: &DependencyMemo<'a>,
+    ) -> ResolvedToggle {
+        let evaluation = self.evaluate(toggle, context, memo);
+        ResolvedToggle {
+            enabled: evaluation.enabled,
+            reason: evaluation.reason,
+            impression_data: toggle.impression_data,
+            project: toggle.project.clone(),
+            variant: self.variant_from(toggle, context, evaluation),
+        }
     }
 
     pub fn resolve_all(
         &self,
-        context: &Context,
+        context: &impl ContextSource,
         external_values: &Option<HashMap<String, bool>>,
     ) -> Option<HashMap<String, ResolvedToggle>> {
+        let memo = DependencyMemo::default();
+        // Every toggle sees the same context, so its fields only need parsing once
+        let parsed_values = ParsedValues::default();
         self.compiled_state.as_ref().map(|state| {
             state
                 .iter()
                 .map(|(name, toggle)| {
-                    let enriched_context =
-                        EnrichedContext::from(context, name, external_values.as_ref());
+                    let enriched_context = self.with_sources(EnrichedContext {
+                        parsed_values: Some(&parsed_values),
+                        ..EnrichedContext::from(context, name, external_values.as_ref())
+                    });
 
-                    let enabled = self.enabled(toggle, &enriched_context);
                     (
                         name.clone(),
-                        ResolvedToggle {
-                            enabled,
-                            impression_data: toggle.impression_data,
-                            variant: self.get_variant(name, context, external_values),
-                            project: toggle.project.clone(),
-                        },
+                        self.resolve_toggle(toggle, &enriched_context, &memo),
                     )
                 })
                 .collect()
@@ -582,20 +1176,17 @@ impl EngineState {
     pub fn resolve(
         &self,
         name: &str,
-        context: &Context,
+        context: &impl ContextSource,
         external_values: &Option<HashMap<String, bool>>,
     ) -> Option<ResolvedToggle> {
-        self.compiled_state.as_ref().and_then(|state| {
-            state.get(name).map(|compiled_toggle| ResolvedToggle {
-                enabled: self.enabled(
-                    compiled_toggle,
-                    &EnrichedContext::from(context, name, external_values.as_ref()),
-                ),
-                impression_data: compiled_toggle.impression_data,
-                variant: self.get_variant(name, context, external_values),
-                project: compiled_toggle.project.clone(),
-            })
-        })
+        let memo = DependencyMemo::default();
+        let enriched_context = self.with_sources(EnrichedContext::from(
+            context,
+            name,
+            external_values.as_ref(),
+        ));
+        self.get_toggle(name)
+            .map(|toggle| self.resolve_toggle(toggle, &enriched_context, &memo))
     }
 
     pub fn list_known_toggles(&self) -> Vec<ToggleDefinition> {
@@ -618,6 +1209,14 @@ impl EngineState {
             .unwrap_or_default()
     }
 
+    /// The custom strategies on a toggle that have no registered implementation, keyed
+    /// the way `external_values` expects them. These are listed when the toggle compiles,
+    /// so they always match the rule it's evaluated with. Returns None for unknown toggles
+    pub fn list_external_strategies(&self, toggle_name: &str) -> Option<&[ExternalStrategy]> {
+        self.get_toggle(toggle_name)
+            .map(|toggle| toggle.external_strategies.as_slice())
+    }
+
     pub fn should_emit_impression_event(&self, name: &str) -> bool {
         self.compiled_state
             .as_ref()
@@ -630,21 +1229,26 @@ impl EngineState {
     }
 
     pub fn check_enabled(&self, context: &EnrichedContext) -> Option<bool> {
+        let context = self.with_sources(*context);
         self.get_toggle(context.toggle_name)
-            .map(|toggle| self.enabled(toggle, context))
+            .map(|toggle| self.enabled(toggle, &context, &DependencyMemo::default()))
     }
 
     pub fn is_enabled(
         &self,
         name: &str,
-        context: &Context,
+        context: &impl ContextSource,
         external_values: &Option<HashMap<String, bool>>,
     ) -> bool {
-        let enriched_context = EnrichedContext::from(context, name, external_values.as_ref());
+        let enriched_context = self.with_sources(EnrichedContext::from(
+            context,
+            name,
+            external_values.as_ref(),
+        ));
 
         let is_enabled = self
             .get_toggle(name)
-            .map(|toggle| self.enabled(toggle, &enriched_context))
+            .map(|toggle| self.enabled(toggle, &enriched_context, &DependencyMemo::default()))
             .unwrap_or_default();
 
         is_enabled
@@ -655,12 +1259,12 @@ impl EngineState {
         variants: &'a [CompiledVariant],
         group_id: &str,
         context: &EnrichedContext,
-    ) -> Option<&'a CompiledVariant> {
+    ) -> Option<(&'a CompiledVariant, bool)> {
         if variants.is_empty() {
             return None;
         }
         if let Some(found_override) = check_for_variant_override(variants, context) {
-            return Some(found_override);
+            return Some((found_override, true));
         }
         let total_weight: u32 = variants.iter().map(|var| var.weight as u32).sum();
 
@@ -668,16 +1272,19 @@ impl EngineState {
 
         let target = match get_seed(stickiness, context) {
             Some(seed) => {
-                normalized_hash(group_id, seed, total_weight, VARIANT_NORMALIZATION_SEED).ok()?
+                normalized_hash(group_id, &seed, total_weight, VARIANT_NORMALIZATION_SEED).ok()?
             }
-            None => rand::rng().random_range(1..=total_weight),
+            None => context
+                .random_source
+                .unwrap_or(&ThreadRandom)
+                .random_range(1..=total_weight),
         };
 
         let mut total_weight = 0;
         for variant in variants {
             total_weight += variant.weight as u32;
             if total_weight >= target {
-                return Some(variant);
+                return Some((variant, false));
             }
         }
         None
@@ -692,14 +1299,15 @@ impl EngineState {
             .compiled_variant_strategy
             .as_ref()
             .and_then(|variant_strategies| {
-                variant_strategies
-                    .iter()
-                    .find_map(|(rule, rule_variants, group_id)| {
+                variant_strategies.iter().enumerate().find_map(
+                    |(index, (rule, rule_variants, group_id))| {
                         (rule)(context).then_some(MatchedStrategyVariants {
+                            index,
                             variants: rule_variants,
                             group_id,
                         })
-                    })
+                    },
+                )
             })
     }
 
@@ -708,22 +1316,42 @@ impl EngineState {
         toggle: &CompiledToggle,
         context: &EnrichedContext,
         matched_strategy: Option<MatchedStrategyVariants<'_>>,
-    ) -> Option<VariantDef> {
-        let variant = match matched_strategy {
+    ) -> Option<(VariantDef, EvaluationReason)> {
+        let strategy_index = matched_strategy.as_ref().map(|strategy| strategy.index);
+        let (variant, reason) = match matched_strategy {
             Some(strategy) if strategy.has_strategy_variants() => {
-                self.resolve_variant(strategy.variants, strategy.group_id, context)
+                let (variant, _) =
+                    self.resolve_variant(strategy.variants, strategy.group_id, context)?;
+                (
+                    variant,
+                    EvaluationReason::StrategyVariant {
+                        strategy_index: strategy.index,
+                    },
+                )
             }
             // Unleash can and will send empty lists for strategy variants when they aren't set
             // in that case, we should treat it the same as missing variants and
             // attempt to fall back to the top level variant
-            Some(_) | None => self.resolve_variant(&toggle.variants, &toggle.name, context),
+            Some(_) | None => {
+                let (variant, overridden) =
+                    self.resolve_variant(&toggle.variants, &toggle.name, context)?;
+                let reason = if overridden {
+                    EvaluationReason::VariantOverride { strategy_index }
+                } else {
+                    EvaluationReason::ToggleVariant { strategy_index }
+                };
+                (variant, reason)
+            }
         };
 
-        variant.map(|variant| VariantDef {
-            name: variant.name.clone(),
-            payload: variant.payload.clone(),
-            enabled: true,
-        })
+        Some((
+            VariantDef {
+                name: variant.name.clone(),
+                payload: variant.payload.clone(),
+                enabled: true,
+            },
+            reason,
+        ))
     }
 
     fn check_variant_by_toggle(
@@ -733,38 +1361,16 @@ impl EngineState {
     ) -> Option<VariantDef> {
         let strategy_variants = self.compute_variant_strategies(toggle, context);
         self.choose_variant(toggle, context, strategy_variants)
-    }
-
-    fn variant_enabled<'a>(
-        &self,
-        toggle: &'a CompiledToggle,
-        context: &EnrichedContext,
-    ) -> (bool, Option<MatchedStrategyVariants<'a>>) {
-        if !toggle.enabled || !self.is_parent_dependency_satisfied(toggle, context) {
-            return (false, None);
-        }
-
-        let strategy_variants = self.compute_variant_strategies(toggle, context);
-        if strategy_variants.is_some() {
-            return (true, strategy_variants);
-        }
-
-        if toggle
-            .compiled_variant_strategy
-            .as_ref()
-            .is_some_and(|strategies| !strategies.is_empty())
-        {
-            return (false, None);
-        }
-
-        ((toggle.compiled_strategy)(context), None)
+            .map(|(variant, _)| variant)
     }
 
     pub fn check_variant(&self, context: &EnrichedContext) -> Option<VariantDef> {
+        let context = self.with_sources(*context);
         self.get_toggle(context.toggle_name).map(|toggle| {
-            let (enabled, strategy_variants) = self.variant_enabled(toggle, context);
-            if enabled {
-                self.choose_variant(toggle, context, strategy_variants)
+            let evaluation = self.evaluate(toggle, &context, &DependencyMemo::default());
+            if evaluation.enabled {
+                self.choose_variant(toggle, &context, evaluation.strategy_variants)
+                    .map(|(variant, _)| variant)
                     .unwrap_or_default()
             } else {
                 VariantDef::default()
@@ -775,28 +1381,180 @@ impl EngineState {
     pub fn get_variant(
         &self,
         name: &str,
-        context: &Context,
+        context: &impl ContextSource,
         external_values: &Option<HashMap<String, bool>>,
     ) -> ExtendedVariantDef {
-        let toggle = self.get_toggle(name);
-        let enriched_context = EnrichedContext::from(context, name, external_values.as_ref());
+        let Some(toggle) = self.get_toggle(name) else {
+            return VariantDef::default()
+                .to_enriched_response(false, EvaluationReason::FlagNotFound);
+        };
 
-        let (enabled, strategy_variants) = toggle
-            .map(|toggle| self.variant_enabled(toggle, &enriched_context))
-            .unwrap_or((false, None));
+        let enriched_context = self.with_sources(EnrichedContext::from(
+            context,
+            name,
+            external_values.as_ref(),
+        ));
+        let evaluation = self.evaluate(toggle, &enriched_context, &DependencyMemo::default());
+        self.variant_from(toggle, &enriched_context, evaluation)
+    }
 
-        let variant = match toggle {
-            Some(toggle) if enabled => {
-                self.choose_variant(toggle, &enriched_context, strategy_variants)
-            }
-            _ => None,
+    // A toggle that's on but has no variant to hand out still reports why it's on
+    fn variant_from(
+        &self,
+        toggle: &CompiledToggle,
+        context: &EnrichedContext,
+        evaluation: Evaluation<'_>,
+    ) -> ExtendedVariantDef {
+        if !evaluation.enabled {
+            return VariantDef::default().to_enriched_response(false, evaluation.reason);
         }
-        .unwrap_or_default();
 
-        variant.to_enriched_response(enabled)
+        match self.choose_variant(toggle, context, evaluation.strategy_variants) {
+            Some((variant, reason)) => variant.to_enriched_response(true, reason),
+            None => VariantDef::default().to_enriched_response(true, evaluation.reason),
+        }
     }
 
-    pub fn take_state(&mut self, message: UpdateMessage) -> Option<Vec<EvalWarning>> {
+    /// Evaluates a toggle the same way `is_enabled` and `get_variant` would, but reports on
+    /// every step along the way rather than only the outcome. Strategies are traced from the
+    /// rules the toggle was compiled with, so a version kept by a failure policy is reported
+    /// on rather than the one that failed. Tracing doesn't short circuit, so this is slower
+    /// than a normal evaluation and is intended for debugging only
+    pub fn explain(
+        &self,
+        name: &str,
+        context: &impl ContextSource,
+        external_values: &Option<HashMap<String, bool>>,
+    ) -> Option<ToggleExplanation> {
+        let toggle = self.get_toggle(name)?;
+        let enriched_context = self.with_sources(EnrichedContext::from(
+            context,
+            name,
+            external_values.as_ref(),
+        ));
+
+        let memo = DependencyMemo::default();
+        let dependencies = toggle
+            .dependencies
+            .iter()
+            .map(|parent_dependency| {
+                let check = self.check_parent_dependency(
+                    toggle,
+                    parent_dependency,
+                    &enriched_context,
+                    &memo,
+                );
+                DependencyExplanation {
+                    feature: parent_dependency.feature.clone(),
+                    expected_enabled: parent_dependency.enabled.unwrap_or(true),
+                    expected_variants: parent_dependency.variants.clone(),
+                    parent_found: check.parent_found,
+                    parent_enabled: check.parent_enabled,
+                    parent_variant: check.parent_variant.map(|variant| variant.name),
+                    satisfied: check.satisfied,
+                }
+            })
+            .collect();
+
+        let strategies = toggle
+            .compiled_strategies
+            .iter()
+            .enumerate()
+            .map(|(index, strategy)| match &strategy.rule {
+                Ok((rule, tree)) => {
+                    let trace = tree.trace(&enriched_context);
+                    StrategyExplanation {
+                        index,
+                        name: strategy.name.clone(),
+                        rule: Some(rule.to_string()),
+                        result: trace.result(),
+                        trace: Some(trace),
+                        error: None,
+                    }
+                }
+                Err(e) => StrategyExplanation {
+                    index,
+                    name: strategy.name.clone(),
+                    rule: None,
+                    result: false,
+                    trace: None,
+                    error: Some(format!("{e:?}")),
+                },
+            })
+            .collect();
+
+        let resolved = self.resolve_toggle(toggle, &enriched_context, &memo);
+        Some(ToggleExplanation {
+            name: toggle.name.clone(),
+            toggle_enabled: toggle.enabled,
+            dependencies,
+            strategies,
+            enabled: resolved.enabled,
+            reason: resolved.reason,
+            variant: resolved.variant,
+        })
+    }
+
+    /// Registers a native implementation for a custom strategy. Toggles using a strategy
+    /// by that name will call it instead of looking up an external value. If a state
+    /// has already been applied it's recompiled, so the strategy applies straight away
+    pub fn register_custom_strategy(&mut self, strategy: impl CustomStrategy + 'static) {
+        self.custom_strategies.register(Arc::new(strategy));
+        self.recompile(None);
+    }
+
+    /// Sets how toggles that fail to compile behave. If a state has already been applied
+    /// it's recompiled, so the policies apply straight away
+    pub fn set_failure_policies(&mut self, failure_policies: FailurePolicies) {
+        self.failure_policies = failure_policies;
+        self.recompile(None);
+    }
+
+    /// Sets the clock that date constraints compare against when the context has no
+    /// current time
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = Some(clock);
+    }
+
+    /// Sets where random stickiness, random constraints and variants without a
+    /// stickiness value get their numbers from
+    pub fn set_random_source(&mut self, random_source: Arc<dyn RandomSource>) {
+        self.random_source = Some(random_source);
+    }
+
+    // Contexts are evaluated with this engine's clock and random source, unless the
+    // caller has already given them their own
+    fn with_sources<'a>(&'a self, context: EnrichedContext<'a>) -> EnrichedContext<'a> {
+        EnrichedContext {
+            clock: context.clock.or(self.clock.as_deref()),
+            random_source: context.random_source.or(self.random_source.as_deref()),
+            ..context
+        }
+    }
+
+    pub(crate) fn set_custom_strategies(
+        &mut self,
+        custom_strategies: CustomStrategies,
+        cache: Option<&mut ConstraintCache>,
+    ) {
+        self.custom_strategies = custom_strategies;
+        self.recompile(cache);
+    }
+
+    fn recompile(&mut self, cache: Option<&mut ConstraintCache>) {
+        if self.compiled_state.is_some() {
+            let (compiled_state, _) = compile_state_with(
+                &self.previous_state,
+                &self.custom_strategies,
+                &self.failure_policies,
+                self.compiled_state.as_ref(),
+                cache,
+            );
+            self.compiled_state = Some(compiled_state);
+        }
+    }
+
+    pub fn take_state(&mut self, message: UpdateMessage) -> StateUpdate {
         match message {
             UpdateMessage::PartialUpdate(delta) => self.apply_delta(&delta),
             UpdateMessage::FullResponse(state) => self.apply_client_features(state),
@@ -811,35 +1569,66 @@ impl EngineState {
         }
     }
 
-    pub fn apply_client_features(&mut self, toggles: ClientFeatures) -> Option<Vec<EvalWarning>> {
-        let (compiled_state, warnings) = compile_state(&toggles);
+    pub fn apply_client_features(&mut self, toggles: ClientFeatures) -> StateUpdate {
+        self.apply_client_features_with(toggles, None)
+    }
+
+    pub(crate) fn apply_client_features_with(
+        &mut self,
+        toggles: ClientFeatures,
+        cache: Option<&mut ConstraintCache>,
+    ) -> StateUpdate {
+        let (compiled_state, update) = self.compile_client_features(&toggles, cache);
         self.previous_state = toggles;
         self.compiled_state = Some(compiled_state);
-        if !warnings.is_empty() {
-            Some(warnings)
-        } else {
-            None
-        }
+        update
     }
-}
 
-fn get_seed<'a>(stickiness: Option<&str>, context: &'a EnrichedContext<'a>) -> Option<&'a str> {
-    match stickiness {
-        Some("default") | None => context.user_id.or(context.session_id),
-        Some(custom) => match custom {
-            "userId" => context.user_id,
-            "sessionId" => context.session_id,
-            "environment" => context.environment,
-            "appName" => context.app_name,
-            "remoteAddress" => context.remote_address,
-            _ => context
-                .properties
-                .as_ref()
-                .and_then(|props| props.get(custom)),
-        },
+    // Compiles a full state and diffs it against this one, without modifying this one
+    fn compile_client_features(
+        &self,
+        toggles: &ClientFeatures,
+        cache: Option<&mut ConstraintCache>,
+    ) -> (CompiledState, StateUpdate) {
+        let (compiled_state, warnings) = compile_state_with(
+            toggles,
+            &self.custom_strategies,
+            &self.failure_policies,
+            self.compiled_state.as_ref(),
+            cache,
+        );
+        let changes = ChangeSet::between(&self.previous_state, toggles);
+
+        (
+            compiled_state,
+            StateUpdate {
+                warnings: (!warnings.is_empty()).then_some(warnings),
+                changes,
+            },
+        )
     }
 }
 
+// Variants are seeded from the same stickiness chains as rollouts. There's no seed
+// once the chain reaches random, which happens at the end of the default chain or
+// when every field in the chain is missing, and the variant is picked at random.
+// Variant stickiness on currentTime has always read a property of that name rather
+// than the context's time or the clock, so that's kept as is to avoid rebucketing
+fn get_seed<'a>(
+    stickiness: Option<&str>,
+    context: &'a EnrichedContext<'a>,
+) -> Option<Cow<'a, str>> {
+    let fields: Vec<ContextField> = lower_stickiness(stickiness)
+        .into_iter()
+        .take_while(|field| !matches!(field, ContextField::Random(_)))
+        .map(|field| match field {
+            ContextField::CurrentTime => ContextField::Property("currentTime".into()),
+            field => field,
+        })
+        .collect();
+    first_field_value(&fields, context)
+}
+
 fn lookup_override_context<'a>(
     variant_override: &Override,
     context: &'a EnrichedContext<'a>,
@@ -885,12 +1674,17 @@ pub struct VariantDef {
 }
 
 impl VariantDef {
-    pub fn to_enriched_response(&self, toggle_enabled: bool) -> ExtendedVariantDef {
+    pub fn to_enriched_response(
+        &self,
+        toggle_enabled: bool,
+        reason: EvaluationReason,
+    ) -> ExtendedVariantDef {
         ExtendedVariantDef {
             name: self.name.clone(),
             payload: self.payload.clone(),
             enabled: self.enabled,
             feature_enabled: toggle_enabled,
+            reason,
         }
     }
 }
@@ -903,6 +1697,8 @@ pub struct ExtendedVariantDef {
     pub payload: Option<Payload>,
     pub enabled: bool,
     pub feature_enabled: bool,
+    #[serde(default)]
+    pub reason: EvaluationReason,
 }
 
 impl Default for VariantDef {
@@ -930,12 +1726,18 @@ mod test {
     };
     use test_case::test_case;
     use unleash_types::client_features::{
-        ClientFeaturesDelta, FeatureDependency, Override, Payload,
+        ClientFeatures, ClientFeaturesDelta, FeatureDependency, Override, Payload,
     };
 
     use crate::{
-        check_for_variant_override, get_seed, state::EnrichedContext, CompiledToggle,
-        CompiledVariant, Context, EngineState, UpdateMessage, VariantDef,
+        change_set::{ChangeKind, ChangeSet, ModifiedToggle},
+        check_for_variant_override,
+        custom_strategy::CustomStrategy,
+        get_seed,
+        state::{EnrichedContext, PropertyValue, StructuredContext},
+        strategy_parsing::RuleTrace,
+        CompiledToggle, CompiledVariant, Context, EngineState, EvaluationReason,
+        ExtendedVariantDef, StateUpdate, UpdateMessage, VariantDef,
     };
 
     const SPEC_FOLDER: &str = "../client-specification/specifications";
@@ -1005,14 +1807,293 @@ mod test {
             ..Context::default()
         };
 
-        engine.apply_delta(&delta);
-        assert!(!engine.is_enabled("test-flag", &context, &None));
-        assert!(engine.get_toggle("removed-flag").is_some());
-        assert!(!engine.is_enabled("segment-flag", &context, &None));
-        engine.apply_delta(&patch);
-        assert!(engine.is_enabled("test-flag", &context, &None));
-        assert!(engine.get_toggle("removed-flag").is_none());
-        assert!(engine.is_enabled("segment-flag", &context, &None));
+        engine.apply_delta(&delta);
+        assert!(!engine.is_enabled("test-flag", &context, &None));
+        assert!(engine.get_toggle("removed-flag").is_some());
+        assert!(!engine.is_enabled("segment-flag", &context, &None));
+        engine.apply_delta(&patch);
+        assert!(engine.is_enabled("test-flag", &context, &None));
+        assert!(engine.get_toggle("removed-flag").is_none());
+        assert!(engine.is_enabled("segment-flag", &context, &None));
+    }
+
+    fn incremental_test_state() -> ClientFeatures {
+        serde_json::from_value(serde_json::json!({
+            "version": 2,
+            "features": [
+                {
+                    "name": "first-segment-flag",
+                    "enabled": true,
+                    "strategies": [{ "name": "default", "segments": [1] }]
+                },
+                {
+                    "name": "second-segment-flag",
+                    "enabled": true,
+                    "strategies": [{ "name": "default", "segments": [2] }]
+                },
+                {
+                    "name": "plain-flag",
+                    "enabled": true,
+                    "strategies": [{
+                        "name": "userWithId",
+                        "parameters": { "userIds": "1, 3, 5" }
+                    }],
+                    "variants": [{ "name": "blue", "weight": 1000, "stickiness": "default" }]
+                },
+                {
+                    "name": "broken-flag",
+                    "enabled": true,
+                    "strategies": [{
+                        "name": "default",
+                        "constraints": [{
+                            "contextName": "userId",
+                            "operator": "NOT_AN_OPERATOR",
+                            "values": ["1"]
+                        }]
+                    }]
+                }
+            ],
+            "segments": [
+                {
+                    "id": 1,
+                    "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["1", "2"] }]
+                },
+                {
+                    "id": 2,
+                    "constraints": [{ "contextName": "environment", "operator": "IN", "values": ["prod"] }]
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn incremental_test_delta(events: serde_json::Value) -> ClientFeaturesDelta {
+        serde_json::from_value(serde_json::json!({ "events": events })).unwrap()
+    }
+
+    fn assert_matches_full_compile(engine: &EngineState) {
+        let mut full = EngineState::default();
+        full.apply_client_features(engine.get_state());
+
+        let resolve = |engine: &EngineState, context: &Context| {
+            let mut resolved: Vec<(String, bool, ExtendedVariantDef)> = engine
+                .resolve_all(context, &None)
+                .unwrap()
+                .into_iter()
+                .map(|(name, toggle)| (name, toggle.enabled, toggle.variant))
+                .collect();
+            resolved.sort_by(|a, b| a.0.cmp(&b.0));
+            resolved
+        };
+
+        for user_id in 0..8 {
+            for environment in ["prod", "dev"] {
+                let context = Context {
+                    user_id: Some(user_id.to_string()),
+                    environment: Some(environment.into()),
+                    ..Context::default()
+                };
+                assert_eq!(resolve(engine, &context), resolve(&full, &context));
+            }
+        }
+    }
+
+    fn incremental_test_deltas() -> Vec<serde_json::Value> {
+        vec![
+            serde_json::json!([{
+                "type": "segment-updated",
+                "eventId": 2,
+                "segment": {
+                    "id": 1,
+                    "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["4"] }]
+                }
+            }]),
+            serde_json::json!([{ "type": "segment-removed", "eventId": 3, "segmentId": 2 }]),
+            serde_json::json!([
+                {
+                    "type": "feature-updated",
+                    "eventId": 4,
+                    "feature": {
+                        "name": "plain-flag",
+                        "enabled": true,
+                        "strategies": [{
+                            "name": "userWithId",
+                            "parameters": { "userIds": "2, 4" },
+                            "variants": [{ "name": "red", "weight": 1000, "stickiness": "default" }]
+                        }]
+                    }
+                },
+                {
+                    "type": "feature-updated",
+                    "eventId": 4,
+                    "feature": {
+                        "name": "new-flag",
+                        "enabled": true,
+                        "strategies": [{ "name": "default", "segments": [1] }]
+                    }
+                }
+            ]),
+            serde_json::json!([
+                { "type": "feature-removed", "eventId": 5, "featureName": "first-segment-flag", "project": "default" },
+                {
+                    "type": "segment-updated",
+                    "eventId": 5,
+                    "segment": {
+                        "id": 2,
+                        "constraints": [{ "contextName": "environment", "operator": "IN", "values": ["dev"] }]
+                    }
+                }
+            ]),
+        ]
+    }
+
+    #[test]
+    fn incremental_delta_matches_full_recompilation() {
+        let mut engine = EngineState::default();
+        engine.apply_client_features(incremental_test_state());
+        assert_matches_full_compile(&engine);
+
+        let deltas = incremental_test_deltas();
+
+        for delta in deltas {
+            engine.apply_delta(&incremental_test_delta(delta));
+            assert_matches_full_compile(&engine);
+        }
+
+        assert!(engine.get_toggle("first-segment-flag").is_none());
+        assert!(engine.get_toggle("new-flag").is_some());
+    }
+
+    #[test]
+    fn delta_change_sets_match_a_full_diff() {
+        let mut engine = EngineState::default();
+        engine.apply_client_features(incremental_test_state());
+
+        for delta in incremental_test_deltas() {
+            let old_state = engine.get_state();
+            let changes = engine.apply_delta(&incremental_test_delta(delta)).changes;
+
+            assert!(!changes.is_empty());
+            assert_eq!(changes, ChangeSet::between(&old_state, &engine.get_state()));
+        }
+    }
+
+    #[test]
+    fn state_updates_report_changed_toggles() {
+        let mut engine = EngineState::default();
+        let changes = engine
+            .apply_client_features(incremental_test_state())
+            .changes;
+        assert_eq!(
+            changes.added,
+            vec![
+                "broken-flag",
+                "first-segment-flag",
+                "plain-flag",
+                "second-segment-flag"
+            ]
+        );
+
+        let changes = engine
+            .apply_delta(&incremental_test_delta(serde_json::json!([
+                {
+                    "type": "feature-updated",
+                    "eventId": 2,
+                    "feature": {
+                        "name": "plain-flag",
+                        "enabled": false,
+                        "impressionData": true,
+                        "strategies": [{
+                            "name": "userWithId",
+                            "parameters": { "userIds": "1, 3, 5" }
+                        }],
+                        "variants": [{ "name": "blue", "weight": 1000, "stickiness": "default" }]
+                    }
+                },
+                { "type": "feature-removed", "eventId": 2, "featureName": "broken-flag", "project": "default" },
+                {
+                    "type": "segment-updated",
+                    "eventId": 2,
+                    "segment": {
+                        "id": 1,
+                        "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["4"] }]
+                    }
+                },
+                {
+                    "type": "segment-updated",
+                    "eventId": 2,
+                    "segment": {
+                        "id": 2,
+                        "constraints": [{ "contextName": "environment", "operator": "IN", "values": ["prod"] }]
+                    }
+                }
+            ])))
+            .changes;
+
+        assert_eq!(
+            changes,
+            ChangeSet {
+                added: vec![],
+                removed: vec!["broken-flag".into()],
+                modified: vec![
+                    ModifiedToggle {
+                        name: "first-segment-flag".into(),
+                        changes: vec![ChangeKind::Segments],
+                    },
+                    ModifiedToggle {
+                        name: "plain-flag".into(),
+                        changes: vec![ChangeKind::Enabled, ChangeKind::ImpressionData],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn incremental_delta_only_recompiles_affected_toggles() {
+        let mut engine = EngineState::default();
+        let warnings = engine
+            .apply_client_features(incremental_test_state())
+            .warnings;
+        assert_eq!(warnings.unwrap().len(), 2);
+
+        let warnings = engine
+            .apply_delta(&incremental_test_delta(serde_json::json!([{
+                "type": "segment-updated",
+                "eventId": 2,
+                "segment": {
+                    "id": 1,
+                    "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["4"] }]
+                }
+            }])))
+            .warnings;
+        assert!(warnings.is_none());
+
+        let warnings = engine
+            .apply_delta(&incremental_test_delta(serde_json::json!([
+                { "type": "segment-removed", "eventId": 3, "segmentId": 2 }
+            ])))
+            .warnings
+            .unwrap();
+        let names: Vec<&str> = warnings.iter().map(|w| w.toggle_name.as_str()).collect();
+        assert_eq!(names, vec!["second-segment-flag", "second-segment-flag"]);
+    }
+
+    #[test]
+    fn hydration_delta_replaces_the_whole_state() {
+        let mut engine = EngineState::default();
+        engine.apply_client_features(incremental_test_state());
+
+        engine.apply_delta(&incremental_test_delta(serde_json::json!([{
+            "type": "hydration",
+            "eventId": 10,
+            "features": [{ "name": "only-flag", "enabled": true, "strategies": [] }],
+            "segments": []
+        }])));
+
+        assert!(engine.get_toggle("plain-flag").is_none());
+        assert!(engine.get_toggle("only-flag").is_some());
+        assert_matches_full_compile(&engine);
     }
 
     #[test_case("01-simple-examples.json"; "Basic client spec")]
@@ -1039,7 +2120,10 @@ mod test {
     #[test_case("22-cidr-constraint-operators.json"; "Cidr constraints")]
 
     fn run_client_spec(spec_name: &str) {
-        let spec = load_spec(spec_name);
+        run_suite(load_spec(spec_name));
+    }
+
+    fn run_suite(spec: TestSuite) {
         let mut engine = EngineState::default();
         engine.take_state(spec.state);
 
@@ -1075,12 +2159,112 @@ mod test {
         }
     }
 
+    #[test]
+    fn strategies_are_evaluated_in_sort_order() {
+        let strategy = |sort_order: Option<i32>, variant: &str| {
+            serde_json::json!({
+                "name": "flexibleRollout",
+                "sortOrder": sort_order,
+                "parameters": { "rollout": "100", "stickiness": "default", "groupId": "group" },
+                "variants": [{ "name": variant, "weight": 1000, "stickiness": "default" }]
+            })
+        };
+        let variant_test = |description: &str, toggle_name: &str, variant: &str| {
+            serde_json::json!({
+                "description": description,
+                "context": { "userId": "1" },
+                "toggleName": toggle_name,
+                "expectedResult": { "name": variant, "enabled": true, "feature_enabled": true }
+            })
+        };
+
+        run_suite(
+            serde_json::from_value(serde_json::json!({
+                "state": {
+                    "version": 2,
+                    "features": [
+                        {
+                            "name": "reversed",
+                            "enabled": true,
+                            "strategies": [strategy(Some(2), "second"), strategy(Some(1), "first")]
+                        },
+                        {
+                            "name": "missing-sort-order",
+                            "enabled": true,
+                            "strategies": [strategy(None, "unsorted"), strategy(Some(5), "sorted")]
+                        },
+                        {
+                            "name": "same-sort-order",
+                            "enabled": true,
+                            "strategies": [strategy(Some(1), "first"), strategy(Some(1), "second")]
+                        },
+                        {
+                            "name": "negative-sort-order",
+                            "enabled": true,
+                            "strategies": [strategy(Some(0), "zero"), strategy(Some(-1), "negative")]
+                        }
+                    ]
+                },
+                "variantTests": [
+                    variant_test("Lowest sort order wins over payload order", "reversed", "first"),
+                    variant_test(
+                        "Strategies without a sort order go last",
+                        "missing-sort-order",
+                        "sorted"
+                    ),
+                    variant_test("Ties keep payload order", "same-sort-order", "first"),
+                    variant_test("Negative sort orders go first", "negative-sort-order", "negative")
+                ]
+            }))
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn strategy_indexes_follow_sort_order() {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_value(serde_json::json!({
+                "version": 2,
+                "features": [{
+                    "name": "toggle",
+                    "enabled": true,
+                    "strategies": [
+                        {
+                            "name": "default",
+                            "sortOrder": 2,
+                            "variants": [{ "name": "default", "weight": 1000, "stickiness": "default" }]
+                        },
+                        {
+                            "name": "userWithId",
+                            "sortOrder": 1,
+                            "parameters": { "userIds": "1" },
+                            "variants": [{ "name": "user", "weight": 1000, "stickiness": "default" }]
+                        }
+                    ]
+                }]
+            }))
+            .unwrap(),
+        ));
+
+        let resolved = engine.resolve("toggle", &user_context("2"), &None).unwrap();
+        assert_eq!(resolved.variant.name, "default");
+        assert_eq!(
+            resolved.variant.reason,
+            EvaluationReason::StrategyVariant { strategy_index: 1 }
+        );
+
+        let explanation = engine.explain("toggle", &user_context("1"), &None).unwrap();
+        assert_eq!(explanation.strategies[0].name, "userWithId");
+        assert!(explanation.strategies[0].result);
+    }
+
     #[test]
     pub fn stickiness_for_variants_falls_back_to_random_if_no_context_property_present() {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "cool-animals".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "cool-animals".into(),
                 enabled: true,
                 variants: vec![CompiledVariant {
@@ -1091,7 +2275,7 @@ mod test {
                     overrides: None,
                 }],
                 ..CompiledToggle::default()
-            },
+            }),
         );
         let state = EngineState {
             compiled_state: Some(compiled_state),
@@ -1107,13 +2291,13 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "test".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "test".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
                 variants: vec![],
                 ..CompiledToggle::default()
-            },
+            }),
         );
         let state = EngineState {
             compiled_state: Some(compiled_state),
@@ -1123,7 +2307,12 @@ mod test {
 
         assert_eq!(
             state.get_variant("test", &context, &None),
-            VariantDef::default().to_enriched_response(true)
+            VariantDef::default().to_enriched_response(
+                true,
+                EvaluationReason::TargetingMatch {
+                    strategy_index: None
+                }
+            )
         );
     }
 
@@ -1132,12 +2321,12 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         let mut state = EngineState {
@@ -1154,12 +2343,12 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         let mut state = EngineState {
@@ -1206,7 +2395,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             toggle_name.to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: toggle_name.into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
@@ -1218,7 +2407,7 @@ mod test {
                     overrides: None,
                 }],
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         let mut state = EngineState {
@@ -1247,6 +2436,11 @@ mod test {
     #[test_case(Some("sessionId"), Some("sessionId"), Some("userId"), Some("sessionId"); "should use custom sessionId stickiness")]
     #[test_case(Some("random"), Some("sessionId"), Some("userId"), None; "should return no seed for random stickiness")]
     #[test_case(Some("customId"), Some("sessionId"), Some("userId"), Some("customId"); "should use custom stickiness")]
+    #[test_case(Some("userId|customId"), Some("sessionId"), None, Some("customId"); "should fall back along a chain")]
+    #[test_case(Some("deviceId | sessionId"), Some("sessionId"), Some("userId"), Some("sessionId"); "should skip missing fields in a chain")]
+    #[test_case(Some("userId|sessionId"), Some("sessionId"), Some("userId"), Some("userId"); "should prefer the first field in a chain")]
+    #[test_case(Some("deviceId|otherId"), Some("sessionId"), Some("userId"), None; "should return no seed when the whole chain is missing")]
+    #[test_case(Some("random|userId"), Some("sessionId"), Some("userId"), None; "should stop at random in a chain")]
     pub fn test_get_seed(
         stickiness: Option<&str>,
         session_id: Option<&str>,
@@ -1267,7 +2461,33 @@ mod test {
 
         let enriched_context = EnrichedContext::from(&context, "some-toggle", None);
 
-        assert_eq!(get_seed(stickiness, &enriched_context), expected);
+        assert_eq!(get_seed(stickiness, &enriched_context).as_deref(), expected);
+    }
+
+    #[test]
+    fn current_time_variant_stickiness_reads_the_property() {
+        let mut context = Context {
+            user_id: Some("7".into()),
+            current_time: Some("2024-01-01T00:00:00Z".into()),
+            ..Default::default()
+        };
+        let enriched_context = EnrichedContext::from(&context, "some-toggle", None);
+        assert_eq!(get_seed(Some("currentTime"), &enriched_context), None);
+        assert_eq!(
+            get_seed(Some("currentTime|userId"), &enriched_context).as_deref(),
+            Some("7")
+        );
+
+        context
+            .properties
+            .as_mut()
+            .unwrap()
+            .insert("currentTime".into(), "morning".into());
+        let enriched_context = EnrichedContext::from(&context, "some-toggle", None);
+        assert_eq!(
+            get_seed(Some("currentTime"), &enriched_context).as_deref(),
+            Some("morning")
+        );
     }
 
     #[test]
@@ -1275,7 +2495,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
@@ -1287,17 +2507,17 @@ mod test {
                     overrides: None,
                 }],
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         compiled_state.insert(
             "some-toggle-other".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle-other".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         let state = EngineState {
@@ -1325,7 +2545,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
@@ -1337,17 +2557,17 @@ mod test {
                     overrides: None,
                 }],
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         compiled_state.insert(
             "some-toggle-other".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle-other".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         let state = EngineState {
@@ -1439,7 +2659,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
@@ -1462,7 +2682,7 @@ mod test {
                     "some-toggle".to_string(),
                 )]),
                 ..CompiledToggle::default()
-            },
+            }),
         );
         let state = EngineState {
             compiled_state: Some(compiled_state),
@@ -1480,7 +2700,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new({
@@ -1509,7 +2729,7 @@ mod test {
                     "some-toggle".to_string(),
                 )]),
                 ..CompiledToggle::default()
-            },
+            }),
         );
         let state = EngineState {
             compiled_state: Some(compiled_state),
@@ -1528,7 +2748,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: false,
                 compiled_strategy: Box::new(|_| true),
@@ -1545,7 +2765,7 @@ mod test {
                     "some-toggle".to_string(),
                 )]),
                 ..CompiledToggle::default()
-            },
+            }),
         );
         let state = EngineState {
             compiled_state: Some(compiled_state),
@@ -1612,7 +2832,7 @@ mod test {
             ..Context::default()
         };
 
-        let warnings = engine.take_state(feature_set);
+        let warnings = engine.take_state(feature_set).warnings;
 
         let results = engine.resolve_all(&context, &None);
         let targeted_toggle = results.unwrap().get("toggle1").unwrap().clone();
@@ -1689,7 +2909,7 @@ mod test {
             ..Context::default()
         };
 
-        let warnings = engine.take_state(feature_set);
+        let warnings = engine.take_state(feature_set).warnings;
         let enabled = engine
             .check_enabled(&EnrichedContext::from(&context, "toggle1", None))
             .unwrap();
@@ -1763,7 +2983,7 @@ mod test {
             ..Context::default()
         };
 
-        let warnings = engine.take_state(feature_set);
+        let warnings = engine.take_state(feature_set).warnings;
         let enabled = engine
             .check_enabled(&EnrichedContext::from(&context, "toggle1", None))
             .unwrap();
@@ -1777,7 +2997,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
@@ -1794,18 +3014,18 @@ mod test {
                     variants: Some(vec!["don't-ignore-me".into()]),
                 }],
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         compiled_state.insert(
             "parent-flag".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "parent-flag".into(),
                 enabled: false,
                 compiled_strategy: Box::new(|_| true),
                 variants: vec![],
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         let state = EngineState {
@@ -1820,6 +3040,316 @@ mod test {
         assert_eq!(variant.name, "disabled");
     }
 
+    fn dependency_test_engine(features: serde_json::Value) -> (EngineState, StateUpdate) {
+        let mut engine = EngineState::default();
+        let update = engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_value(serde_json::json!({ "version": 2, "features": features }))
+                .unwrap(),
+        ));
+        (engine, update)
+    }
+
+    fn user_context(user_id: &str) -> Context {
+        Context {
+            user_id: Some(user_id.into()),
+            ..Context::default()
+        }
+    }
+
+    fn reason_test_engine() -> EngineState {
+        let variant = |name: &str| serde_json::json!({ "name": name, "weight": 1000, "stickiness": "default" });
+        let user_ids = |ids: &str| serde_json::json!({ "name": "userWithId", "parameters": { "userIds": ids } });
+        let (engine, _) = dependency_test_engine(serde_json::json!([
+            { "name": "disabled", "enabled": false, "strategies": [user_ids("1")] },
+            { "name": "dependent", "enabled": true, "dependencies": [{ "feature": "disabled" }] },
+            {
+                "name": "broken",
+                "enabled": true,
+                "strategies": [{
+                    "name": "default",
+                    "constraints": [{ "contextName": "userId", "operator": "NOT_AN_OPERATOR", "values": ["1"] }]
+                }]
+            },
+            { "name": "unmatched", "enabled": true, "strategies": [user_ids("2")] },
+            { "name": "no-strategies", "enabled": true },
+            { "name": "second-strategy", "enabled": true, "strategies": [user_ids("2"), user_ids("1")] },
+            {
+                "name": "strategy-variant",
+                "enabled": true,
+                "strategies": [
+                    user_ids("2"),
+                    { "name": "default", "variants": [variant("from-strategy")] }
+                ],
+                "variants": [variant("from-toggle")]
+            },
+            {
+                "name": "toggle-variant",
+                "enabled": true,
+                "strategies": [user_ids("1")],
+                "variants": [variant("from-toggle")]
+            },
+            {
+                "name": "variant-override",
+                "enabled": true,
+                "strategies": [user_ids("1")],
+                "variants": [
+                    variant("from-toggle"),
+                    {
+                        "name": "overridden",
+                        "weight": 0,
+                        "stickiness": "default",
+                        "overrides": [{ "contextName": "userId", "values": ["1"] }]
+                    }
+                ]
+            }
+        ]));
+        engine
+    }
+
+    #[test_case("disabled", EvaluationReason::Disabled, EvaluationReason::Disabled; "disabled")]
+    #[test_case("dependent", EvaluationReason::DependencyUnsatisfied, EvaluationReason::DependencyUnsatisfied; "dependency")]
+    #[test_case("broken", EvaluationReason::Error, EvaluationReason::Error; "compile failure")]
+    #[test_case("unmatched", EvaluationReason::Default, EvaluationReason::Default; "no match")]
+    #[test_case(
+        "no-strategies",
+        EvaluationReason::TargetingMatch { strategy_index: None },
+        EvaluationReason::TargetingMatch { strategy_index: None };
+        "no strategies"
+    )]
+    #[test_case(
+        "second-strategy",
+        EvaluationReason::TargetingMatch { strategy_index: Some(1) },
+        EvaluationReason::TargetingMatch { strategy_index: Some(1) };
+        "matching strategy"
+    )]
+    #[test_case(
+        "strategy-variant",
+        EvaluationReason::TargetingMatch { strategy_index: Some(1) },
+        EvaluationReason::StrategyVariant { strategy_index: 1 };
+        "strategy variant"
+    )]
+    #[test_case(
+        "toggle-variant",
+        EvaluationReason::TargetingMatch { strategy_index: Some(0) },
+        EvaluationReason::ToggleVariant { strategy_index: Some(0) };
+        "toggle variant"
+    )]
+    #[test_case(
+        "variant-override",
+        EvaluationReason::TargetingMatch { strategy_index: Some(0) },
+        EvaluationReason::VariantOverride { strategy_index: Some(0) };
+        "variant override"
+    )]
+    fn resolving_reports_the_reason(
+        name: &str,
+        expected: EvaluationReason,
+        expected_variant: EvaluationReason,
+    ) {
+        let engine = reason_test_engine();
+        let context = user_context("1");
+
+        let resolved = engine.resolve(name, &context, &None).unwrap();
+        assert_eq!(resolved.reason, expected);
+        assert_eq!(resolved.variant.reason, expected_variant);
+        assert_eq!(engine.get_variant(name, &context, &None), resolved.variant);
+        assert_eq!(
+            engine.resolve_all(&context, &None).unwrap()[name].reason,
+            expected
+        );
+    }
+
+    #[test]
+    fn unknown_toggles_report_that_they_were_not_found() {
+        let engine = reason_test_engine();
+
+        let variant = engine.get_variant("missing", &Context::default(), &None);
+
+        assert_eq!(variant.reason, EvaluationReason::FlagNotFound);
+    }
+
+    #[test]
+    fn reasons_serialize_with_their_strategy_index() {
+        let reason = EvaluationReason::StrategyVariant { strategy_index: 2 };
+
+        assert_eq!(
+            serde_json::to_value(reason).unwrap(),
+            serde_json::json!({ "kind": "STRATEGY_VARIANT", "strategyIndex": 2 })
+        );
+    }
+
+    #[test]
+    fn variants_serialized_without_a_reason_still_deserialize() {
+        let variant: ExtendedVariantDef = serde_json::from_value(serde_json::json!({
+            "name": "blue",
+            "enabled": true,
+            "featureEnabled": true
+        }))
+        .unwrap();
+
+        assert_eq!(variant.reason, EvaluationReason::Unknown);
+    }
+
+    #[test]
+    fn dependencies_are_followed_through_every_level() {
+        let (engine, update) = dependency_test_engine(serde_json::json!([
+            { "name": "child", "enabled": true, "dependencies": [{ "feature": "parent" }] },
+            { "name": "parent", "enabled": true, "dependencies": [{ "feature": "grandparent" }] },
+            {
+                "name": "grandparent",
+                "enabled": true,
+                "strategies": [{ "name": "userWithId", "parameters": { "userIds": "1" } }]
+            }
+        ]));
+
+        assert!(update.warnings.is_none());
+        assert!(engine.is_enabled("child", &user_context("1"), &None));
+        assert!(!engine.is_enabled("child", &user_context("2"), &None));
+        assert!(
+            !engine
+                .get_variant("child", &user_context("2"), &None)
+                .feature_enabled
+        );
+    }
+
+    #[test_case("blue", true; "expected variant")]
+    #[test_case("red", false; "other variant")]
+    fn variant_dependencies_are_checked_at_every_level(variant: &str, expected: bool) {
+        let (engine, _) = dependency_test_engine(serde_json::json!([
+            { "name": "child", "enabled": true, "dependencies": [{ "feature": "parent" }] },
+            {
+                "name": "parent",
+                "enabled": true,
+                "dependencies": [{ "feature": "grandparent", "variants": ["blue"] }]
+            },
+            {
+                "name": "grandparent",
+                "enabled": true,
+                "variants": [{ "name": variant, "weight": 1000, "stickiness": "default" }]
+            }
+        ]));
+
+        let resolved = engine.resolve_all(&user_context("1"), &None).unwrap();
+
+        assert_eq!(resolved["parent"].enabled, expected);
+        assert_eq!(resolved["child"].enabled, expected);
+    }
+
+    #[test]
+    fn dependency_cycles_and_missing_parents_are_reported_and_off() {
+        let (engine, update) = dependency_test_engine(serde_json::json!([
+            { "name": "a", "enabled": true, "dependencies": [{ "feature": "b" }] },
+            { "name": "b", "enabled": true, "dependencies": [{ "feature": "a", "enabled": false }] },
+            { "name": "depends-on-cycle", "enabled": true, "dependencies": [{ "feature": "a" }] },
+            { "name": "orphan", "enabled": true, "dependencies": [{ "feature": "missing" }] }
+        ]));
+
+        let mut warned: Vec<_> = update
+            .warnings
+            .unwrap()
+            .into_iter()
+            .map(|warning| warning.toggle_name)
+            .collect();
+        warned.sort();
+        assert_eq!(warned, vec!["a", "b", "orphan"]);
+
+        let resolved = engine.resolve_all(&Context::default(), &None).unwrap();
+        assert!(resolved.values().all(|toggle| !toggle.enabled));
+    }
+
+    #[test]
+    fn deltas_that_close_a_cycle_are_reported() {
+        let (mut engine, _) = dependency_test_engine(serde_json::json!([
+            { "name": "a", "enabled": true, "dependencies": [{ "feature": "b" }] },
+            { "name": "b", "enabled": true }
+        ]));
+        assert!(engine.is_enabled("a", &Context::default(), &None));
+
+        let delta: ClientFeaturesDelta = serde_json::from_value(serde_json::json!({
+            "events": [{
+                "type": "feature-updated",
+                "eventId": 2,
+                "feature": { "name": "b", "enabled": true, "dependencies": [{ "feature": "a" }] }
+            }]
+        }))
+        .unwrap();
+        let update = engine.apply_delta(&delta);
+
+        assert_eq!(update.warnings.unwrap().len(), 2);
+        assert!(!engine.is_enabled("a", &Context::default(), &None));
+        assert!(!engine.is_enabled("b", &Context::default(), &None));
+    }
+
+    #[test]
+    fn parents_are_evaluated_once_per_child_per_call() {
+        struct Counting(Arc<AtomicUsize>);
+
+        impl CustomStrategy for Counting {
+            fn name(&self) -> &str {
+                "counting"
+            }
+
+            fn is_enabled(&self, _: &HashMap<String, String>, _: &EnrichedContext) -> bool {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (mut engine, _) = dependency_test_engine(serde_json::json!([
+            {
+                "name": "diamond",
+                "enabled": true,
+                "dependencies": [{ "feature": "left" }, { "feature": "right" }]
+            },
+            { "name": "left", "enabled": true, "dependencies": [{ "feature": "root" }] },
+            { "name": "right", "enabled": true, "dependencies": [{ "feature": "root" }] },
+            { "name": "root", "enabled": true, "strategies": [{ "name": "counting" }] }
+        ]));
+        engine.register_custom_strategy(Counting(calls.clone()));
+
+        assert!(engine.is_enabled("left", &Context::default(), &None));
+        let single_path = calls.swap(0, Ordering::Relaxed);
+        assert!(engine.is_enabled("diamond", &Context::default(), &None));
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 2 * single_path);
+        engine.resolve("root", &Context::default(), &None).unwrap();
+        let root_itself = calls.swap(0, Ordering::Relaxed);
+
+        // Once for root itself and once under each of its children, however many
+        // paths lead through them
+        engine.resolve_all(&Context::default(), &None).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), root_itself + 2 * single_path);
+    }
+
+    #[test]
+    fn parents_are_evaluated_under_the_name_of_their_child() {
+        let rollout = |group_id: Option<&str>| {
+            let mut parameters = serde_json::json!({ "rollout": "50", "stickiness": "userId" });
+            if let Some(group_id) = group_id {
+                parameters["groupId"] = group_id.into();
+            }
+            serde_json::json!([{ "name": "flexibleRollout", "parameters": parameters }])
+        };
+        let (engine, _) = dependency_test_engine(serde_json::json!([
+            { "name": "parent", "enabled": true, "strategies": rollout(None) },
+            { "name": "child", "enabled": true, "dependencies": [{ "feature": "parent" }] },
+            { "name": "as-child", "enabled": true, "strategies": rollout(Some("child")) }
+        ]));
+
+        let mut differs_from_parent = false;
+        for user_id in 0..100 {
+            let context = user_context(&user_id.to_string());
+            let resolved = engine.resolve_all(&context, &None).unwrap();
+
+            assert_eq!(resolved["child"].enabled, resolved["as-child"].enabled);
+            assert_eq!(
+                engine.is_enabled("child", &context, &None),
+                resolved["as-child"].enabled
+            );
+            differs_from_parent |= resolved["child"].enabled != resolved["parent"].enabled;
+        }
+        assert!(differs_from_parent);
+    }
+
     #[test]
     pub fn strategy_variants_are_selected_over_base_variants_if_present_and_also_when_previous_failing_strategy_has_none(
     ) {
@@ -1905,7 +3435,7 @@ mod test {
             ..Context::default()
         };
 
-        let warnings = engine.take_state(feature_set);
+        let warnings = engine.take_state(feature_set).warnings;
 
         let results = engine.resolve_all(&context, &None);
         let targeted_toggle = results.unwrap().get("toggle1").unwrap().clone();
@@ -1953,7 +3483,7 @@ mod test {
             UpdateMessage::FullResponse(serde_json::from_str(raw_state).unwrap());
         let mut engine = EngineState::default();
 
-        let warnings = engine.take_state(feature_set);
+        let warnings = engine.take_state(feature_set).warnings;
 
         let context = Context {
             user_id: Some("okay".into()),
@@ -1966,6 +3496,94 @@ mod test {
         assert!(warnings.is_none());
     }
 
+    #[test]
+    fn explain_reports_each_strategy_and_dependency() {
+        let raw_state = r#"
+        {
+            "version": 2,
+            "features": [
+                {
+                    "name": "parent",
+                    "enabled": true,
+                    "strategies": [{ "name": "default" }]
+                },
+                {
+                    "name": "child",
+                    "enabled": true,
+                    "dependencies": [{ "feature": "parent" }],
+                    "strategies": [
+                        {
+                            "name": "default",
+                            "constraints": [
+                                {
+                                    "contextName": "userId",
+                                    "operator": "IN",
+                                    "values": ["7"],
+                                    "inverted": false,
+                                    "caseInsensitive": false
+                                }
+                            ]
+                        },
+                        {
+                            "name": "flexibleRollout",
+                            "parameters": {
+                                "groupId": "child",
+                                "rollout": "100",
+                                "stickiness": "userId"
+                            }
+                        }
+                    ],
+                    "variants": [
+                        {
+                            "name": "blue",
+                            "weight": 1000,
+                            "stickiness": "default"
+                        }
+                    ]
+                }
+            ]
+        }
+        "#;
+
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_str(raw_state).unwrap(),
+        ));
+        let context = Context {
+            user_id: Some("8".into()),
+            ..Context::default()
+        };
+
+        let explanation = engine.explain("child", &context, &None).unwrap();
+
+        assert!(explanation.toggle_enabled);
+        assert!(explanation.enabled);
+        assert_eq!(explanation.variant.name, "blue");
+
+        assert_eq!(explanation.dependencies.len(), 1);
+        assert!(explanation.dependencies[0].satisfied);
+        assert_eq!(explanation.dependencies[0].parent_enabled, Some(true));
+
+        assert_eq!(explanation.strategies.len(), 2);
+        assert_eq!(explanation.strategies[0].name, "default");
+        assert!(!explanation.strategies[0].result);
+        assert_eq!(explanation.strategies[1].name, "flexibleRollout");
+        assert!(explanation.strategies[1].result);
+
+        let Some(RuleTrace::Constraint(rollout)) = &explanation.strategies[1].trace else {
+            panic!("Expected the rollout to be a single constraint");
+        };
+        assert!(rollout.rollout.as_ref().unwrap().bucket.is_some());
+    }
+
+    #[test]
+    fn explain_returns_none_for_unknown_toggle() {
+        let engine = EngineState::default();
+        assert!(engine
+            .explain("missing", &Context::default(), &None)
+            .is_none());
+    }
+
     #[test]
     fn get_state_returns_default_when_empty() {
         let engine = EngineState::default();
@@ -2004,7 +3622,7 @@ mod test {
         assert_eq!(state.version, 2);
         assert_eq!(state.features.len(), 1);
         assert_eq!(state.features[0].name, "test-feature");
-        assert_eq!(state.features[0].enabled, true);
+        assert!(state.features[0].enabled);
     }
 
     #[test]
@@ -2012,7 +3630,7 @@ mod test {
         let mut compiled_state = AHashMap::new();
         compiled_state.insert(
             "some-toggle".to_string(),
-            CompiledToggle {
+            Arc::new(CompiledToggle {
                 name: "some-toggle".into(),
                 enabled: true,
                 compiled_strategy: Box::new(|_| true),
@@ -2033,7 +3651,7 @@ mod test {
                     },
                 ],
                 ..CompiledToggle::default()
-            },
+            }),
         );
 
         let state = EngineState {
@@ -2046,4 +3664,163 @@ mod test {
             assert_eq!(variant.name, "full-weight-variant".to_string());
         }
     }
+
+    #[test]
+    fn structured_properties_match_constraints_on_any_of_their_values() {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_value(serde_json::json!({
+                "version": 2,
+                "features": [{
+                    "name": "toggle",
+                    "enabled": true,
+                    "strategies": [{
+                        "name": "default",
+                        "constraints": [
+                            { "contextName": "roles", "operator": "IN", "values": ["admin"] },
+                            { "contextName": "org.team", "operator": "STR_STARTS_WITH", "values": ["co"] }
+                        ]
+                    }]
+                }]
+            }))
+            .unwrap(),
+        ));
+        let context = |roles: serde_json::Value| -> StructuredContext {
+            serde_json::from_value(serde_json::json!({
+                "properties": { "roles": "ignored" },
+                "structuredProperties": { "roles": roles, "org": { "team": "core" } }
+            }))
+            .unwrap()
+        };
+
+        assert!(engine.is_enabled(
+            "toggle",
+            &context(serde_json::json!(["viewer", "admin"])),
+            &None
+        ));
+        assert!(!engine.is_enabled("toggle", &context(serde_json::json!(["viewer"])), &None));
+
+        let resolved = engine
+            .resolve_all(&context(serde_json::json!(["admin"])), &None)
+            .unwrap();
+        assert!(resolved["toggle"].enabled);
+    }
+
+    #[test]
+    fn structured_properties_sit_on_top_of_string_properties() {
+        let mut context = StructuredContext::from(Context {
+            properties: Some(HashMap::from([
+                ("team".to_string(), "core".to_string()),
+                ("roles".to_string(), "admin".to_string()),
+            ])),
+            ..Context::default()
+        });
+        context
+            .structured_properties
+            .insert("roles".into(), PropertyValue::List(vec!["viewer".into()]));
+        let enriched_context = EnrichedContext::from(&context, "toggle", None);
+        let properties = enriched_context.properties.unwrap();
+
+        assert_eq!(properties.get("team"), Some("core"));
+        assert_eq!(properties.get("roles"), None);
+        assert_eq!(
+            properties
+                .get_values("roles")
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec!["viewer"]
+        );
+    }
+
+    #[test]
+    fn variant_stickiness_falls_back_along_a_chain() {
+        let variants = serde_json::json!([
+            { "name": "a", "weight": 250, "stickiness": "deviceId|userId" },
+            { "name": "b", "weight": 250, "stickiness": "deviceId|userId" },
+            { "name": "c", "weight": 250, "stickiness": "deviceId|userId" },
+            { "name": "d", "weight": 250, "stickiness": "deviceId|userId" }
+        ]);
+        let (engine, _) = dependency_test_engine(serde_json::json!([
+            { "name": "toggle", "enabled": true, "variants": variants },
+            {
+                "name": "strategy",
+                "enabled": true,
+                "strategies": [{
+                    "name": "flexibleRollout",
+                    "parameters": { "rollout": "50", "stickiness": "deviceId|userId", "groupId": "strategy" },
+                    "variants": variants
+                }]
+            }
+        ]));
+        let device = |device_id: &str| Context {
+            properties: Some(HashMap::from([("deviceId".into(), device_id.into())])),
+            ..Context::default()
+        };
+
+        for id in (0..50).map(|id| id.to_string()) {
+            for toggle in ["toggle", "strategy"] {
+                assert_eq!(
+                    engine.get_variant(toggle, &device(&id), &None),
+                    engine.get_variant(toggle, &user_context(&id), &None),
+                    "{toggle} for {id}"
+                );
+            }
+        }
+        assert!(
+            engine
+                .get_variant("toggle", &Context::default(), &None)
+                .feature_enabled
+        );
+    }
+
+    #[test]
+    fn resolve_all_agrees_with_single_evaluations_on_typed_constraints() {
+        let constrained = |name: &str, constraint: serde_json::Value| {
+            serde_json::json!({
+                "name": name,
+                "enabled": true,
+                "strategies": [{ "name": "default", "constraints": [constraint] }]
+            })
+        };
+        let (engine, _) = dependency_test_engine(serde_json::json!([
+            constrained("number", serde_json::json!({ "contextName": "userId", "operator": "NUM_GT", "value": "5" })),
+            constrained("fewer", serde_json::json!({ "contextName": "userId", "operator": "NUM_LTE", "value": "5" })),
+            constrained("version", serde_json::json!({ "contextName": "appVersion", "operator": "SEMVER_GT", "value": "1.0.0" })),
+            constrained("date", serde_json::json!({ "contextName": "currentTime", "operator": "DATE_AFTER", "value": "2024-01-01T00:00:00Z" })),
+            constrained("earlier", serde_json::json!({ "contextName": "currentTime", "operator": "DATE_BEFORE", "value": "2024-01-01T00:00:00Z" })),
+            {
+                "name": "address",
+                "enabled": true,
+                "strategies": [{ "name": "remoteAddress", "parameters": { "IPs": "10.0.0.0/8" } }]
+            }
+        ]));
+
+        for (user_id, app_version, current_time, remote_address) in [
+            ("7", "1.2.0", "2025-06-01T00:00:00Z", "10.1.2.3"),
+            ("3", "0.9.0", "2023-06-01T00:00:00Z", "192.168.0.1"),
+            ("seven", "latest", "yesterday", "localhost"),
+        ] {
+            let context = Context {
+                user_id: Some(user_id.into()),
+                current_time: Some(current_time.into()),
+                remote_address: Some(remote_address.into()),
+                properties: Some(HashMap::from([(
+                    "appVersion".to_string(),
+                    app_version.to_string(),
+                )])),
+                ..Context::default()
+            };
+
+            let resolved = engine.resolve_all(&context, &None).unwrap();
+
+            for (name, toggle) in resolved {
+                assert_eq!(
+                    toggle.enabled,
+                    engine.is_enabled(&name, &context, &None),
+                    "{name} with user {user_id}"
+                );
+            }
+        }
+    }
 }
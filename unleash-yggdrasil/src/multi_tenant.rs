@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use unleash_types::client_metrics::MetricBucket;
+
+use crate::custom_strategy::{CustomStrategies, CustomStrategy};
+use crate::strategy_parsing::ConstraintCache;
+use crate::{EngineState, StateUpdate, UpdateMessage};
+
+type TenantSetup = Box<dyn Fn(&str, &mut EngineState) + Send + Sync>;
+
+/// Several independent engines, one per tenant (an environment, an API token or
+/// anything else that receives its own feature state), behind a single handle.
+///
+/// Each tenant has its own compiled state and its own toggle metrics. All tenants
+/// compile through the same constraint cache, so constraints that are identical
+/// across tenants, which is typically the case for shared segments, are only held
+/// in memory once
+pub struct MultiTenantEngine {
+    tenants: HashMap<String, EngineState>,
+    constraints: ConstraintCache,
+    custom_strategies: CustomStrategies,
+    tenant_setup: Option<TenantSetup>,
+    // Where the current metrics window started, tenants created partway through it
+    // report from here so that every tenant's buckets line up
+    metrics_start: DateTime<Utc>,
+}
+
+impl MultiTenantEngine {
+    pub fn new(started: DateTime<Utc>) -> Self {
+        Self {
+            tenants: HashMap::new(),
+            constraints: ConstraintCache::default(),
+            custom_strategies: CustomStrategies::default(),
+            tenant_setup: None,
+            metrics_start: started,
+        }
+    }
+
+    /// Applies an update to a tenant, creating the tenant if this is the first
+    /// update it has received
+    pub fn take_state(&mut self, tenant_id: &str, message: UpdateMessage) -> StateUpdate {
+        let tenant = self
+            .tenants
+            .entry(tenant_id.to_string())
+            .or_insert_with(|| {
+                let mut tenant = EngineState::initial_state(self.metrics_start);
+                tenant.set_custom_strategies(self.custom_strategies.clone(), None);
+                if let Some(setup) = &self.tenant_setup {
+                    setup(tenant_id, &mut tenant);
+                }
+                tenant
+            });
+
+        let update = match message {
+            UpdateMessage::PartialUpdate(delta) => {
+                tenant.apply_delta_with(&delta, Some(&mut self.constraints))
+            }
+            UpdateMessage::FullResponse(state) => {
+                tenant.apply_client_features_with(state, Some(&mut self.constraints))
+            }
+        };
+        self.constraints.prune();
+        update
+    }
+
+    /// Registers a custom strategy for every tenant, including the ones added later
+    pub fn register_custom_strategy(&mut self, strategy: impl CustomStrategy + 'static) {
+        self.custom_strategies.register(Arc::new(strategy));
+        for tenant in self.tenants.values_mut() {
+            tenant
+                .set_custom_strategies(self.custom_strategies.clone(), Some(&mut self.constraints));
+        }
+        self.constraints.prune();
+    }
+
+    /// Configures each new tenant before it compiles its first state, for anything that's
+    /// set per engine such as failure policies or a clock. Tenants that already exist
+    /// are left as they are
+    pub fn set_tenant_setup(
+        &mut self,
+        setup: impl Fn(&str, &mut EngineState) + Send + Sync + 'static,
+    ) {
+        self.tenant_setup = Some(Box::new(setup));
+    }
+
+    pub fn tenant(&self, tenant_id: &str) -> Option<&EngineState> {
+        self.tenants.get(tenant_id)
+    }
+
+    pub fn tenant_ids(&self) -> impl Iterator<Item = &str> {
+        self.tenants.keys().map(String::as_str)
+    }
+
+    pub fn remove_tenant(&mut self, tenant_id: &str) -> Option<EngineState> {
+        let removed = self.tenants.remove(tenant_id);
+        self.constraints.prune();
+        removed
+    }
+
+    /// The number of distinct compiled constraints held across all tenants
+    pub fn compiled_constraint_count(&self) -> usize {
+        self.constraints.len()
+    }
+
+    /// One metrics bucket per tenant, keyed by tenant id. Tenants that have nothing
+    /// to report are left out
+    pub fn get_metrics(&mut self, close_time: DateTime<Utc>) -> HashMap<String, MetricBucket> {
+        self.metrics_start = close_time;
+        self.tenants
+            .iter_mut()
+            .filter_map(|(tenant_id, tenant)| {
+                tenant
+                    .get_metrics(close_time)
+                    .map(|bucket| (tenant_id.clone(), bucket))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "wall-clock")]
+impl Default for MultiTenantEngine {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure_policy::{FailurePolicies, FailurePolicy};
+    use crate::Context;
+    use serde_json::json;
+    use unleash_types::client_features::{ClientFeatures, ClientFeaturesDelta};
+
+    fn state(user_ids: &[&str], toggles: &[&str]) -> UpdateMessage {
+        let features: Vec<_> = toggles
+            .iter()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "enabled": true,
+                    "strategies": [{ "name": "default", "segments": [1] }]
+                })
+            })
+            .collect();
+
+        UpdateMessage::FullResponse(
+            serde_json::from_value::<ClientFeatures>(json!({
+                "version": 2,
+                "features": features,
+                "segments": [{
+                    "id": 1,
+                    "constraints": [{ "contextName": "userId", "operator": "IN", "values": user_ids }]
+                }]
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn user(user_id: &str) -> Context {
+        Context {
+            user_id: Some(user_id.into()),
+            ..Context::default()
+        }
+    }
+
+    #[test]
+    fn tenants_resolve_against_their_own_state() {
+        let mut engine = MultiTenantEngine::default();
+        engine.take_state("production", state(&["1"], &["toggle"]));
+        engine.take_state("development", state(&["2"], &["toggle"]));
+
+        let production = engine.tenant("production").unwrap();
+        let development = engine.tenant("development").unwrap();
+
+        assert!(production.is_enabled("toggle", &user("1"), &None));
+        assert!(!production.is_enabled("toggle", &user("2"), &None));
+        assert!(development.is_enabled("toggle", &user("2"), &None));
+        assert!(engine.tenant("staging").is_none());
+    }
+
+    #[test]
+    fn identical_constraints_are_compiled_once_across_tenants() {
+        let mut engine = MultiTenantEngine::default();
+        engine.take_state("production", state(&["1", "2"], &["a", "b"]));
+        let single_tenant = engine.compiled_constraint_count();
+
+        engine.take_state("development", state(&["1", "2"], &["a", "b", "c"]));
+        assert_eq!(engine.compiled_constraint_count(), single_tenant);
+
+        engine.take_state("staging", state(&["3"], &["a"]));
+        assert_eq!(engine.compiled_constraint_count(), single_tenant + 1);
+    }
+
+    #[test]
+    fn constraints_are_released_once_no_tenant_uses_them() {
+        let mut engine = MultiTenantEngine::default();
+        engine.take_state("production", state(&["1"], &["toggle"]));
+        engine.take_state("development", state(&["2"], &["toggle"]));
+        let both = engine.compiled_constraint_count();
+
+        engine.take_state("development", state(&["1"], &["toggle"]));
+        assert!(engine.compiled_constraint_count() < both);
+
+        engine.remove_tenant("production");
+        engine.remove_tenant("development");
+        assert_eq!(engine.compiled_constraint_count(), 0);
+    }
+
+    #[test]
+    fn deltas_only_apply_to_their_tenant() {
+        let mut engine = MultiTenantEngine::default();
+        engine.take_state("production", state(&["1"], &["toggle"]));
+        engine.take_state("development", state(&["1"], &["toggle"]));
+
+        let delta: ClientFeaturesDelta = serde_json::from_value(json!({
+            "events": [{
+                "type": "segment-updated",
+                "eventId": 2,
+                "segment": {
+                    "id": 1,
+                    "constraints": [{ "contextName": "userId", "operator": "IN", "values": ["2"] }]
+                }
+            }]
+        }))
+        .unwrap();
+        let update = engine.take_state("development", UpdateMessage::PartialUpdate(delta));
+
+        assert_eq!(update.changes.modified.len(), 1);
+        assert!(engine
+            .tenant("production")
+            .unwrap()
+            .is_enabled("toggle", &user("1"), &None));
+        assert!(engine
+            .tenant("development")
+            .unwrap()
+            .is_enabled("toggle", &user("2"), &None));
+    }
+
+    #[test]
+    fn new_tenants_are_set_up_before_their_first_compile() {
+        let mut engine = MultiTenantEngine::default();
+        engine.set_tenant_setup(|tenant_id, tenant| {
+            if tenant_id == "production" {
+                let mut policies = FailurePolicies::default();
+                policies.set_for_toggle("toggle", FailurePolicy::AlwaysOn);
+                tenant.set_failure_policies(policies);
+            }
+        });
+        let broken = || {
+            UpdateMessage::FullResponse(
+                serde_json::from_value(json!({
+                    "version": 2,
+                    "features": [{
+                        "name": "toggle",
+                        "enabled": true,
+                        "strategies": [{
+                            "name": "default",
+                            "constraints": [{ "contextName": "userId", "operator": "NOT_AN_OPERATOR", "values": ["1"] }]
+                        }]
+                    }]
+                }))
+                .unwrap(),
+            )
+        };
+
+        engine.take_state("production", broken());
+        engine.take_state("development", broken());
+
+        assert!(engine
+            .tenant("production")
+            .unwrap()
+            .is_enabled("toggle", &user("2"), &None));
+        assert!(!engine
+            .tenant("development")
+            .unwrap()
+            .is_enabled("toggle", &user("2"), &None));
+    }
+
+    #[test]
+    fn metrics_are_reported_per_tenant() {
+        let mut engine = MultiTenantEngine::default();
+        engine.take_state("production", state(&["1"], &["toggle"]));
+        engine.take_state("development", state(&["1"], &["toggle"]));
+        engine.take_state("staging", state(&["1"], &["toggle"]));
+
+        let production = engine.tenant("production").unwrap();
+        production.count_toggle("toggle", true);
+        production.count_toggle("toggle", true);
+        engine
+            .tenant("development")
+            .unwrap()
+            .count_toggle("toggle", false);
+
+        let metrics = engine.get_metrics(Utc::now());
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics["production"].toggles["toggle"].yes, 2);
+        assert_eq!(metrics["production"].toggles["toggle"].no, 0);
+        assert_eq!(metrics["development"].toggles["toggle"].no, 1);
+        assert!(engine.get_metrics(Utc::now()).is_empty());
+    }
+
+    #[test]
+    fn tenants_added_later_report_from_the_current_metrics_window() {
+        let mut engine = MultiTenantEngine::default();
+        engine.take_state("production", state(&["1"], &["toggle"]));
+        engine
+            .tenant("production")
+            .unwrap()
+            .count_toggle("toggle", true);
+        let close_time = Utc::now();
+        engine.get_metrics(close_time);
+
+        engine.take_state("development", state(&["1"], &["toggle"]));
+        for tenant in ["production", "development"] {
+            engine.tenant(tenant).unwrap().count_toggle("toggle", true);
+        }
+        let metrics = engine.get_metrics(Utc::now());
+
+        assert_eq!(metrics["production"].start, close_time);
+        assert_eq!(metrics["development"].start, close_time);
+    }
+}
@@ -0,0 +1,293 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use unleash_types::client_features::Payload;
+
+use crate::{ExtendedVariantDef, VariantDef};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadType {
+    Json,
+    Number,
+    String,
+    Csv,
+}
+
+impl FromStr for PayloadType {
+    type Err = PayloadError;
+
+    fn from_str(payload_type: &str) -> Result<Self, Self::Err> {
+        match payload_type {
+            "json" => Ok(PayloadType::Json),
+            "number" => Ok(PayloadType::Number),
+            "string" => Ok(PayloadType::String),
+            "csv" => Ok(PayloadType::Csv),
+            _ => Err(PayloadError::UnknownType(payload_type.to_string())),
+        }
+    }
+}
+
+impl Display for PayloadType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let payload_type = match self {
+            PayloadType::Json => "json",
+            PayloadType::Number => "number",
+            PayloadType::String => "string",
+            PayloadType::Csv => "csv",
+        };
+        f.write_str(payload_type)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PayloadError {
+    // The variant has no payload, which includes the disabled variant
+    Missing,
+    UnknownType(String),
+    WrongType {
+        expected: PayloadType,
+        actual: PayloadType,
+    },
+    Invalid {
+        payload_type: PayloadType,
+        message: String,
+    },
+}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::Missing => write!(f, "variant has no payload"),
+            PayloadError::UnknownType(payload_type) => {
+                write!(f, "unknown payload type {payload_type}")
+            }
+            PayloadError::WrongType { expected, actual } => {
+                write!(
+                    f,
+                    "expected a {expected} payload but found a {actual} payload"
+                )
+            }
+            PayloadError::Invalid {
+                payload_type,
+                message,
+            } => write!(f, "invalid {payload_type} payload: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+/// Typed access to a variant's payload. Payloads are checked when the state is
+/// compiled and any that can't be read as their declared type are reported as
+/// warnings then, so these only fail for payloads that were already warned about or
+/// when the payload is read as a different type than it was declared as
+pub trait TypedPayload {
+    fn raw_payload(&self) -> Option<&Payload>;
+
+    /// Deserializes a json payload into any type serde can produce
+    fn payload_json<T: DeserializeOwned>(&self) -> Result<T, PayloadError> {
+        let value = typed_value(self.raw_payload(), PayloadType::Json)?;
+        serde_json::from_str(value).map_err(|e| invalid(PayloadType::Json, e))
+    }
+
+    fn payload_number(&self) -> Result<f64, PayloadError> {
+        parse_number(typed_value(self.raw_payload(), PayloadType::Number)?)
+    }
+
+    fn payload_string(&self) -> Result<&str, PayloadError> {
+        typed_value(self.raw_payload(), PayloadType::String)
+    }
+
+    /// The comma separated entries of a csv payload, with surrounding whitespace
+    /// trimmed. An empty payload has no entries
+    fn payload_csv(&self) -> Result<Vec<&str>, PayloadError> {
+        let value = typed_value(self.raw_payload(), PayloadType::Csv)?;
+        if value.trim().is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(value.split(',').map(str::trim).collect())
+    }
+}
+
+impl TypedPayload for VariantDef {
+    fn raw_payload(&self) -> Option<&Payload> {
+        self.payload.as_ref()
+    }
+}
+
+impl TypedPayload for ExtendedVariantDef {
+    fn raw_payload(&self) -> Option<&Payload> {
+        self.payload.as_ref()
+    }
+}
+
+/// Checks that a payload can be read as the type it declares
+pub fn validate_payload(payload: &Payload) -> Result<(), PayloadError> {
+    match payload.payload_type.parse()? {
+        PayloadType::Json => serde_json::from_str::<serde_json::Value>(&payload.value)
+            .map(|_| ())
+            .map_err(|e| invalid(PayloadType::Json, e)),
+        PayloadType::Number => parse_number(&payload.value).map(|_| ()),
+        PayloadType::String | PayloadType::Csv => Ok(()),
+    }
+}
+
+fn typed_value(payload: Option<&Payload>, expected: PayloadType) -> Result<&str, PayloadError> {
+    let payload = payload.ok_or(PayloadError::Missing)?;
+    let actual = payload.payload_type.parse()?;
+    if actual != expected {
+        return Err(PayloadError::WrongType { expected, actual });
+    }
+    Ok(&payload.value)
+}
+
+fn parse_number(value: &str) -> Result<f64, PayloadError> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+        .ok_or_else(|| invalid(PayloadType::Number, format!("{value} is not a number")))
+}
+
+fn invalid(payload_type: PayloadType, message: impl Display) -> PayloadError {
+    PayloadError::Invalid {
+        payload_type,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_state;
+    use serde::Deserialize;
+    use test_case::test_case;
+    use unleash_types::client_features::ClientFeatures;
+
+    fn variant(payload_type: &str, value: &str) -> VariantDef {
+        VariantDef {
+            name: "variant".into(),
+            payload: Some(Payload {
+                payload_type: payload_type.into(),
+                value: value.into(),
+            }),
+            enabled: true,
+        }
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Banner {
+        color: String,
+        size: u32,
+    }
+
+    #[test]
+    fn json_payloads_deserialize_into_caller_types() {
+        let variant = variant("json", r#"{ "color": "blue", "size": 3 }"#);
+
+        assert_eq!(
+            variant.payload_json::<Banner>().unwrap(),
+            Banner {
+                color: "blue".into(),
+                size: 3
+            }
+        );
+        assert!(matches!(
+            variant.payload_json::<Vec<u32>>(),
+            Err(PayloadError::Invalid {
+                payload_type: PayloadType::Json,
+                ..
+            })
+        ));
+    }
+
+    #[test_case("42", 42.0)]
+    #[test_case(" -1.5 ", -1.5)]
+    #[test_case("1e3", 1000.0)]
+    fn number_payloads_parse(value: &str, expected: f64) {
+        assert_eq!(variant("number", value).payload_number().unwrap(), expected);
+    }
+
+    #[test_case("many")]
+    #[test_case("")]
+    #[test_case("1e400"; "out of range")]
+    #[test_case("NaN")]
+    fn invalid_number_payloads_are_rejected(value: &str) {
+        assert!(variant("number", value).payload_number().is_err());
+        assert!(validate_payload(variant("number", value).payload.as_ref().unwrap()).is_err());
+    }
+
+    #[test_case("a,b,c", &["a", "b", "c"])]
+    #[test_case(" a , b ", &["a", "b"])]
+    #[test_case("single", &["single"])]
+    #[test_case("", &[])]
+    fn csv_payloads_split_on_commas(value: &str, expected: &[&str]) {
+        assert_eq!(variant("csv", value).payload_csv().unwrap(), expected);
+    }
+
+    #[test]
+    fn payloads_are_only_read_as_their_declared_type() {
+        let variant = variant("string", "42");
+
+        assert_eq!(variant.payload_string().unwrap(), "42");
+        assert_eq!(
+            variant.payload_number(),
+            Err(PayloadError::WrongType {
+                expected: PayloadType::Number,
+                actual: PayloadType::String
+            })
+        );
+        assert_eq!(
+            VariantDef::default().payload_string(),
+            Err(PayloadError::Missing)
+        );
+        assert_eq!(
+            self::variant("yaml", "a: b").payload_string(),
+            Err(PayloadError::UnknownType("yaml".into()))
+        );
+    }
+
+    #[test]
+    fn invalid_payloads_are_reported_when_the_state_is_compiled() {
+        let state: ClientFeatures = serde_json::from_value(serde_json::json!({
+            "version": 2,
+            "features": [{
+                "name": "toggle",
+                "enabled": true,
+                "strategies": [{
+                    "name": "default",
+                    "variants": [{
+                        "name": "strategy-variant",
+                        "weight": 1000,
+                        "payload": { "type": "json", "value": "{" }
+                    }]
+                }],
+                "variants": [
+                    { "name": "valid", "weight": 500, "payload": { "type": "number", "value": "1" } },
+                    { "name": "not-a-number", "weight": 500, "payload": { "type": "number", "value": "one" } }
+                ]
+            }]
+        }))
+        .unwrap();
+
+        let (_, warnings) = compile_state(&state);
+
+        let messages: Vec<_> = warnings
+            .iter()
+            .map(|warning| warning.message.as_str())
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("Variant not-a-number"));
+        assert!(messages[1].starts_with("Variant strategy-variant"));
+    }
+
+    #[test]
+    fn extended_variants_expose_the_same_accessors() {
+        let variant =
+            variant("number", "7").to_enriched_response(true, crate::EvaluationReason::Default);
+
+        assert_eq!(variant.payload_number().unwrap(), 7.0);
+    }
+}
@@ -0,0 +1,118 @@
+use std::ops::RangeInclusive;
+use std::sync::{Mutex, PoisonError};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Where random stickiness, random constraints and variants without a stickiness
+/// value get their numbers from. Engines use thread local randomness unless they're
+/// given another source
+pub trait RandomSource: Send + Sync {
+    /// A uniformly distributed number within `range`
+    fn random_range(&self, range: RangeInclusive<u32>) -> u32;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ThreadRandom;
+
+impl RandomSource for ThreadRandom {
+    fn random_range(&self, range: RangeInclusive<u32>) -> u32 {
+        rand::rng().random_range(range)
+    }
+}
+
+/// The same sequence of numbers every time for the same seed, so that tests can
+/// reproduce random evaluations
+#[derive(Debug)]
+pub struct SeededRandom {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn random_range(&self, range: RangeInclusive<u32>) -> u32 {
+        self.rng
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .random_range(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, EngineState, UpdateMessage};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn engine(seed: u64) -> EngineState {
+        let mut engine = EngineState::default();
+        engine.take_state(UpdateMessage::FullResponse(
+            serde_json::from_value(json!({
+                "version": 2,
+                "features": [
+                    {
+                        "name": "flexible",
+                        "enabled": true,
+                        "strategies": [{
+                            "name": "flexibleRollout",
+                            "parameters": { "rollout": "50", "stickiness": "random", "groupId": "flexible" }
+                        }]
+                    },
+                    {
+                        "name": "gradual",
+                        "enabled": true,
+                        "strategies": [{ "name": "gradualRolloutRandom", "parameters": { "percentage": "50" } }]
+                    },
+                    {
+                        "name": "variants",
+                        "enabled": true,
+                        "variants": [
+                            { "name": "a", "weight": 500, "stickiness": "default" },
+                            { "name": "b", "weight": 500, "stickiness": "default" }
+                        ]
+                    }
+                ]
+            }))
+            .unwrap(),
+        ));
+        engine.set_random_source(Arc::new(SeededRandom::new(seed)));
+        engine
+    }
+
+    // Evaluates every random toggle enough times to tell apart different sequences
+    fn outcomes(engine: &EngineState) -> Vec<String> {
+        let context = Context::default();
+        (0..32)
+            .flat_map(|_| {
+                [
+                    engine.is_enabled("flexible", &context, &None).to_string(),
+                    engine.is_enabled("gradual", &context, &None).to_string(),
+                    engine.get_variant("variants", &context, &None).name,
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeded_engines_reproduce_random_evaluations() {
+        assert_eq!(outcomes(&engine(7)), outcomes(&engine(7)));
+        assert_ne!(outcomes(&engine(7)), outcomes(&engine(8)));
+    }
+
+    #[test]
+    fn seeded_random_evaluations_still_spread_out() {
+        let outcomes = outcomes(&engine(7));
+
+        for outcome in ["true", "false", "a", "b"] {
+            assert!(outcomes.iter().any(|value| value == outcome), "{outcome}");
+        }
+    }
+}
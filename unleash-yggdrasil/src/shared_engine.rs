@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use unleash_types::client_features::{ClientFeatures, ClientFeaturesDelta};
+use unleash_types::client_metrics::MetricBucket;
+
+use crate::{
+    ContextSource, EngineState, ExtendedVariantDef, ResolvedToggle, StateUpdate, UpdateMessage,
+};
+
+/// An engine that can be shared between threads without wrapping it in a lock.
+///
+/// Updates compile the new state off to the side and then atomically swap it in, so
+/// readers always see a complete snapshot and never wait on a compile. Every snapshot
+/// records into the same toggle and impact metrics, so nothing is lost across swaps.
+/// Anything not exposed directly here is available on a snapshot
+pub struct SharedEngine {
+    current: ArcSwap<EngineState>,
+    // Only writers take this, so that two concurrent updates can't both build on the
+    // same snapshot and drop each other's changes
+    update_lock: Mutex<()>,
+    toggle_metrics_start: Mutex<DateTime<Utc>>,
+}
+
+impl SharedEngine {
+    pub fn new(engine: EngineState) -> Self {
+        Self {
+            toggle_metrics_start: Mutex::new(engine.toggle_metrics_start),
+            current: ArcSwap::from_pointee(engine),
+            update_lock: Mutex::new(()),
+        }
+    }
+
+    /// The current compiled state. This stays valid and unchanged for as long as it's
+    /// held, even if updates are applied in the meantime
+    pub fn snapshot(&self) -> Arc<EngineState> {
+        self.current.load_full()
+    }
+
+    pub fn take_state(&self, message: UpdateMessage) -> StateUpdate {
+        match message {
+            UpdateMessage::PartialUpdate(delta) => self.apply_delta(&delta),
+            UpdateMessage::FullResponse(state) => self.apply_client_features(state),
+        }
+    }
+
+    pub fn apply_client_features(&self, toggles: ClientFeatures) -> StateUpdate {
+        let _update = self
+            .update_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current = self.current.load();
+
+        let (compiled_state, update) = current.compile_client_features(&toggles, None);
+        self.current
+            .store(Arc::new(current.successor(Some(compiled_state), toggles)));
+        update
+    }
+
+    pub fn apply_delta(&self, delta: &ClientFeaturesDelta) -> StateUpdate {
+        let _update = self
+            .update_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current = self.current.load();
+
+        let mut next = current.successor(
+            current.compiled_state.clone(),
+            current.previous_state.clone(),
+        );
+        let update = next.apply_delta(delta);
+        self.current.store(Arc::new(next));
+        update
+    }
+
+    pub fn get_state(&self) -> ClientFeatures {
+        self.current.load().get_state()
+    }
+
+    pub fn is_enabled(
+        &self,
+        name: &str,
+        context: &impl ContextSource,
+        external_values: &Option<HashMap<String, bool>>,
+    ) -> bool {
+        self.current
+            .load()
+            .is_enabled(name, context, external_values)
+    }
+
+    pub fn get_variant(
+        &self,
+        name: &str,
+        context: &impl ContextSource,
+        external_values: &Option<HashMap<String, bool>>,
+    ) -> ExtendedVariantDef {
+        self.current
+            .load()
+            .get_variant(name, context, external_values)
+    }
+
+    pub fn resolve(
+        &self,
+        name: &str,
+        context: &impl ContextSource,
+        external_values: &Option<HashMap<String, bool>>,
+    ) -> Option<ResolvedToggle> {
+        self.current.load().resolve(name, context, external_values)
+    }
+
+    pub fn resolve_all(
+        &self,
+        context: &impl ContextSource,
+        external_values: &Option<HashMap<String, bool>>,
+    ) -> Option<HashMap<String, ResolvedToggle>> {
+        self.current.load().resolve_all(context, external_values)
+    }
+
+    pub fn count_toggle(&self, name: &str, enabled: bool) {
+        self.current.load().count_toggle(name, enabled);
+    }
+
+    pub fn count_variant(&self, toggle_name: &str, variant: &str) {
+        self.current.load().count_variant(toggle_name, variant);
+    }
+
+    pub fn get_metrics(&self, close_time: DateTime<Utc>) -> Option<MetricBucket> {
+        let mut start = self
+            .toggle_metrics_start
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let metrics = self.current.load().drain_toggle_metrics();
+        if metrics.is_empty() {
+            return None;
+        }
+
+        let bucket = MetricBucket {
+            toggles: metrics,
+            start: *start,
+            stop: close_time,
+        };
+        *start = close_time;
+        Some(bucket)
+    }
+}
+
+impl From<EngineState> for SharedEngine {
+    fn from(engine: EngineState) -> Self {
+        SharedEngine::new(engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+    use crate::impact_metrics::MetricOptions;
+    use crate::Context;
+
+    fn state_with(enabled: bool) -> ClientFeatures {
+        serde_json::from_value(serde_json::json!({
+            "version": 2,
+            "features": [{
+                "name": "toggle",
+                "enabled": enabled,
+                "strategies": [{ "name": "default" }]
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn engine_with(enabled: bool) -> SharedEngine {
+        let engine = SharedEngine::new(EngineState::default());
+        engine.apply_client_features(state_with(enabled));
+        engine
+    }
+
+    #[test]
+    fn shared_engine_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedEngine>();
+    }
+
+    #[test]
+    fn snapshots_are_unaffected_by_later_updates() {
+        let engine = engine_with(true);
+        let snapshot = engine.snapshot();
+
+        engine.apply_client_features(state_with(false));
+
+        assert!(snapshot.is_enabled("toggle", &Context::default(), &None));
+        assert!(!engine.is_enabled("toggle", &Context::default(), &None));
+    }
+
+    #[test]
+    fn deltas_build_on_the_current_snapshot() {
+        let engine = engine_with(true);
+        let delta: ClientFeaturesDelta = serde_json::from_value(serde_json::json!({
+            "events": [{
+                "type": "feature-updated",
+                "eventId": 2,
+                "feature": { "name": "other", "enabled": true, "strategies": [] }
+            }]
+        }))
+        .unwrap();
+
+        let update = engine.apply_delta(&delta);
+
+        assert_eq!(update.changes.added, vec!["other"]);
+        assert!(engine.is_enabled("toggle", &Context::default(), &None));
+        assert!(engine.is_enabled("other", &Context::default(), &None));
+    }
+
+    #[test]
+    fn deltas_share_the_toggles_they_leave_alone() {
+        let engine = engine_with(true);
+        let before = engine.snapshot();
+        let delta: ClientFeaturesDelta = serde_json::from_value(serde_json::json!({
+            "events": [{
+                "type": "feature-updated",
+                "eventId": 2,
+                "feature": { "name": "other", "enabled": true, "strategies": [] }
+            }]
+        }))
+        .unwrap();
+
+        engine.apply_delta(&delta);
+
+        let toggle =
+            |engine: &EngineState| engine.compiled_state.as_ref().unwrap()["toggle"].clone();
+        assert!(Arc::ptr_eq(&toggle(&before), &toggle(&engine.snapshot())));
+    }
+
+    #[test]
+    fn readers_keep_resolving_while_updates_are_applied() {
+        let engine = engine_with(true);
+        let done = AtomicBool::new(false);
+        let reads = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let resolved = engine.resolve_all(&Context::default(), &None).unwrap();
+                        // Every read sees one complete state or the other
+                        assert_eq!(resolved.len(), 1);
+                        engine.count_toggle("toggle", resolved["toggle"].enabled);
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+
+            for update in 0..50 {
+                engine.apply_client_features(state_with(update % 2 == 0));
+            }
+            while reads.load(Ordering::Relaxed) == 0 {
+                thread::yield_now();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        let metrics = engine.get_metrics(Utc::now()).unwrap();
+        let stats = &metrics.toggles["toggle"];
+        assert_eq!(
+            (stats.yes + stats.no) as usize,
+            reads.load(Ordering::Relaxed)
+        );
+    }
+
+    #[test]
+    fn metrics_are_kept_across_swaps() {
+        let engine = engine_with(true);
+        engine.count_toggle("toggle", true);
+        engine.count_variant("toggle", "disabled");
+        engine
+            .snapshot()
+            .define_counter(MetricOptions::new("requests", "requests served"));
+
+        engine.apply_client_features(state_with(false));
+        engine.count_toggle("toggle", false);
+        engine.snapshot().inc_counter("requests");
+
+        let metrics = engine.get_metrics(Utc::now()).unwrap();
+        let stats = &metrics.toggles["toggle"];
+        assert_eq!((stats.yes, stats.no), (1, 1));
+        assert_eq!(stats.variants["disabled"], 1);
+        assert!(engine.get_metrics(Utc::now()).is_none());
+
+        let impact_metrics = engine.snapshot().collect_impact_metrics();
+        assert_eq!(impact_metrics.len(), 1);
+        assert_eq!(impact_metrics[0].name, "requests");
+    }
+
+    #[test]
+    fn metrics_buckets_follow_on_from_each_other() {
+        let engine = engine_with(true);
+        let first_close = Utc::now();
+
+        engine.count_toggle("toggle", true);
+        let first = engine.get_metrics(first_close).unwrap();
+        engine.apply_client_features(state_with(false));
+        engine.count_toggle("toggle", false);
+        let second = engine.get_metrics(Utc::now()).unwrap();
+
+        assert_eq!(first.stop, second.start);
+    }
+}
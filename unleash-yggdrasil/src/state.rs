@@ -1,13 +1,104 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, net::IpAddr};
+
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
 use unleash_types::client_features::Context;
 
+use crate::clock::Clock;
+use crate::random_source::RandomSource;
+use crate::strategy_ast::ContextField;
+
 pub type PropertiesCow<'a> = HashMap<Cow<'a, str>, Cow<'a, str>>;
 pub type ExternalResultsCow<'a> = HashMap<Cow<'a, str>, bool>;
 
+/// A context property that can hold more than a single string. Nested objects are
+/// addressed by dotted path, so `{"org": {"team": "core"}}` has an `org.team` property
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    String(String),
+    List(Vec<String>),
+    Object(HashMap<String, PropertyValue>),
+}
+
+pub type StructuredProperties = HashMap<String, PropertyValue>;
+
+/// A context whose properties can hold lists and objects, which `Context` can't. These
+/// are looked up before the context's own string properties, so a structured property
+/// hides a string property of the same name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructuredContext {
+    #[serde(flatten)]
+    pub context: Context,
+    #[serde(default)]
+    pub structured_properties: StructuredProperties,
+}
+
+impl From<Context> for StructuredContext {
+    fn from(context: Context) -> Self {
+        StructuredContext {
+            context,
+            structured_properties: StructuredProperties::new(),
+        }
+    }
+}
+
+/// Anything that toggles can be evaluated against
+pub trait ContextSource {
+    fn context(&self) -> &Context;
+
+    fn properties(&self) -> Option<PropertiesRef<'_>>;
+}
+
+impl ContextSource for Context {
+    fn context(&self) -> &Context {
+        self
+    }
+
+    fn properties(&self) -> Option<PropertiesRef<'_>> {
+        self.properties.as_ref().map(PropertiesRef::Strings)
+    }
+}
+
+impl ContextSource for StructuredContext {
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn properties(&self) -> Option<PropertiesRef<'_>> {
+        Some(match &self.context.properties {
+            Some(strings) => PropertiesRef::Layered(&self.structured_properties, strings),
+            None => PropertiesRef::Structured(&self.structured_properties),
+        })
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum PropertiesRef<'a> {
     Strings(&'a HashMap<String, String>),
     Cows(&'a PropertiesCow<'a>),
+    Structured(&'a StructuredProperties),
+    // Structured properties with string properties underneath them
+    Layered(&'a StructuredProperties, &'a HashMap<String, String>),
+}
+
+/// The value of a context field. Only structured properties can hold more than one
+#[derive(Clone, Debug)]
+pub enum ContextValues<'a> {
+    One(Cow<'a, str>),
+    Many(&'a [String]),
+}
+
+impl ContextValues<'_> {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let (one, many): (Option<&str>, &[String]) = match self {
+            ContextValues::One(value) => (Some(value), &[]),
+            ContextValues::Many(values) => (None, values),
+        };
+        one.into_iter().chain(many.iter().map(String::as_str))
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -17,14 +108,64 @@ pub enum ExternalResultsRef<'a> {
 }
 
 impl<'a> PropertiesRef<'a> {
+    /// A property with a single value. Lists and objects have no single value, so
+    /// anything that needs exactly one, like stickiness, treats them as missing
     pub fn get(&self, key: &str) -> Option<&'a str> {
+        if let Some(value) = self.structured(key) {
+            return match value {
+                PropertyValue::String(value) => Some(value),
+                PropertyValue::List(_) | PropertyValue::Object(_) => None,
+            };
+        }
         match self {
-            PropertiesRef::Strings(m) => m.get(key).map(|v| v.as_str()),
+            PropertiesRef::Strings(m) | PropertiesRef::Layered(_, m) => {
+                m.get(key).map(|v| v.as_str())
+            }
             PropertiesRef::Cows(m) => m.get(key).map(|v| v.as_ref()),
+            PropertiesRef::Structured(_) => None,
+        }
+    }
+
+    pub fn get_values(&self, key: &str) -> Option<ContextValues<'a>> {
+        match self.structured(key) {
+            Some(PropertyValue::String(value)) => Some(ContextValues::One(Cow::Borrowed(value))),
+            Some(PropertyValue::List(values)) => Some(ContextValues::Many(values)),
+            Some(PropertyValue::Object(_)) => None,
+            None => self
+                .get(key)
+                .map(|value| ContextValues::One(Cow::Borrowed(value))),
+        }
+    }
+
+    fn structured(&self, key: &str) -> Option<&'a PropertyValue> {
+        match self {
+            PropertiesRef::Structured(m) | PropertiesRef::Layered(m, _) => {
+                structured_property(m, key)
+            }
+            PropertiesRef::Strings(_) | PropertiesRef::Cows(_) => None,
         }
     }
 }
 
+// Keys that contain dots are matched as they are before being treated as a path
+fn structured_property<'a>(
+    properties: &'a StructuredProperties,
+    key: &str,
+) -> Option<&'a PropertyValue> {
+    if let Some(value) = properties.get(key) {
+        return Some(value);
+    }
+    let mut segments = key.split('.');
+    let mut value = properties.get(segments.next()?)?;
+    for segment in segments {
+        let PropertyValue::Object(object) = value else {
+            return None;
+        };
+        value = object.get(segment)?;
+    }
+    Some(value)
+}
+
 impl<'a> ExternalResultsRef<'a> {
     pub fn get(&self, key: &str) -> Option<bool> {
         match self {
@@ -34,6 +175,7 @@ impl<'a> ExternalResultsRef<'a> {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct EnrichedContext<'a> {
     pub user_id: Option<&'a str>,
     pub session_id: Option<&'a str>,
@@ -45,14 +187,18 @@ pub struct EnrichedContext<'a> {
     pub external_results: Option<ExternalResultsRef<'a>>,
     pub toggle_name: &'a str,
     pub runtime_hostname: Option<&'a str>,
+    pub parsed_values: Option<&'a ParsedValues>,
+    pub clock: Option<&'a dyn Clock>,
+    pub random_source: Option<&'a dyn RandomSource>,
 }
 
 impl<'a> EnrichedContext<'a> {
     pub fn from(
-        context: &'a Context,
+        source: &'a (impl ContextSource + ?Sized),
         toggle_name: &'a str,
         external_results: Option<&'a HashMap<String, bool>>,
     ) -> Self {
+        let context = source.context();
         EnrichedContext {
             user_id: context.user_id.as_deref(),
             session_id: context.session_id.as_deref(),
@@ -60,10 +206,13 @@ impl<'a> EnrichedContext<'a> {
             app_name: context.app_name.as_deref(),
             current_time: context.current_time.as_deref(),
             remote_address: context.remote_address.as_deref(),
-            properties: context.properties.as_ref().map(PropertiesRef::Strings),
+            properties: source.properties(),
             external_results: external_results.map(ExternalResultsRef::Strings),
             toggle_name,
             runtime_hostname: None,
+            parsed_values: None,
+            clock: None,
+            random_source: None,
         }
     }
 }
@@ -81,11 +230,97 @@ impl<'a> EnrichedContext<'a> {
             external_results: self.external_results,
             toggle_name,
             runtime_hostname: self.runtime_hostname,
+            parsed_values: self.parsed_values,
+            clock: self.clock,
+            random_source: self.random_source,
+        }
+    }
+}
+
+/// A context value type that constraints compare against
+pub(crate) trait ParsedValue: Clone + Sized {
+    fn parse(value: &str) -> Option<Self>;
+
+    fn slot(field: &mut ParsedField) -> &mut Option<Option<Self>>;
+}
+
+impl ParsedValue for f64 {
+    fn parse(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+
+    fn slot(field: &mut ParsedField) -> &mut Option<Option<Self>> {
+        &mut field.number
+    }
+}
+
+impl ParsedValue for Version {
+    fn parse(value: &str) -> Option<Self> {
+        Version::parse(value).ok()
+    }
+
+    fn slot(field: &mut ParsedField) -> &mut Option<Option<Self>> {
+        &mut field.version
+    }
+}
+
+impl ParsedValue for DateTime<Utc> {
+    fn parse(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+
+    fn slot(field: &mut ParsedField) -> &mut Option<Option<Self>> {
+        &mut field.date
+    }
+}
+
+impl ParsedValue for IpAddr {
+    fn parse(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+
+    fn slot(field: &mut ParsedField) -> &mut Option<Option<Self>> {
+        &mut field.ip
+    }
+}
+
+// Each slot is None until that type is first asked for, after which it holds the
+// parsed value, or None again if the field was missing or didn't parse
+#[derive(Default)]
+pub(crate) struct ParsedField {
+    number: Option<Option<f64>>,
+    version: Option<Option<Version>>,
+    date: Option<Option<DateTime<Utc>>>,
+    ip: Option<Option<IpAddr>>,
+}
+
+/// Typed views of the fields of a context, parsed the first time a constraint asks
+/// for them. Evaluations that share one only parse each field once, however many
+/// toggles compare against it, so it must only be shared between evaluations of the
+/// same context. A single call evaluates on a single thread, so this isn't `Sync`
+#[derive(Default)]
+pub struct ParsedValues {
+    fields: RefCell<HashMap<ContextField, ParsedField>>,
+}
+
+impl ParsedValues {
+    pub(crate) fn get_or_parse<T: ParsedValue>(
+        &self,
+        field: &ContextField,
+        parse: impl FnOnce() -> Option<T>,
+    ) -> Option<T> {
+        if let Some(Some(value)) = self.fields.borrow_mut().get_mut(field).map(T::slot) {
+            return value.clone();
         }
+        // Parsed outside of the borrow, so nothing a parser does can trip over it
+        let value = parse();
+        let mut fields = self.fields.borrow_mut();
+        *T::slot(fields.entry(field.clone()).or_default()) = Some(value.clone());
+        value
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SdkError {
     StrategyEvaluationError,
     StrategyParseError(String),
@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Not;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use semver::{Version, VersionReq};
+use serde::{Serialize, Serializer};
+
+use crate::custom_strategy::CustomStrategy;
+use crate::state::SdkError;
+use crate::strategy_upgrade::escape_string;
+
+// A typed representation of the strategy DSL in strategy_grammar.pest. Parsing
+// produces one of these and compilation consumes it, so anything that wants to
+// inspect, render or diff a rule can do so without going through pest
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleAst {
+    Constraint(ConstraintAst),
+    And(Box<RuleAst>, Box<RuleAst>),
+    Or(Box<RuleAst>, Box<RuleAst>),
+    // Negation of a whole expression. Single constraints carry their own inversion
+    // instead, see the Not impl
+    Not(Box<RuleAst>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstraintAst {
+    pub inverted: bool,
+    pub kind: ConstraintKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstraintKind {
+    Default(bool),
+    Hostname {
+        hostnames: Vec<String>,
+    },
+    Ip {
+        context: ContextField,
+        ranges: Vec<String>,
+    },
+    StringFragment {
+        context: ContextField,
+        comparator: StringComparator,
+        ignore_case: bool,
+        values: Vec<String>,
+    },
+    Regex {
+        context: ContextField,
+        ignore_case: bool,
+        pattern: String,
+    },
+    List {
+        context: ContextField,
+        comparator: ContentComparator,
+        // Only applies to string values
+        ignore_case: bool,
+        values: ListValues,
+    },
+    Date {
+        context: ContextField,
+        comparator: OrdinalComparator,
+        value: DateTime<Utc>,
+    },
+    Numeric {
+        context: ContextField,
+        comparator: OrdinalComparator,
+        value: f64,
+    },
+    Semver {
+        context: ContextField,
+        comparator: OrdinalComparator,
+        value: Version,
+    },
+    Rollout {
+        percentage: Percentage,
+        stickiness: Vec<ContextField>,
+        group_id: Option<String>,
+    },
+    ExternalValue(String),
+    // A registered custom strategy, bound when strategies are lowered. This has no
+    // form in the grammar, so it's written as the external value it stands in for
+    // and text rules can only refer to custom strategies through those
+    Custom {
+        key: String,
+        strategy: Arc<dyn CustomStrategy>,
+        parameters: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContextField {
+    UserId,
+    SessionId,
+    RemoteAddress,
+    AppName,
+    Environment,
+    CurrentTime,
+    Random(Option<usize>),
+    Property(String),
+}
+
+/// A rollout percentage with up to two decimals, kept in hundredths of a percent so
+/// that it compares exactly against rollout buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Percentage(u16);
+
+impl Percentage {
+    pub fn from_hundredths(hundredths: u16) -> Option<Self> {
+        (hundredths <= 10000).then_some(Percentage(hundredths))
+    }
+
+    pub fn hundredths(self) -> u16 {
+        self.0
+    }
+}
+
+impl FromStr for Percentage {
+    type Err = SdkError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (whole, fraction) = value.split_once('.').unwrap_or((value, "0"));
+        let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        (is_digits(whole) && is_digits(fraction) && fraction.len() <= 2)
+            .then(|| {
+                let whole = whole.parse::<u16>().ok()?;
+                let fraction = format!("{fraction:0<2}").parse::<u16>().ok()?;
+                Percentage::from_hundredths(whole.checked_mul(100)?.checked_add(fraction)?)
+            })
+            .flatten()
+            .ok_or_else(|| {
+                SdkError::StrategyParseError(format!(
+                    "Failed to compile {value} as a percentage value"
+                ))
+            })
+    }
+}
+
+// Whole percentages stay whole numbers, so traces of integer rollouts look the same
+// as they always have
+impl Serialize for Percentage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_multiple_of(100) {
+            serializer.serialize_u16(self.0 / 100)
+        } else {
+            serializer.serialize_f64(f64::from(self.0) / 100.0)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListValues {
+    Empty,
+    Numeric(Vec<f64>),
+    Strings(Vec<String>),
+    // Matched against context values parsed as versions, either exactly or as ranges
+    Semver(Vec<VersionReq>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrdinalComparator {
+    Lte,
+    Lt,
+    Gte,
+    Gt,
+    Eq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentComparator {
+    In,
+    NotIn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringComparator {
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+impl RuleAst {
+    pub fn constraint(kind: ConstraintKind) -> Self {
+        RuleAst::Constraint(ConstraintAst {
+            inverted: false,
+            kind,
+        })
+    }
+
+    pub fn and(lhs: RuleAst, rhs: RuleAst) -> Self {
+        RuleAst::And(Box::new(lhs), Box::new(rhs))
+    }
+
+    pub fn or(lhs: RuleAst, rhs: RuleAst) -> Self {
+        RuleAst::Or(Box::new(lhs), Box::new(rhs))
+    }
+}
+
+/// Negating a constraint flips its inversion rather than wrapping it and negating a
+/// negation unwraps it, so a rule only ever has the one form
+impl Not for RuleAst {
+    type Output = RuleAst;
+
+    fn not(self) -> Self::Output {
+        match self {
+            RuleAst::Constraint(constraint) => RuleAst::Constraint(ConstraintAst {
+                inverted: !constraint.inverted,
+                ..constraint
+            }),
+            RuleAst::Not(rule) => *rule,
+            rule => RuleAst::Not(Box::new(rule)),
+        }
+    }
+}
+
+impl ConstraintKind {
+    pub fn context(&self) -> Option<&ContextField> {
+        match self {
+            ConstraintKind::Ip { context, .. }
+            | ConstraintKind::StringFragment { context, .. }
+            | ConstraintKind::Regex { context, .. }
+            | ConstraintKind::List { context, .. }
+            | ConstraintKind::Date { context, .. }
+            | ConstraintKind::Numeric { context, .. }
+            | ConstraintKind::Semver { context, .. } => Some(context),
+            ConstraintKind::Default(_)
+            | ConstraintKind::Hostname { .. }
+            | ConstraintKind::Rollout { .. }
+            | ConstraintKind::ExternalValue(_)
+            | ConstraintKind::Custom { .. } => None,
+        }
+    }
+
+    pub fn operator(&self) -> Option<String> {
+        match self {
+            ConstraintKind::Hostname { .. } => Some("in".into()),
+            ConstraintKind::Ip { .. } => Some("in_cidr".into()),
+            ConstraintKind::StringFragment {
+                comparator,
+                ignore_case,
+                ..
+            } => Some(string_operator(comparator, *ignore_case).into()),
+            ConstraintKind::Regex { ignore_case, .. } => Some(regex_operator(*ignore_case).into()),
+            ConstraintKind::List {
+                comparator,
+                ignore_case,
+                ..
+            } => Some(list_operator(comparator, *ignore_case)),
+            ConstraintKind::Date { comparator, .. }
+            | ConstraintKind::Numeric { comparator, .. }
+            | ConstraintKind::Semver { comparator, .. } => Some(comparator.to_string()),
+            ConstraintKind::Default(_)
+            | ConstraintKind::Rollout { .. }
+            | ConstraintKind::ExternalValue(_)
+            | ConstraintKind::Custom { .. } => None,
+        }
+    }
+
+    pub fn values(&self) -> Vec<String> {
+        match self {
+            ConstraintKind::Default(value) => vec![value.to_string()],
+            ConstraintKind::Hostname { hostnames: values }
+            | ConstraintKind::Ip { ranges: values, .. }
+            | ConstraintKind::StringFragment { values, .. } => values.clone(),
+            ConstraintKind::Regex { pattern, .. } => vec![pattern.clone()],
+            ConstraintKind::List { values, .. } => match values {
+                ListValues::Empty => vec![],
+                ListValues::Numeric(values) => values.iter().map(f64::to_string).collect(),
+                ListValues::Strings(values) => values.clone(),
+                ListValues::Semver(values) => values.iter().map(semver_requirement).collect(),
+            },
+            ConstraintKind::Date { value, .. } => vec![format_date(value)],
+            ConstraintKind::Numeric { value, .. } => vec![value.to_string()],
+            ConstraintKind::Semver { value, .. } => vec![value.to_string()],
+            ConstraintKind::Rollout { percentage, .. } => vec![percentage.to_string()],
+            ConstraintKind::ExternalValue(key) => vec![key.clone()],
+            ConstraintKind::Custom { strategy, .. } => vec![strategy.name().to_string()],
+        }
+    }
+}
+
+fn string_operator(comparator: &StringComparator, ignore_case: bool) -> &'static str {
+    match (comparator, ignore_case) {
+        (StringComparator::StartsWith, false) => "starts_with_any",
+        (StringComparator::EndsWith, false) => "ends_with_any",
+        (StringComparator::Contains, false) => "contains_any",
+        (StringComparator::StartsWith, true) => "starts_with_any_ignore_case",
+        (StringComparator::EndsWith, true) => "ends_with_any_ignore_case",
+        (StringComparator::Contains, true) => "contains_any_ignore_case",
+    }
+}
+
+fn list_operator(comparator: &ContentComparator, ignore_case: bool) -> String {
+    if ignore_case {
+        format!("{comparator}_ignore_case")
+    } else {
+        comparator.to_string()
+    }
+}
+
+fn regex_operator(ignore_case: bool) -> &'static str {
+    if ignore_case {
+        "matches_regex_ignoring_case"
+    } else {
+        "matches_regex"
+    }
+}
+
+// Comparators are separated by spaces rather than the commas semver uses, since
+// commas already separate the values in a list
+fn semver_requirement(requirement: &VersionReq) -> String {
+    if requirement.comparators.is_empty() {
+        return "*".into();
+    }
+    requirement
+        .comparators
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+struct Quoted<'a>(&'a str);
+
+impl Display for Quoted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", escape_string(self.0))
+    }
+}
+
+fn write_string_list(f: &mut Formatter<'_>, values: &[String]) -> fmt::Result {
+    write!(f, "[")?;
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", Quoted(value))?;
+    }
+    write!(f, "]")
+}
+
+impl Display for RuleAst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (lhs, operator, rhs) = match self {
+            RuleAst::Constraint(constraint) => return write!(f, "{constraint}"),
+            RuleAst::Not(rule) => return write!(f, "!({rule})"),
+            RuleAst::And(lhs, rhs) => (lhs, "and", rhs),
+            RuleAst::Or(lhs, rhs) => (lhs, "or", rhs),
+        };
+
+        // Only a left leaning chain of the same operator can go without
+        // parentheses, everything else gets wrapped so that the output
+        // parses back to exactly the same tree
+        let needs_parens = |child: &RuleAst, is_rhs: bool| match child {
+            RuleAst::Constraint(_) | RuleAst::Not(_) => false,
+            RuleAst::And(..) => is_rhs || !matches!(self, RuleAst::And(..)),
+            RuleAst::Or(..) => is_rhs || !matches!(self, RuleAst::Or(..)),
+        };
+
+        for (index, child) in [lhs, rhs].into_iter().enumerate() {
+            if index > 0 {
+                write!(f, " {operator} ")?;
+            }
+            if needs_parens(child, index > 0) {
+                write!(f, "({child})")?;
+            } else {
+                write!(f, "{child}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for ConstraintAst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.inverted {
+            write!(f, "!")?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Display for ConstraintKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstraintKind::Default(value) => write!(f, "{value}"),
+            ConstraintKind::Hostname { hostnames } => {
+                write!(f, "hostname in ")?;
+                write_string_list(f, hostnames)
+            }
+            ConstraintKind::Ip { context, ranges } => {
+                write!(f, "{context} in_cidr ")?;
+                write_string_list(f, ranges)
+            }
+            ConstraintKind::StringFragment {
+                context,
+                comparator,
+                ignore_case,
+                values,
+            } => {
+                write!(
+                    f,
+                    "{context} {} ",
+                    string_operator(comparator, *ignore_case)
+                )?;
+                write_string_list(f, values)
+            }
+            ConstraintKind::Regex {
+                context,
+                ignore_case,
+                pattern,
+            } => write!(
+                f,
+                "{context} {} {}",
+                regex_operator(*ignore_case),
+                Quoted(pattern)
+            ),
+            ConstraintKind::List {
+                context,
+                comparator,
+                ignore_case,
+                values,
+            } => write!(
+                f,
+                "{context} {} {values}",
+                list_operator(comparator, *ignore_case)
+            ),
+            ConstraintKind::Date {
+                context,
+                comparator,
+                value,
+            } => write!(f, "{context} {comparator} {}", format_date(value)),
+            ConstraintKind::Numeric {
+                context,
+                comparator,
+                value,
+            } => write!(f, "{context} {comparator} {value}"),
+            ConstraintKind::Semver {
+                context,
+                comparator,
+                value,
+            } => write!(f, "{context} {comparator} {value}"),
+            ConstraintKind::Rollout {
+                percentage,
+                stickiness,
+                group_id,
+            } => {
+                write!(f, "{percentage}%")?;
+                if !stickiness.is_empty() {
+                    write!(f, " sticky on ")?;
+                    for (index, field) in stickiness.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, " | ")?;
+                        }
+                        write!(f, "{field}")?;
+                    }
+                }
+                if let Some(group_id) = group_id {
+                    write!(f, " with group_id of {}", Quoted(group_id))?;
+                }
+                Ok(())
+            }
+            ConstraintKind::ExternalValue(key) | ConstraintKind::Custom { key, .. } => {
+                write!(f, "external_value[{}]", Quoted(key))
+            }
+        }
+    }
+}
+
+impl Display for ContextField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextField::UserId => write!(f, "user_id"),
+            ContextField::SessionId => write!(f, "session_id"),
+            ContextField::RemoteAddress => write!(f, "remote_address"),
+            ContextField::AppName => write!(f, "app_name"),
+            ContextField::Environment => write!(f, "environment"),
+            ContextField::CurrentTime => write!(f, "current_time"),
+            ContextField::Random(None) => write!(f, "random"),
+            ContextField::Random(Some(max)) => write!(f, "random[{max}]"),
+            ContextField::Property(name) => write!(f, "context[{}]", Quoted(name)),
+        }
+    }
+}
+
+impl Display for Percentage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (whole, fraction) = (self.0 / 100, self.0 % 100);
+        match fraction {
+            0 => write!(f, "{whole}"),
+            fraction if fraction.is_multiple_of(10) => write!(f, "{whole}.{}", fraction / 10),
+            fraction => write!(f, "{whole}.{fraction:02}"),
+        }
+    }
+}
+
+impl Display for ListValues {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ListValues::Empty => write!(f, "[]"),
+            ListValues::Strings(values) => write_string_list(f, values),
+            ListValues::Semver(values) => {
+                let values: Vec<_> = values.iter().map(semver_requirement).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            ListValues::Numeric(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl Display for OrdinalComparator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let operator = match self {
+            OrdinalComparator::Lte => "<=",
+            OrdinalComparator::Lt => "<",
+            OrdinalComparator::Gte => ">=",
+            OrdinalComparator::Gt => ">",
+            OrdinalComparator::Eq => "==",
+        };
+        write!(f, "{operator}")
+    }
+}
+
+impl Display for ContentComparator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let operator = match self {
+            ContentComparator::In => "in",
+            ContentComparator::NotIn => "not_in",
+        };
+        write!(f, "{operator}")
+    }
+}
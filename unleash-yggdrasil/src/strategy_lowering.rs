@@ -0,0 +1,596 @@
+#![deny(clippy::expect_used, clippy::unwrap_used)]
+
+use std::collections::HashMap;
+
+use unleash_types::client_features::{Constraint, Operator, Segment, Strategy, StrategyVariant};
+
+use crate::custom_strategy::CustomStrategies;
+use crate::state::SdkError;
+use crate::strategy_ast::{
+    ConstraintAst, ConstraintKind, ContentComparator, ContextField, ListValues, OrdinalComparator,
+    Percentage, RuleAst, StringComparator,
+};
+use crate::strategy_parsing::{date_literal, numeric_literal, semver_literal};
+use crate::strategy_upgrade::{
+    custom_strategy_key, get_rollout_percentage, get_rollout_target, is_stringy,
+    number_custom_strategies, resolve_constraints, sorted_strategies, stickiness_chain,
+    variant_parameters, PropResolver, StrategyType,
+};
+
+// Lowers strategies straight into the rule AST. This produces the same rules as
+// strategy_upgrade without round tripping the values through the text DSL, so
+// values are carried over verbatim rather than escaped and re-parsed
+
+const DEFAULT_RANDOM: ContextField = ContextField::Random(Some(10000));
+
+pub(crate) type LoweredVariantRule = Vec<(RuleAst, Vec<StrategyVariant>, String, String)>;
+
+pub fn lower(
+    strategies: &[Strategy],
+    segment_map: &HashMap<i32, Segment>,
+    custom_strategies: &CustomStrategies,
+) -> Result<RuleAst, SdkError> {
+    lower_strategies(strategies, segment_map, custom_strategies)
+        .into_iter()
+        .try_fold(None, |rule: Option<RuleAst>, strategy_rule| {
+            let strategy_rule = strategy_rule?;
+            Ok(Some(match rule {
+                Some(rule) => RuleAst::or(rule, strategy_rule),
+                None => strategy_rule,
+            }))
+        })
+        .map(|rule| rule.unwrap_or_else(|| boolean(true)))
+}
+
+/// Lowers each strategy to its own rule, in evaluation order. Registered custom strategies are
+/// bound directly, any others are numbered the same way as in `upgrade_strategies`
+pub fn lower_strategies(
+    strategies: &[Strategy],
+    segment_map: &HashMap<i32, Segment>,
+    custom_strategies: &CustomStrategies,
+) -> Vec<Result<RuleAst, SdkError>> {
+    number_custom_strategies(strategies)
+        .map(|(strategy, custom_count)| {
+            lower_strategy(strategy, segment_map, custom_strategies, custom_count)
+        })
+        .collect()
+}
+
+pub fn lower_variant_rules(
+    strategies: &[Strategy],
+    segment_map: &HashMap<i32, Segment>,
+    custom_strategies: &CustomStrategies,
+    toggle_name: &str,
+) -> Result<LoweredVariantRule, SdkError> {
+    sorted_strategies(strategies)
+        .into_iter()
+        .zip(lower_strategies(strategies, segment_map, custom_strategies))
+        .map(|(strategy, rule)| {
+            let (variants, stickiness, group_id) = variant_parameters(strategy, toggle_name);
+            Ok((rule?, variants, stickiness, group_id))
+        })
+        .collect()
+}
+
+fn lower_strategy(
+    strategy: &Strategy,
+    segment_map: &HashMap<i32, Segment>,
+    custom_strategies: &CustomStrategies,
+    strategy_count: usize,
+) -> Result<RuleAst, SdkError> {
+    let strategy_rule = match StrategyType::from(strategy.name.as_str()) {
+        StrategyType::Default => boolean(true),
+        StrategyType::UserWithId => lower_user_id_strategy(strategy),
+        StrategyType::GradualRolloutUserId => {
+            lower_gradual_rollout_strategy(strategy, ContextField::UserId)?
+        }
+        StrategyType::GradualRolloutSessionId => {
+            lower_gradual_rollout_strategy(strategy, ContextField::SessionId)?
+        }
+        StrategyType::GradualRolloutRandom => lower_random(strategy),
+        StrategyType::FlexibleRollout => lower_flexible_rollout_strategy(strategy)?,
+        StrategyType::RemoteAddress => lower_remote_address(strategy),
+        StrategyType::ApplicationHostname => lower_hostname(strategy),
+        StrategyType::Custom(name) => match custom_strategies.get(&name) {
+            Some(custom_strategy) => RuleAst::constraint(ConstraintKind::Custom {
+                key: custom_strategy_key(strategy_count),
+                strategy: custom_strategy.clone(),
+                parameters: strategy.parameters.clone().unwrap_or_default(),
+            }),
+            None => RuleAst::constraint(ConstraintKind::ExternalValue(custom_strategy_key(
+                strategy_count,
+            ))),
+        },
+    };
+
+    let constraints = resolve_constraints(strategy, segment_map)?
+        .iter()
+        .map(lower_constraint)
+        .try_fold(None, |rule: Option<RuleAst>, constraint| {
+            let constraint = constraint?;
+            Ok::<_, SdkError>(Some(match rule {
+                Some(rule) => RuleAst::and(rule, constraint),
+                None => constraint,
+            }))
+        })?;
+
+    match constraints {
+        Some(constraints) => Ok(RuleAst::and(strategy_rule, constraints)),
+        None => Ok(strategy_rule),
+    }
+}
+
+fn boolean(value: bool) -> RuleAst {
+    RuleAst::constraint(ConstraintKind::Default(value))
+}
+
+fn split_parameter(value: &str) -> Vec<String> {
+    value.split(',').map(|x| x.trim().to_string()).collect()
+}
+
+fn percentage(rollout: usize) -> Result<Percentage, SdkError> {
+    u16::try_from(rollout)
+        .ok()
+        .and_then(|rollout| rollout.checked_mul(100))
+        .and_then(Percentage::from_hundredths)
+        .ok_or_else(|| {
+            SdkError::StrategyParseError(format!(
+                "Failed to compile {rollout} as a percentage value"
+            ))
+        })
+}
+
+fn lower_flexible_rollout_strategy(strategy: &Strategy) -> Result<RuleAst, SdkError> {
+    match get_rollout_percentage(strategy, "rollout") {
+        Some(rollout) => Ok(RuleAst::constraint(ConstraintKind::Rollout {
+            percentage: rollout?,
+            stickiness: lower_stickiness(strategy.get_param("stickiness").map(String::as_str)),
+            group_id: strategy.get_param("groupId").cloned(),
+        })),
+        None => Ok(boolean(false)),
+    }
+}
+
+fn lower_user_id_strategy(strategy: &Strategy) -> RuleAst {
+    match strategy.get_param("userIds") {
+        Some(user_ids) => RuleAst::constraint(ConstraintKind::List {
+            context: ContextField::UserId,
+            comparator: ContentComparator::In,
+            ignore_case: false,
+            values: ListValues::Strings(split_parameter(user_ids)),
+        }),
+        None => boolean(false),
+    }
+}
+
+fn lower_remote_address(strategy: &Strategy) -> RuleAst {
+    match strategy.get_param("IPs") {
+        Some(addresses) => RuleAst::constraint(ConstraintKind::Ip {
+            context: ContextField::RemoteAddress,
+            ranges: split_parameter(addresses),
+        }),
+        None => boolean(false),
+    }
+}
+
+fn lower_gradual_rollout_strategy(
+    strategy: &Strategy,
+    stickiness: ContextField,
+) -> Result<RuleAst, SdkError> {
+    let percentage_param = get_rollout_target(strategy, "percentage");
+
+    match (percentage_param, strategy.get_param("groupId")) {
+        (Some(rollout), Some(group_id)) => Ok(RuleAst::constraint(ConstraintKind::Rollout {
+            percentage: percentage(rollout)?,
+            stickiness: vec![stickiness],
+            group_id: Some(group_id.clone()),
+        })),
+        _ => Ok(boolean(false)),
+    }
+}
+
+fn lower_hostname(strategy: &Strategy) -> RuleAst {
+    //intentional, unleash returns "" when no hostnames are set
+    let hostnames = strategy
+        .get_param("hostNames")
+        .map(String::as_str)
+        .unwrap_or_default();
+
+    RuleAst::constraint(ConstraintKind::Hostname {
+        hostnames: split_parameter(hostnames),
+    })
+}
+
+fn lower_random(strategy: &Strategy) -> RuleAst {
+    match get_rollout_target(strategy, "percentage") {
+        Some(percent) => RuleAst::constraint(ConstraintKind::Numeric {
+            context: ContextField::Random(None),
+            comparator: OrdinalComparator::Lt,
+            value: percent as f64,
+        }),
+        None => boolean(false),
+    }
+}
+
+fn constraint_value(constraint: &Constraint) -> Result<&str, SdkError> {
+    constraint
+        .value
+        .as_deref()
+        .ok_or_else(|| SdkError::StrategyParseError("Failed to resolve constraint value".into()))
+}
+
+fn lower_constraint(constraint: &Constraint) -> Result<RuleAst, SdkError> {
+    let context = lower_context_name(&constraint.context_name);
+    let values = constraint.values.clone().unwrap_or_default();
+    let ignore_case = constraint.case_insensitive;
+
+    if is_stringy(&constraint.operator) && values.is_empty() {
+        match constraint.operator {
+            Operator::In | Operator::NotIn => {}
+            _ => {
+                return Err(SdkError::StrategyParseError(
+                    "Failed to compile an empty list of values".into(),
+                ))
+            }
+        }
+    }
+
+    let kind = match &constraint.operator {
+        Operator::In | Operator::NotIn => ConstraintKind::List {
+            context,
+            comparator: if constraint.operator == Operator::In {
+                ContentComparator::In
+            } else {
+                ContentComparator::NotIn
+            },
+            ignore_case,
+            values: if values.is_empty() {
+                ListValues::Empty
+            } else {
+                ListValues::Strings(values)
+            },
+        },
+        Operator::StrEndsWith => ConstraintKind::StringFragment {
+            context,
+            comparator: StringComparator::EndsWith,
+            ignore_case,
+            values,
+        },
+        Operator::StrStartsWith => ConstraintKind::StringFragment {
+            context,
+            comparator: StringComparator::StartsWith,
+            ignore_case,
+            values,
+        },
+        Operator::StrContains => ConstraintKind::StringFragment {
+            context,
+            comparator: StringComparator::Contains,
+            ignore_case,
+            values,
+        },
+        Operator::InCidr => ConstraintKind::Ip {
+            context,
+            ranges: values,
+        },
+        Operator::RegexMatch => ConstraintKind::Regex {
+            context,
+            ignore_case,
+            pattern: constraint.value.clone().unwrap_or_default(),
+        },
+        Operator::NumEq
+        | Operator::NumGt
+        | Operator::NumGte
+        | Operator::NumLt
+        | Operator::NumLte
+        | Operator::DateAfter
+        | Operator::DateBefore => ordinal_constraint(
+            context,
+            lower_ordinal_operator(&constraint.operator)?,
+            constraint_value(constraint)?,
+        )?,
+        Operator::SemverEq
+        | Operator::SemverLt
+        | Operator::SemverGt
+        | Operator::SemverLte
+        | Operator::SemverGte => {
+            let value = constraint_value(constraint)?;
+            // Semver values with a v prefix are rejected outright, regardless of inversion,
+            // matching the behaviour of strategy_upgrade
+            if value.starts_with('v') {
+                return Ok(boolean(false));
+            }
+            ordinal_constraint(
+                context,
+                lower_ordinal_operator(&constraint.operator)?,
+                value,
+            )?
+        }
+        Operator::Unknown(_) => {
+            return Err(SdkError::StrategyParseError(
+                "Failed to resolve constraint operator".into(),
+            ))
+        }
+    };
+
+    Ok(RuleAst::Constraint(ConstraintAst {
+        inverted: constraint.inverted,
+        kind,
+    }))
+}
+
+// The grammar decides what kind of comparison an ordinal operator makes from the shape
+// of the value rather than the operator, trying dates, then semver, then numbers. That's
+// preserved here so that a mistyped value compiles to the same rule it always has
+fn ordinal_constraint(
+    context: ContextField,
+    comparator: OrdinalComparator,
+    value: &str,
+) -> Result<ConstraintKind, SdkError> {
+    if let Ok(value) = date_literal(value) {
+        return Ok(ConstraintKind::Date {
+            context,
+            comparator,
+            value,
+        });
+    }
+    if let Ok(value) = semver_literal(value) {
+        return Ok(ConstraintKind::Semver {
+            context,
+            comparator,
+            value,
+        });
+    }
+    Ok(ConstraintKind::Numeric {
+        context,
+        comparator,
+        value: numeric_literal(value)?,
+    })
+}
+
+fn lower_ordinal_operator(op: &Operator) -> Result<OrdinalComparator, SdkError> {
+    match op {
+        Operator::NumEq | Operator::SemverEq => Ok(OrdinalComparator::Eq),
+        Operator::NumGt | Operator::SemverGt | Operator::DateAfter => Ok(OrdinalComparator::Gt),
+        Operator::NumGte | Operator::SemverGte => Ok(OrdinalComparator::Gte),
+        Operator::NumLt | Operator::SemverLt | Operator::DateBefore => Ok(OrdinalComparator::Lt),
+        Operator::NumLte | Operator::SemverLte => Ok(OrdinalComparator::Lte),
+        _ => Err(SdkError::StrategyParseError(
+            "Failed to resolve constraint operator".into(),
+        )),
+    }
+}
+
+/// The fields a stickiness parameter tries in turn. Chains like `userId|deviceId`
+/// fall back from one field to the next, and `default` is itself a chain that ends
+/// in random
+pub(crate) fn lower_stickiness(stickiness_param: Option<&str>) -> Vec<ContextField> {
+    stickiness_chain(stickiness_param)
+        .flat_map(|stickiness| match stickiness {
+            "random" => vec![DEFAULT_RANDOM],
+            "default" => vec![
+                ContextField::UserId,
+                ContextField::SessionId,
+                DEFAULT_RANDOM,
+            ],
+            stickiness => vec![lower_context_name(stickiness)],
+        })
+        .collect()
+}
+
+fn lower_context_name(context_name: &str) -> ContextField {
+    match context_name {
+        "userId" => ContextField::UserId,
+        "sessionId" => ContextField::SessionId,
+        "currentTime" => ContextField::CurrentTime,
+        "environment" => ContextField::Environment,
+        "appName" => ContextField::AppName,
+        "remoteAddress" => ContextField::RemoteAddress,
+        _ => ContextField::Property(context_name.to_string()),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::state::EnrichedContext as Context;
+    use crate::strategy_parsing::{compile_rule, compile_rule_ast, parse_rule};
+    use crate::strategy_upgrade::upgrade;
+    use test_case::test_case;
+
+    fn strategy(name: &str, parameters: &[(&str, &str)]) -> Strategy {
+        Strategy {
+            name: name.into(),
+            parameters: Some(
+                parameters
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            constraints: None,
+            segments: None,
+            sort_order: None,
+            variants: None,
+        }
+    }
+
+    fn constraint(context_name: &str, operator: Operator, values: &[&str]) -> Constraint {
+        let is_list = is_stringy(&operator);
+        Constraint {
+            context_name: context_name.into(),
+            operator,
+            case_insensitive: false,
+            inverted: false,
+            values: is_list.then(|| values.iter().map(|value| value.to_string()).collect()),
+            value: (!is_list).then(|| values.join("")),
+        }
+    }
+
+    fn constrained(constraints: Vec<Constraint>) -> Strategy {
+        Strategy {
+            constraints: Some(constraints),
+            ..strategy("default", &[])
+        }
+    }
+
+    fn segments() -> HashMap<i32, Segment> {
+        let mut segments = HashMap::new();
+        segments.insert(
+            1,
+            Segment {
+                id: 1,
+                constraints: vec![constraint("environment", Operator::In, &["prod"])],
+            },
+        );
+        segments
+    }
+
+    // Both paths have to agree on the exact rule, not just the outcome, or fail together
+    fn assert_paths_agree(strategies: &[Strategy]) {
+        let segments = segments();
+        let from_text = upgrade(strategies, &segments).and_then(|rule| parse_rule(&rule));
+        let lowered = lower(strategies, &segments, &CustomStrategies::default());
+
+        match (from_text, lowered) {
+            (Ok(from_text), Ok(lowered)) => assert_eq!(from_text, lowered),
+            (Err(_), Err(_)) => {}
+            (from_text, lowered) => panic!(
+                "Paths disagree, text produced {from_text:?} but lowering produced {lowered:?}"
+            ),
+        }
+    }
+
+    #[test_case(vec![]; "no strategies")]
+    #[test_case(vec![strategy("default", &[])]; "default")]
+    #[test_case(vec![strategy("userWithId", &[("userIds", "123, 222,88")])]; "user with id")]
+    #[test_case(vec![strategy("userWithId", &[])]; "user with id without ids")]
+    #[test_case(vec![strategy("gradualRolloutUserId", &[("percentage", "40"), ("groupId", "g")])]; "gradual user id")]
+    #[test_case(vec![strategy("gradualRolloutSessionId", &[("percentage", "40"), ("groupId", "g")])]; "gradual session id")]
+    #[test_case(vec![strategy("gradualRolloutSessionId", &[("percentage", "40")])]; "gradual session id without group")]
+    #[test_case(vec![strategy("gradualRolloutRandom", &[("percentage", "40")])]; "gradual random")]
+    #[test_case(vec![strategy("gradualRolloutRandom", &[("percentage", "nope")])]; "gradual random invalid")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "55"), ("stickiness", "default"), ("groupId", "g")])]; "flexible default stickiness")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "100"), ("stickiness", "random")])]; "flexible random stickiness")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "0"), ("stickiness", "customField")])]; "flexible custom stickiness")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "5"), ("stickiness", "sessionId")])]; "flexible session stickiness")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "5"), ("stickiness", "userId|deviceId|sessionId")])]; "flexible stickiness chain")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "5"), ("stickiness", " deviceId | random ")])]; "flexible stickiness chain ending in random")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "150")])]; "flexible out of range")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "0.25"), ("stickiness", "default")])]; "flexible fractional")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "99.90")])]; "flexible trailing zero")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "0.125")])]; "flexible too precise")]
+    #[test_case(vec![strategy("flexibleRollout", &[("rollout", "1.5.0")])]; "flexible not a decimal")]
+    #[test_case(vec![strategy("flexibleRollout", &[])]; "flexible without rollout")]
+    #[test_case(vec![strategy("remoteAddress", &[("IPs", "192.168.0.1, 10.0.0.0/8")])]; "remote address")]
+    #[test_case(vec![strategy("applicationHostname", &[("hostNames", "a, b")])]; "hostname")]
+    #[test_case(vec![strategy("applicationHostname", &[])]; "hostname without hostnames")]
+    #[test_case(vec![strategy("custom", &[]), strategy("default", &[]), strategy("other", &[])]; "custom numbering")]
+    #[test_case(vec![strategy("userWithId", &[("userIds", "1")]), strategy("default", &[]), strategy("userWithId", &[("userIds", "2")])]; "or chain")]
+    fn strategies_lower_to_the_same_rule_as_the_upgraded_text(strategies: Vec<Strategy>) {
+        assert_paths_agree(&strategies);
+    }
+
+    #[test_case(constraint("userId", Operator::In, &["7", "8"]); "in")]
+    #[test_case(constraint("customField", Operator::NotIn, &["7"]); "not in")]
+    #[test_case(constraint("userId", Operator::In, &[]); "in empty")]
+    #[test_case(constraint("userId", Operator::NotIn, &[]); "not in empty")]
+    #[test_case(constraint("appName", Operator::StrStartsWith, &["my"]); "starts with")]
+    #[test_case(constraint("appName", Operator::StrEndsWith, &["app"]); "ends with")]
+    #[test_case(constraint("appName", Operator::StrContains, &["pp", "y"]); "contains")]
+    #[test_case(constraint("appName", Operator::StrContains, &[]); "contains empty")]
+    #[test_case(constraint("remoteAddress", Operator::InCidr, &["10.0.0.0/8"]); "in cidr")]
+    #[test_case(constraint("remoteAddress", Operator::InCidr, &[]); "in cidr empty")]
+    #[test_case(constraint("email", Operator::RegexMatch, &["^.+@example\\.com$"]); "regex")]
+    #[test_case(constraint("count", Operator::NumEq, &["5"]); "num eq")]
+    #[test_case(constraint("count", Operator::NumGt, &["-5.5"]); "num gt")]
+    #[test_case(constraint("count", Operator::NumGte, &["1e3"]); "num gte")]
+    #[test_case(constraint("count", Operator::NumLt, &["abc"]); "num lt invalid")]
+    #[test_case(constraint("currentTime", Operator::DateAfter, &["2022-01-29T13:00:00.000Z"]); "date after")]
+    #[test_case(constraint("currentTime", Operator::DateBefore, &["2022-01-29T13:00:00+01:00"]); "date before")]
+    #[test_case(constraint("currentTime", Operator::DateBefore, &["5"]); "date with a number")]
+    #[test_case(constraint("version", Operator::SemverEq, &["1.2.3"]); "semver eq")]
+    #[test_case(constraint("version", Operator::SemverGt, &["1.2.3-beta.1+build"]); "semver gt")]
+    #[test_case(constraint("version", Operator::SemverGte, &["v1.2.3"]); "semver with v prefix")]
+    #[test_case(constraint("version", Operator::SemverLt, &["1.2"]); "semver with a number")]
+    #[test_case(constraint("version", Operator::SemverLte, &["01.2.3"]); "semver with leading zero")]
+    #[test_case(constraint("version", Operator::Unknown("SEMVER_NOPE".into()), &["1.2.3"]); "unknown operator")]
+    fn constraints_lower_to_the_same_rule_as_the_upgraded_text(constraint: Constraint) {
+        for (inverted, case_insensitive) in [(false, false), (true, false), (false, true)] {
+            let constraint = Constraint {
+                inverted,
+                case_insensitive,
+                ..constraint.clone()
+            };
+            assert_paths_agree(&[constrained(vec![constraint.clone()])]);
+            assert_paths_agree(&[constrained(vec![constraint.clone(), constraint])]);
+        }
+    }
+
+    #[test]
+    fn segment_constraints_follow_strategy_constraints() {
+        let strategy = Strategy {
+            segments: Some(vec![1]),
+            ..constrained(vec![constraint("userId", Operator::In, &["7"])])
+        };
+
+        assert_paths_agree(&[strategy]);
+    }
+
+    #[test]
+    fn unresolvable_segments_fail_to_lower() {
+        let strategy = Strategy {
+            segments: Some(vec![2]),
+            ..strategy("default", &[])
+        };
+
+        assert!(lower(&[strategy], &segments(), &CustomStrategies::default()).is_err());
+    }
+
+    #[test_case("C:\\"; "trailing backslash")]
+    #[test_case("\\\""; "escaped quote")]
+    #[test_case("say \"hi\""; "quotes")]
+    fn values_are_carried_over_verbatim(value: &str) {
+        let strategies = [constrained(vec![constraint(
+            "userId",
+            Operator::In,
+            &[value],
+        )])];
+
+        let rule = compile_rule_ast(
+            &lower(&strategies, &HashMap::new(), &CustomStrategies::default()).unwrap(),
+        )
+        .unwrap();
+        let context = Context {
+            user_id: Some(value),
+            ..Context::default()
+        };
+
+        assert!(rule.evaluate(&context));
+    }
+
+    #[test_case("C:\\"; "trailing backslash")]
+    #[test_case("\\\""; "escaped quote")]
+    #[test_case("line\nbreak\ttab\u{7}"; "control characters")]
+    #[test_case("café \u{1F600}"; "unicode")]
+    fn escaped_values_survive_the_text_path(value: &str) {
+        let strategies = [constrained(vec![
+            constraint("userId", Operator::In, &[value]),
+            constraint("email", Operator::RegexMatch, &[value]),
+        ])];
+
+        assert!(compile_rule(&upgrade(&strategies, &HashMap::new()).unwrap()).is_ok());
+        assert_paths_agree(&strategies);
+    }
+
+    #[test]
+    fn values_cannot_change_the_shape_of_the_rule() {
+        let strategies = [constrained(vec![constraint(
+            "count",
+            Operator::NumLte,
+            &["5 or true"],
+        )])];
+
+        assert!(upgrade(&strategies, &HashMap::new()).is_err());
+        assert!(lower(&strategies, &HashMap::new(), &CustomStrategies::default()).is_err());
+    }
+}
@@ -1,25 +1,33 @@
 extern crate pest;
 
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::Cursor;
 use std::net::IpAddr;
 use std::num::ParseFloatError;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
+#[cfg(feature = "wall-clock")]
+use crate::clock::SystemClock;
+use crate::custom_strategy::CustomStrategy;
+use crate::random_source::ThreadRandom;
 use crate::sendable_closures::SendableFragment;
-use crate::state::SdkError;
+use crate::state::{ContextValues, ParsedValue, SdkError};
+use crate::strategy_ast::{
+    ConstraintAst, ConstraintKind, ContentComparator, ContextField, ListValues, OrdinalComparator,
+    Percentage, RuleAst, StringComparator,
+};
 use crate::EnrichedContext as Context;
 use chrono::{DateTime, Utc};
-use ipnetwork::{IpNetwork, IpNetworkError};
+use ipnetwork::IpNetwork;
 use murmur3::murmur3_32;
 use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest::Parser;
-use rand::Rng;
 use regex::RegexBuilder;
-use semver::Version;
+use semver::{Version, VersionReq};
+use serde::Serialize;
 
 #[cfg(feature = "hostname")]
 use hostname;
@@ -38,6 +46,7 @@ lazy_static! {
         PrattParser::new()
             .op(Op::infix(and, Left))
             .op(Op::infix(or, Left))
+            .op(Op::prefix(invert_operation))
     };
 }
 
@@ -51,6 +60,16 @@ pub fn normalized_hash(
     murmur3_32(&mut reader, seed).map(|hash_result| hash_result % modulus + 1)
 }
 
+/// Rollout buckets in hundredths of a percent, from 1 to 10000. The whole percent of
+/// the bucket is the `normalized_hash` bucket with a modulus of 100 and the hash
+/// picks the hundredths within it, so `bucket <= percentage * 100` holds for exactly
+/// the identifiers that a whole `percentage` rollout has always included
+pub fn normalized_fine_hash(group: &str, identifier: &str, seed: u32) -> std::io::Result<u32> {
+    let mut reader = Cursor::new(format!("{}:{}", &group, &identifier));
+    murmur3_32(&mut reader, seed)
+        .map(|hash_result| (hash_result % 100) * 100 + (hash_result / 100) % 100 + 1)
+}
+
 fn drain<const N: usize>(node: Pairs<Rule>) -> CompileResult<[Pair<Rule>; N]> {
     Ok(drain_partial(node)?.0)
 }
@@ -82,115 +101,250 @@ pub type RuleFragment = Box<dyn SendableFragment + Send + Sync + 'static>;
 pub type ContextResolver =
     Arc<dyn for<'a> Fn(&'a Context) -> Option<Cow<'a, str>> + Send + Sync + 'static>;
 
-trait Invertible {
-    fn invert(&self, inverted: bool) -> bool;
+pub type TypedResolver<T> = Arc<dyn Fn(&Context) -> Option<T> + Send + Sync + 'static>;
+
+// For constraints that match when any one of several values does
+pub type ContextValuesResolver =
+    Arc<dyn for<'a> Fn(&'a Context) -> Option<ContextValues<'a>> + Send + Sync + 'static>;
+
+// The compiled form of a rule. Each leaf is still a closure, but the boolean
+// structure is kept around so that we can report on every node when explaining
+// why a toggle resolved the way it did
+#[derive(Clone)]
+pub enum RuleNode {
+    Constraint(Arc<ConstraintNode>),
+    And(Box<RuleNode>, Box<RuleNode>),
+    Or(Box<RuleNode>, Box<RuleNode>),
+    Not(Box<RuleNode>),
 }
 
-impl Invertible for bool {
-    fn invert(&self, inverted: bool) -> bool {
-        if inverted {
-            !self
-        } else {
-            *self
-        }
-    }
+#[derive(Clone)]
+pub struct ConstraintNode {
+    rule: String,
+    inverted: bool,
+    operator: Option<String>,
+    values: Vec<String>,
+    context_getter: Option<ContextResolver>,
+    rollout: Option<RolloutDetails>,
+    fragment: RuleFragment,
 }
 
-#[derive(Debug, Clone)]
-enum OrdinalComparator {
-    Lte,
-    Lt,
-    Gte,
-    Gt,
-    Eq,
+#[derive(Clone)]
+struct RolloutDetails {
+    percentage: Percentage,
+    stickiness_resolver: ContextResolver,
+    group_id: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-enum ContentComparator {
-    In,
-    NotIn,
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RuleTrace {
+    And {
+        result: bool,
+        children: Vec<RuleTrace>,
+    },
+    Or {
+        result: bool,
+        children: Vec<RuleTrace>,
+    },
+    Not {
+        result: bool,
+        child: Box<RuleTrace>,
+    },
+    Constraint(ConstraintTrace),
 }
 
-#[derive(Debug, Clone)]
-enum StringComparator {
-    StartsWith,
-    EndsWith,
-    Contains,
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConstraintTrace {
+    pub rule: String,
+    pub inverted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    pub values: Vec<String>,
+    pub context_value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollout: Option<RolloutTrace>,
+    pub result: bool,
 }
 
-struct StringComparatorType {
-    ignore_case: bool,
-    comparator_type: StringComparator,
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutTrace {
+    pub percentage: Percentage,
+    pub group_id: String,
+    pub stickiness_value: Option<String>,
+    /// In hundredths of a percent, see `normalized_fine_hash`
+    pub bucket: Option<u32>,
 }
 
-//Context lifting properties - these resolve properties from the context
-fn context_value(node: Pairs<Rule>) -> CompileResult<ContextResolver> {
-    let [child] = drain(node)?;
+impl RuleTrace {
+    pub fn result(&self) -> bool {
+        match self {
+            RuleTrace::And { result, .. }
+            | RuleTrace::Or { result, .. }
+            | RuleTrace::Not { result, .. } => *result,
+            RuleTrace::Constraint(constraint) => constraint.result,
+        }
+    }
+}
 
-    Ok(match child.as_rule() {
-        Rule::user_id => Arc::new(|c: &Context| c.user_id.map(Cow::Borrowed)),
-        Rule::app_name => Arc::new(|c: &Context| c.app_name.map(Cow::Borrowed)),
-        Rule::environment => Arc::new(|c: &Context| c.environment.map(Cow::Borrowed)),
-        Rule::session_id => Arc::new(|c: &Context| c.session_id.map(Cow::Borrowed)),
-        Rule::remote_address => Arc::new(|c: &Context| c.remote_address.map(Cow::Borrowed)),
-
-        #[cfg(feature = "wall-clock")]
-        Rule::current_time => Arc::new(|c: &Context| {
-            c.current_time.map(Cow::Borrowed).or_else(|| {
-                Some(Cow::Owned(
-                    chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
-                ))
-            })
-        }),
-        #[cfg(not(feature = "wall-clock"))]
-        Rule::current_time => Arc::new(|c: &Context| c.current_time.map(Cow::Borrowed)),
+impl RuleNode {
+    pub fn evaluate(&self, context: &Context) -> bool {
+        match self {
+            RuleNode::Constraint(constraint) => {
+                (constraint.fragment)(context).invert(constraint.inverted)
+            }
+            RuleNode::And(lhs, rhs) => lhs.evaluate(context) && rhs.evaluate(context),
+            RuleNode::Or(lhs, rhs) => lhs.evaluate(context) || rhs.evaluate(context),
+            RuleNode::Not(rule) => !rule.evaluate(context),
+        }
+    }
 
-        Rule::random => {
-            let value = child
-                .into_inner()
-                .next()
-                .and_then(|rule| rule.as_str().parse::<usize>().ok())
-                .unwrap_or(100);
+    pub fn into_fragment(self) -> RuleFragment {
+        Box::new(move |context: &Context| self.evaluate(context))
+    }
 
-            Arc::new(move |_c: &Context| {
-                Some(Cow::Owned(rand::rng().random_range(1..value).to_string()))
-            })
+    // Unlike evaluate, this doesn't short circuit, every node in the tree gets
+    // reported on. Chains of the same boolean operator are flattened into a
+    // single node so that "a and b and c" reads as one list of three constraints
+    pub fn trace(&self, context: &Context) -> RuleTrace {
+        match self {
+            RuleNode::Constraint(constraint) => RuleTrace::Constraint(constraint.trace(context)),
+            RuleNode::And(..) => {
+                let mut children = vec![];
+                self.collect_traces(context, &mut children);
+                RuleTrace::And {
+                    result: children.iter().all(RuleTrace::result),
+                    children,
+                }
+            }
+            RuleNode::Or(..) => {
+                let mut children = vec![];
+                self.collect_traces(context, &mut children);
+                RuleTrace::Or {
+                    result: children.iter().any(RuleTrace::result),
+                    children,
+                }
+            }
+            RuleNode::Not(rule) => {
+                let child = rule.trace(context);
+                RuleTrace::Not {
+                    result: !child.result(),
+                    child: Box::new(child),
+                }
+            }
         }
+    }
 
-        Rule::property => context_property(child.into_inner())?,
-
-        _ => unreachable!(),
-    })
+    fn collect_traces(&self, context: &Context, traces: &mut Vec<RuleTrace>) {
+        let (lhs, rhs) = match self {
+            RuleNode::And(lhs, rhs) | RuleNode::Or(lhs, rhs) => (lhs, rhs),
+            RuleNode::Constraint(_) | RuleNode::Not(_) => unreachable!(),
+        };
+        for child in [lhs, rhs] {
+            match (self, child.as_ref()) {
+                (RuleNode::And(..), RuleNode::And(..)) | (RuleNode::Or(..), RuleNode::Or(..)) => {
+                    child.collect_traces(context, traces)
+                }
+                _ => traces.push(child.trace(context)),
+            }
+        }
+    }
 }
 
-pub(crate) fn coalesce_context_property(node: Pairs<Rule>) -> CompileResult<ContextResolver> {
-    let mut stickiness_resolvers = vec![];
+impl ConstraintNode {
+    fn trace(&self, context: &Context) -> ConstraintTrace {
+        let context_value = self
+            .context_getter
+            .as_ref()
+            .and_then(|getter| getter(context))
+            .map(|value| value.into_owned());
+
+        let rollout = self.rollout.as_ref().map(|rollout| {
+            let stickiness_value =
+                (rollout.stickiness_resolver)(context).map(|value| value.into_owned());
+            let group_id = rollout
+                .group_id
+                .clone()
+                .unwrap_or_else(|| context.toggle_name.to_string());
+            let bucket = stickiness_value
+                .as_ref()
+                .and_then(|stickiness| normalized_fine_hash(&group_id, stickiness, 0).ok());
+
+            RolloutTrace {
+                percentage: rollout.percentage,
+                group_id,
+                stickiness_value,
+                bucket,
+            }
+        });
+
+        // Rollouts are reported from the computed bucket rather than by running the
+        // fragment again, random stickiness would otherwise give us a different answer
+        // to the one we're showing
+        let result = match &rollout {
+            Some(rollout) => rollout
+                .bucket
+                .is_some_and(|bucket| bucket <= rollout.percentage.hundredths().into())
+                .invert(self.inverted),
+            None => (self.fragment)(context).invert(self.inverted),
+        };
 
-    for child in node {
-        stickiness_resolvers.push(context_value(child.into_inner())?)
+        ConstraintTrace {
+            rule: self.rule.clone(),
+            inverted: self.inverted,
+            operator: self.operator.clone(),
+            values: self.values.clone(),
+            context_value,
+            rollout,
+            result,
+        }
     }
+}
 
-    Ok(Arc::new(move |context: &Context| {
-        stickiness_resolvers
-            .iter()
-            .find_map(|resolver| resolver(context))
-    }))
+trait Invertible {
+    fn invert(&self, inverted: bool) -> bool;
 }
 
-fn context_property(node: Pairs<Rule>) -> CompileResult<ContextResolver> {
-    let [content_node] = drain(node)?;
-    let mut chars = content_node.as_str().chars();
-    chars.next();
-    chars.next_back();
-    let context_name = chars.as_str().to_string();
+impl Invertible for bool {
+    fn invert(&self, inverted: bool) -> bool {
+        if inverted {
+            !self
+        } else {
+            *self
+        }
+    }
+}
 
-    Ok(Arc::new(move |context: &Context| -> Option<Cow<'_, str>> {
-        context
-            .properties
-            .as_ref()?
-            .get(context_name.as_str())
-            .map(Cow::Borrowed)
-    }))
+// Parsing - these lift the pest pairs into the rule AST
+fn context_field(node: Pairs<Rule>) -> CompileResult<ContextField> {
+    let [child] = drain(node)?;
+
+    Ok(match child.as_rule() {
+        Rule::user_id => ContextField::UserId,
+        Rule::app_name => ContextField::AppName,
+        Rule::environment => ContextField::Environment,
+        Rule::session_id => ContextField::SessionId,
+        Rule::remote_address => ContextField::RemoteAddress,
+        Rule::current_time => ContextField::CurrentTime,
+        Rule::random => ContextField::Random(
+            child
+                .into_inner()
+                .next()
+                .and_then(|rule| rule.as_str().parse::<usize>().ok()),
+        ),
+        Rule::property => {
+            let [content_node] = drain(child.into_inner())?;
+            ContextField::Property(string(content_node)?)
+        }
+        _ => unreachable!(),
+    })
+}
+
+fn stickiness_param(node: Pairs<Rule>) -> CompileResult<Vec<ContextField>> {
+    node.map(|child| context_field(child.into_inner()))
+        .collect()
 }
 
 fn to_ordinal_comparator(node: Pair<Rule>) -> OrdinalComparator {
@@ -204,40 +358,24 @@ fn to_ordinal_comparator(node: Pair<Rule>) -> OrdinalComparator {
     }
 }
 
-fn to_content_comparator(node: Pair<Rule>) -> ContentComparator {
+fn to_content_comparator(node: Pair<Rule>) -> (ContentComparator, bool) {
     match node.as_str() {
-        "in" => ContentComparator::In,
-        "not_in" => ContentComparator::NotIn,
+        "in" => (ContentComparator::In, false),
+        "not_in" => (ContentComparator::NotIn, false),
+        "in_ignore_case" => (ContentComparator::In, true),
+        "not_in_ignore_case" => (ContentComparator::NotIn, true),
         _ => unreachable!(),
     }
 }
 
-fn to_string_comparator(node: Pair<Rule>) -> StringComparatorType {
+fn to_string_comparator(node: Pair<Rule>) -> (StringComparator, bool) {
     match node.as_str() {
-        "starts_with_any" => StringComparatorType {
-            ignore_case: false,
-            comparator_type: StringComparator::StartsWith,
-        },
-        "ends_with_any" => StringComparatorType {
-            ignore_case: false,
-            comparator_type: StringComparator::EndsWith,
-        },
-        "contains_any" => StringComparatorType {
-            ignore_case: false,
-            comparator_type: StringComparator::Contains,
-        },
-        "starts_with_any_ignore_case" => StringComparatorType {
-            ignore_case: true,
-            comparator_type: StringComparator::StartsWith,
-        },
-        "ends_with_any_ignore_case" => StringComparatorType {
-            ignore_case: true,
-            comparator_type: StringComparator::EndsWith,
-        },
-        "contains_any_ignore_case" => StringComparatorType {
-            ignore_case: true,
-            comparator_type: StringComparator::Contains,
-        },
+        "starts_with_any" => (StringComparator::StartsWith, false),
+        "ends_with_any" => (StringComparator::EndsWith, false),
+        "contains_any" => (StringComparator::Contains, false),
+        "starts_with_any_ignore_case" => (StringComparator::StartsWith, true),
+        "ends_with_any_ignore_case" => (StringComparator::EndsWith, true),
+        "contains_any_ignore_case" => (StringComparator::Contains, true),
         _ => unreachable!(),
     }
 }
@@ -263,147 +401,461 @@ fn semver(node: Pair<Rule>) -> CompileResult<Version> {
     })
 }
 
-fn percentage(node: Pair<Rule>) -> CompileResult<u8> {
-    let mut chars = node.as_str().chars();
-    chars.next_back();
-    let value = chars.as_str();
-    value.parse::<u8>().map_err(|e| {
+fn semver_requirement(node: Pair<Rule>) -> CompileResult<VersionReq> {
+    let value = node.as_str();
+    let comparators: Vec<Pair<Rule>> = node.into_inner().collect();
+    // semver reads a bare version as a caret range, ours match exactly
+    let requirement = match comparators.as_slice() {
+        [version] if version.as_rule() == Rule::semver => format!("={}", version.as_str()),
+        _ => comparators
+            .iter()
+            .map(Pair::as_str)
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    VersionReq::parse(&requirement).map_err(|e| {
         SdkError::StrategyParseError(format!(
-            "Failed to compile {value} as a percentage value: {e}"
+            "Failed to compile {value} as a semver requirement: {e}"
         ))
     })
 }
 
+fn percentage(node: Pair<Rule>) -> CompileResult<Percentage> {
+    let mut chars = node.as_str().chars();
+    chars.next_back();
+    chars.as_str().parse()
+}
+
 fn group_id_param(node: Pairs<Rule>) -> CompileResult<String> {
     let [content_node] = drain(node)?;
-    Ok(string(content_node))
+    string(content_node)
 }
 
-fn string(node: Pair<Rule>) -> String {
+// The grammar only lets valid escapes through, so all that can still go wrong here is
+// a \u escape for half of a surrogate pair that's missing its other half
+fn string(node: Pair<Rule>) -> CompileResult<String> {
     let mut chars = node.as_str().chars();
     chars.next();
     chars.next_back();
-    let string: String = chars.as_str().into();
-    string.replace("\\\"", "\"")
+    let raw = chars.as_str();
+
+    let mut string = String::with_capacity(raw.len());
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => string.push('\u{8}'),
+            Some('f') => string.push('\u{c}'),
+            Some('n') => string.push('\n'),
+            Some('r') => string.push('\r'),
+            Some('t') => string.push('\t'),
+            Some('u') => {
+                let leading = utf16_unit(&mut chars);
+                let mut units = vec![leading];
+                if (0xD800..0xDC00).contains(&leading) && chars.as_str().starts_with("\\u") {
+                    chars.nth(1);
+                    units.push(utf16_unit(&mut chars));
+                }
+                for c in char::decode_utf16(units) {
+                    string.push(c.map_err(|e| {
+                        SdkError::StrategyParseError(format!(
+                            "Failed to compile \"{raw}\" as a string: {e}"
+                        ))
+                    })?);
+                }
+            }
+            Some(c) => string.push(c),
+            None => {}
+        }
+    }
+    Ok(string)
 }
 
-fn ip(node: Pair<Rule>) -> Result<IpNetwork, IpNetworkError> {
-    IpNetwork::from_str(&string(node))
+fn utf16_unit(chars: &mut std::str::Chars) -> u16 {
+    let hex: String = chars.take(4).collect();
+    u16::from_str_radix(&hex, 16).unwrap_or_default()
 }
 
-//Constraints
-fn numeric_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [context_getter, ordinal_operation, number] = drain(node)?;
-
-    let context_getter = context_value(context_getter.into_inner())?;
-    let ordinal_operation = to_ordinal_comparator(ordinal_operation);
-    let number = numeric(number)?;
-
-    Ok(Box::new(move |context: &Context| {
-        let context_value = context_getter(context);
-        match context_value {
-            Some(context_value) => {
-                let Ok(context_value) = context_value.parse::<f64>() else {
-                    return false;
-                };
+fn harvest_string_list(node: Pairs<Rule>) -> CompileResult<Vec<String>> {
+    node.into_iter().map(string).collect()
+}
 
-                match ordinal_operation {
-                    OrdinalComparator::Lte => context_value <= number,
-                    OrdinalComparator::Lt => context_value < number,
-                    OrdinalComparator::Gte => context_value >= number,
-                    OrdinalComparator::Gt => context_value > number,
-                    OrdinalComparator::Eq => (context_value - number).abs() < f64::EPSILON,
-                }
+fn harvest_list(node: Pairs<Rule>) -> CompileResult<Vec<f64>> {
+    let nodes: Result<Vec<f64>, ParseFloatError> =
+        node.into_iter().map(|x| x.as_str().parse()).collect();
+
+    nodes.map_err(|e| {
+        SdkError::StrategyParseError(format!("Failed to compile list as a numeric list: {e}"))
+    })
+}
+
+fn list_values(node: Pair<Rule>) -> CompileResult<ListValues> {
+    Ok(match node.as_rule() {
+        Rule::empty_list => ListValues::Empty,
+        Rule::numeric_list => ListValues::Numeric(harvest_list(node.into_inner())?),
+        Rule::string_list => ListValues::Strings(harvest_string_list(node.into_inner())?),
+        Rule::semver_list => ListValues::Semver(
+            node.into_inner()
+                .map(semver_requirement)
+                .collect::<CompileResult<_>>()?,
+        ),
+        _ => unreachable!(),
+    })
+}
+
+fn rollout(node: Pairs<Rule>) -> CompileResult<ConstraintKind> {
+    let ([percentage_node], node) = drain_partial(node)?;
+
+    let mut stickiness = vec![];
+    let mut group_id = None;
+    for child in node {
+        match child.as_rule() {
+            Rule::stickiness_param => stickiness = stickiness_param(child.into_inner())?,
+            Rule::group_id_param => group_id = Some(group_id_param(child.into_inner())?),
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(ConstraintKind::Rollout {
+        percentage: percentage(percentage_node)?,
+        stickiness,
+        group_id,
+    })
+}
+
+fn constraint_kind(node: Pair<Rule>) -> CompileResult<ConstraintKind> {
+    Ok(match node.as_rule() {
+        Rule::date_constraint => {
+            let [context_node, ordinal_node, date_node] = drain(node.into_inner())?;
+            ConstraintKind::Date {
+                context: context_field(context_node.into_inner())?,
+                comparator: to_ordinal_comparator(ordinal_node),
+                value: date(date_node)?,
+            }
+        }
+        Rule::numeric_constraint => {
+            let [context_node, ordinal_node, number_node] = drain(node.into_inner())?;
+            ConstraintKind::Numeric {
+                context: context_field(context_node.into_inner())?,
+                comparator: to_ordinal_comparator(ordinal_node),
+                value: numeric(number_node)?,
+            }
+        }
+        Rule::semver_constraint => {
+            let [context_node, ordinal_node, semver_node] = drain(node.into_inner())?;
+            ConstraintKind::Semver {
+                context: context_field(context_node.into_inner())?,
+                comparator: to_ordinal_comparator(ordinal_node),
+                value: semver(semver_node)?,
+            }
+        }
+        Rule::regex_constraint => {
+            let [context_node, operation_node, pattern_node] = drain(node.into_inner())?;
+            ConstraintKind::Regex {
+                context: context_field(context_node.into_inner())?,
+                ignore_case: operation_node.as_str() == "matches_regex_ignoring_case",
+                pattern: string(pattern_node)?,
+            }
+        }
+        Rule::string_fragment_constraint => {
+            let [context_node, comparator_node, list_node] = drain(node.into_inner())?;
+            let (comparator, ignore_case) = to_string_comparator(comparator_node);
+            ConstraintKind::StringFragment {
+                context: context_field(context_node.into_inner())?,
+                comparator,
+                ignore_case,
+                values: harvest_string_list(list_node.into_inner())?,
+            }
+        }
+        Rule::list_constraint => {
+            let [context_node, comparator_node, list_node] = drain(node.into_inner())?;
+            let (comparator, ignore_case) = to_content_comparator(comparator_node);
+            ConstraintKind::List {
+                context: context_field(context_node.into_inner())?,
+                comparator,
+                ignore_case,
+                values: list_values(list_node)?,
+            }
+        }
+        Rule::hostname_constraint => {
+            let [list_node] = drain(node.into_inner())?;
+            ConstraintKind::Hostname {
+                hostnames: harvest_string_list(list_node.into_inner())?,
+            }
+        }
+        Rule::ip_constraint => {
+            let [context_node, list_node] = drain(node.into_inner())?;
+            ConstraintKind::Ip {
+                context: context_field(context_node.into_inner())?,
+                ranges: harvest_string_list(list_node.into_inner())?,
             }
-            None => false,
         }
-    }))
+        Rule::rollout_constraint => rollout(node.into_inner())?,
+        Rule::default_strategy_constraint => {
+            let value = node.as_str();
+            ConstraintKind::Default(value.parse().map_err(|e| {
+                SdkError::StrategyParseError(format!(
+                    "Failed to compile {value} as a boolean value: {e}"
+                ))
+            })?)
+        }
+        Rule::external_value => {
+            let [index_node] = drain(node.into_inner())?;
+            ConstraintKind::ExternalValue(string(index_node)?)
+        }
+        _ => unreachable!(),
+    })
 }
 
-fn date_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [context_getter_node, ordinal_operation_node, date_node] = drain(node)?;
+fn expression(node: Pairs<Rule>) -> CompileResult<RuleAst> {
+    PRATT_PARSER
+        .map_primary(|primary| match primary.as_rule() {
+            Rule::constraint => {
+                let [kind] = drain(primary.into_inner())?;
+                Ok(RuleAst::constraint(constraint_kind(kind)?))
+            }
+            Rule::expr => expression(primary.into_inner()),
+            _ => unreachable!(),
+        })
+        .map_prefix(|op, rule| match op.as_rule() {
+            Rule::invert_operation => Ok(!rule?),
+            _ => unreachable!(),
+        })
+        .map_infix(|lhs, op, rhs| match op.as_rule() {
+            Rule::and => Ok(RuleAst::and(lhs?, rhs?)),
+            Rule::or => Ok(RuleAst::or(lhs?, rhs?)),
+            _ => unreachable!(),
+        })
+        .parse(node)
+}
 
-    let context_getter = context_value(context_getter_node.into_inner())?;
-    let ordinal_operation = to_ordinal_comparator(ordinal_operation_node);
-    let date = date(date_node)?;
+pub fn parse_rule(rule: &str) -> CompileResult<RuleAst> {
+    let nodes = Strategy::parse(Rule::strategy, rule)
+        .map_err(|e| SdkError::StrategyParseError(format!("Failed to parse rule {rule}: {e}")))?;
 
-    Ok(Box::new(move |context: &Context| {
-        let context_value = context_getter(context);
-        match context_value {
-            Some(context_value) => {
-                let context_value = context_value.parse::<DateTime<Utc>>();
+    let [strategy_node] = drain(nodes)?;
+    expression(strategy_node.into_inner())
+}
 
-                let Ok(context_value) = context_value else {
-                    return false;
-                };
+// Literal values handed to us outside of the DSL still have to satisfy the grammar,
+// otherwise rules built directly from strategies would accept values that the same
+// rule written as text rejects
+fn literal(rule: Rule, value: &str) -> CompileResult<Pair<'_, Rule>> {
+    let value = value.trim();
+    Strategy::parse(rule, value)
+        .ok()
+        .and_then(|mut nodes| nodes.next())
+        .filter(|node| node.as_str() == value)
+        .ok_or_else(|| {
+            SdkError::StrategyParseError(format!("Failed to parse {value} as a {rule:?} value"))
+        })
+}
 
-                match ordinal_operation {
-                    OrdinalComparator::Lte => context_value <= date,
-                    OrdinalComparator::Lt => context_value < date,
-                    OrdinalComparator::Gte => context_value >= date,
-                    OrdinalComparator::Gt => context_value > date,
-                    OrdinalComparator::Eq => context_value == date,
-                }
-            }
-            None => false,
+pub(crate) fn numeric_literal(value: &str) -> CompileResult<f64> {
+    numeric(literal(Rule::num, value)?)
+}
+
+pub(crate) fn date_literal(value: &str) -> CompileResult<DateTime<Utc>> {
+    date(literal(Rule::date, value)?)
+}
+
+pub(crate) fn semver_literal(value: &str) -> CompileResult<Version> {
+    semver(literal(Rule::semver, value)?)
+}
+
+//Context lifting properties - these resolve properties from the context
+fn context_value(field: &ContextField) -> ContextResolver {
+    let field = field.clone();
+    Arc::new(move |context: &Context| field_value(&field, context))
+}
+
+fn field_value<'a>(field: &ContextField, context: &'a Context) -> Option<Cow<'a, str>> {
+    match field {
+        ContextField::UserId => context.user_id.map(Cow::Borrowed),
+        ContextField::AppName => context.app_name.map(Cow::Borrowed),
+        ContextField::Environment => context.environment.map(Cow::Borrowed),
+        ContextField::SessionId => context.session_id.map(Cow::Borrowed),
+        ContextField::RemoteAddress => context.remote_address.map(Cow::Borrowed),
+
+        ContextField::CurrentTime => context.current_time.map(Cow::Borrowed).or_else(|| {
+            #[cfg(feature = "wall-clock")]
+            let clock = Some(context.clock.unwrap_or(&SystemClock));
+            #[cfg(not(feature = "wall-clock"))]
+            let clock = context.clock;
+            Some(Cow::Owned(
+                clock?.now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            ))
+        }),
+
+        // Random values run from 1 up to but not including the maximum
+        ContextField::Random(max) => {
+            let max = max.map_or(100, |max| u32::try_from(max).unwrap_or(u32::MAX));
+            let value = context
+                .random_source
+                .unwrap_or(&ThreadRandom)
+                .random_range(1..=max.saturating_sub(1).max(1));
+            Some(Cow::Owned(value.to_string()))
         }
-    }))
+
+        ContextField::Property(context_name) => context
+            .properties
+            .as_ref()?
+            .get(context_name.as_str())
+            .map(Cow::Borrowed),
+    }
 }
 
-fn semver_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let children: [Pair<Rule>; 3] = drain(node)?;
-    let [context_getter_node, ordinal_operation_node, semver_node] = children;
+// Parses a field as T, through the context's parsed values when it carries them.
+// Random values change on every read, so they're never cached
+fn typed_value<T: ParsedValue + 'static>(field: &ContextField) -> TypedResolver<T> {
+    let context_getter = context_value(field);
+    let parse = move |context: &Context| T::parse(&context_getter(context)?);
+    if matches!(field, ContextField::Random(_)) {
+        return Arc::new(parse);
+    }
+    let field = field.clone();
+    Arc::new(move |context: &Context| match context.parsed_values {
+        Some(parsed_values) => parsed_values.get_or_parse(&field, || parse(context)),
+        None => parse(context),
+    })
+}
 
-    let context_getter = context_value(context_getter_node.into_inner())?;
-    let ordinal_operation = to_ordinal_comparator(ordinal_operation_node);
-    let semver = semver(semver_node)?;
+pub(crate) fn coalesce_context_property(fields: &[ContextField]) -> ContextResolver {
+    let fields = fields.to_vec();
+    Arc::new(move |context: &Context| first_field_value(&fields, context))
+}
 
-    Ok(Box::new(move |context: &Context| {
-        let context_value = context_getter(context);
+/// The value of the first field in a stickiness chain that has one. Rollouts and
+/// variants both pick their stickiness through here
+pub(crate) fn first_field_value<'a>(
+    fields: &[ContextField],
+    context: &'a Context,
+) -> Option<Cow<'a, str>> {
+    fields.iter().find_map(|field| field_value(field, context))
+}
 
-        match context_value {
-            Some(context_value) => {
-                let context_value = context_value.parse::<Version>();
+// Only properties can hold more than one value, every other field has at most one
+fn context_values(field: &ContextField) -> ContextValuesResolver {
+    match field {
+        ContextField::Property(context_name) => {
+            let context_name = context_name.clone();
+            Arc::new(move |context: &Context| {
+                context
+                    .properties
+                    .as_ref()?
+                    .get_values(context_name.as_str())
+            })
+        }
+        field => {
+            let context_getter = context_value(field);
+            Arc::new(move |context: &Context| context_getter(context).map(ContextValues::One))
+        }
+    }
+}
 
-                let Ok(context_value) = context_value else {
-                    return false;
-                };
+// What traces report as the context value, which lists all the values of a property
+fn context_display(field: &ContextField) -> ContextResolver {
+    let context_getter = context_values(field);
+    Arc::new(move |context: &Context| match context_getter(context)? {
+        ContextValues::One(value) => Some(value),
+        ContextValues::Many(values) => Some(Cow::Owned(values.join(", "))),
+    })
+}
 
-                let ord = context_value.cmp_precedence(&semver);
+fn hostname_resolver() -> ContextResolver {
+    Arc::new(|context: &Context| get_hostname(context).ok().map(Cow::Owned))
+}
 
-                match ordinal_operation {
-                    OrdinalComparator::Lte => ord.is_le(),
-                    OrdinalComparator::Lt => ord.is_lt(),
-                    OrdinalComparator::Gte => ord.is_ge(),
-                    OrdinalComparator::Gt => ord.is_gt(),
-                    OrdinalComparator::Eq => ord.is_eq(),
-                }
-            }
-            None => false,
+fn external_value_resolver(key: String) -> ContextResolver {
+    Arc::new(move |context: &Context| {
+        context
+            .external_results
+            .as_ref()
+            .and_then(|results| results.get(&key))
+            .map(|result| Cow::Owned(result.to_string()))
+    })
+}
+
+//Constraints
+fn numeric_constraint(
+    context_getter: TypedResolver<f64>,
+    ordinal_operation: OrdinalComparator,
+    number: f64,
+) -> RuleFragment {
+    Box::new(move |context: &Context| {
+        let Some(context_value) = context_getter(context) else {
+            return false;
+        };
+
+        match ordinal_operation {
+            OrdinalComparator::Lte => context_value <= number,
+            OrdinalComparator::Lt => context_value < number,
+            OrdinalComparator::Gte => context_value >= number,
+            OrdinalComparator::Gt => context_value > number,
+            OrdinalComparator::Eq => (context_value - number).abs() < f64::EPSILON,
         }
-    }))
+    })
 }
 
-fn rollout_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let (children, mut node) = drain_partial(node)?;
-    let [rollout_node, stickiness_node] = children;
+fn date_constraint(
+    context_getter: TypedResolver<DateTime<Utc>>,
+    ordinal_operation: OrdinalComparator,
+    date: DateTime<Utc>,
+) -> RuleFragment {
+    Box::new(move |context: &Context| {
+        let Some(context_value) = context_getter(context) else {
+            return false;
+        };
 
-    let percent_rollout = percentage(rollout_node)?;
+        match ordinal_operation {
+            OrdinalComparator::Lte => context_value <= date,
+            OrdinalComparator::Lt => context_value < date,
+            OrdinalComparator::Gte => context_value >= date,
+            OrdinalComparator::Gt => context_value > date,
+            OrdinalComparator::Eq => context_value == date,
+        }
+    })
+}
+
+fn semver_constraint(
+    context_getter: TypedResolver<Version>,
+    ordinal_operation: OrdinalComparator,
+    semver: Version,
+) -> RuleFragment {
+    Box::new(move |context: &Context| {
+        let Some(context_value) = context_getter(context) else {
+            return false;
+        };
 
-    let stickiness_resolver = coalesce_context_property(stickiness_node.into_inner())?;
-    let group_id = node
-        .next()
-        .map(|node| group_id_param(node.into_inner()))
-        .transpose()?;
+        let ord = context_value.cmp_precedence(&semver);
 
-    Ok(Box::new(move |context: &Context| {
+        match ordinal_operation {
+            OrdinalComparator::Lte => ord.is_le(),
+            OrdinalComparator::Lt => ord.is_lt(),
+            OrdinalComparator::Gte => ord.is_ge(),
+            OrdinalComparator::Gt => ord.is_gt(),
+            OrdinalComparator::Eq => ord.is_eq(),
+        }
+    })
+}
+
+fn rollout_constraint(details: RolloutDetails) -> RuleFragment {
+    let RolloutDetails {
+        percentage: percent_rollout,
+        stickiness_resolver,
+        group_id,
+    } = details;
+
+    Box::new(move |context: &Context| {
         if let Some(stickiness) = stickiness_resolver(context) {
             let group_id = group_id.as_deref().unwrap_or(context.toggle_name);
 
-            let hash = normalized_hash(group_id, &stickiness, 100, 0);
+            let hash = normalized_fine_hash(group_id, &stickiness, 0);
 
             if let Ok(hash) = hash {
-                hash <= percent_rollout.into()
+                hash <= percent_rollout.hundredths().into()
             } else {
                 // This should probably never occur, it only happens if we
                 // don't feed enough input to the hashing function
@@ -412,7 +864,7 @@ fn rollout_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
         } else {
             false
         }
-    }))
+    })
 }
 
 #[cfg(feature = "hostname")]
@@ -441,253 +893,350 @@ fn get_hostname(context: &Context) -> CompileResult<String> {
     ))
 }
 
-fn hostname_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [hostname_node] = drain(node)?;
+fn hostname_constraint(hostnames: &[String]) -> RuleFragment {
+    let target_hostnames: HashSet<String> = hostnames.iter().map(|x| x.to_lowercase()).collect();
 
-    let target_hostnames: HashSet<String> = harvest_string_list(hostname_node.into_inner())
-        .iter()
-        .map(|x| x.to_lowercase())
-        .collect();
-
-    Ok(Box::new(move |context: &Context| {
-        match get_hostname(context) {
-            Ok(hostname) => target_hostnames.contains(&hostname.to_lowercase()),
-            Err(_) => false,
-        }
-    }))
+    Box::new(move |context: &Context| match get_hostname(context) {
+        Ok(hostname) => target_hostnames.contains(&hostname.to_lowercase()),
+        Err(_) => false,
+    })
 }
 
-fn ip_matching_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [context_node, ip_node] = drain(node)?;
+fn ip_matching_constraint(
+    context_getter: TypedResolver<IpAddr>,
+    ranges: &[String],
+) -> RuleFragment {
+    let ip_list = harvest_ip_list(ranges);
 
-    let context_getter = context_value(context_node.into_inner())?;
-    let ip_list = harvest_ip_list(ip_node.into_inner());
-
-    Ok(Box::new(move |context| {
-        if let Some(context_value) = context_getter(context) {
-            if let Ok(context_ip) = context_value.parse::<IpAddr>() {
-                return ip_list.iter().any(|range| range.contains(context_ip));
-            }
-        }
-        false
-    }))
+    Box::new(move |context| {
+        context_getter(context)
+            .is_some_and(|context_ip| ip_list.iter().any(|range| range.contains(context_ip)))
+    })
 }
 
-fn list_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [context_node, comparator_node, list_node] = drain(node)?;
-
-    let context_getter = context_value(context_node.into_inner())?;
-    let comparator = to_content_comparator(comparator_node);
-    let list = list_node;
+// Typed lists only match when every context value parses, in the same way that a
+// single value that doesn't parse matches neither in nor not_in
+fn any_parsed<T: FromStr>(
+    context_values: &ContextValues,
+    matches: impl Fn(&T) -> bool,
+) -> Option<bool> {
+    context_values.iter().try_fold(false, |matched, value| {
+        let value = value.parse::<T>().ok()?;
+        Some(matched || matches(&value))
+    })
+}
 
-    Ok(match list.as_rule() {
-        Rule::empty_list => Box::new(move |_context: &Context| match comparator {
-            ContentComparator::In => false,
-            ContentComparator::NotIn => true,
-        }),
-        Rule::numeric_list => {
-            let values = harvest_list(list.into_inner())?;
+fn list_constraint(
+    context_getter: ContextValuesResolver,
+    comparator: ContentComparator,
+    ignore_case: bool,
+    list: &ListValues,
+) -> RuleFragment {
+    let compare = move |matched: bool| match comparator {
+        ContentComparator::In => matched,
+        ContentComparator::NotIn => !matched,
+    };
+    match list {
+        ListValues::Empty => Box::new(move |_context: &Context| compare(false)),
+        ListValues::Numeric(values) => {
+            let values = values.clone();
             Box::new(move |context: &Context| {
-                let context_value = context_getter(context);
-                match context_value {
-                    Some(context_value) => {
-                        let Ok(context_value) = context_value.parse::<f64>() else {
-                            return false;
-                        };
-                        match comparator {
-                            ContentComparator::In => values.contains(&context_value),
-                            ContentComparator::NotIn => !values.contains(&context_value),
-                        }
-                    }
-                    None => false,
-                }
+                context_getter(context)
+                    .and_then(|context_values| {
+                        any_parsed(&context_values, |value: &f64| values.contains(value))
+                    })
+                    .is_some_and(compare)
             })
         }
-        Rule::string_list => {
-            let values = harvest_set(list.into_inner());
+        ListValues::Semver(requirements) => {
+            let requirements = requirements.clone();
             Box::new(move |context: &Context| {
-                let context_value = context_getter(context);
-
-                match comparator {
-                    ContentComparator::In => match context_value {
-                        Some(context_value) => values.contains(context_value.as_ref()),
-                        None => false,
-                    },
-                    ContentComparator::NotIn => match context_value {
-                        Some(context_value) => !values.contains(context_value.as_ref()),
-                        None => true,
-                    },
-                }
+                context_getter(context)
+                    .and_then(|context_values| {
+                        any_parsed(&context_values, |value: &Version| {
+                            requirements
+                                .iter()
+                                .any(|requirement| requirement.matches(value))
+                        })
+                    })
+                    .is_some_and(compare)
             })
         }
-        _ => unreachable!(),
-    })
+        ListValues::Strings(values) => {
+            let values: HashSet<String> = if ignore_case {
+                values.iter().map(|value| value.to_lowercase()).collect()
+            } else {
+                values.iter().cloned().collect()
+            };
+            Box::new(move |context: &Context| {
+                // A missing value isn't in any list
+                let Some(context_values) = context_getter(context) else {
+                    return compare(false);
+                };
+                let matched = context_values.iter().any(|value| {
+                    if ignore_case {
+                        values.contains(&value.to_lowercase())
+                    } else {
+                        values.contains(value)
+                    }
+                });
+                compare(matched)
+            })
+        }
+    }
 }
 
-fn external_value(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [index_node] = drain(node)?;
-    let strategy_index = string(index_node);
-    Ok(Box::new(move |context| {
+fn external_value(strategy_index: String) -> RuleFragment {
+    Box::new(move |context| {
         context
             .external_results
             .as_ref()
             .and_then(|strategy_results| strategy_results.get(&strategy_index))
             .unwrap_or(false)
-    }))
-}
-
-fn harvest_set(node: Pairs<Rule>) -> HashSet<String> {
-    node.into_iter().map(string).collect::<HashSet<String>>()
-}
-
-fn harvest_string_list(node: Pairs<Rule>) -> Vec<String> {
-    node.into_iter().map(string).collect::<Vec<String>>()
-}
-
-fn harvest_list(node: Pairs<Rule>) -> CompileResult<Vec<f64>> {
-    let nodes: Result<Vec<f64>, ParseFloatError> =
-        node.into_iter().map(|x| x.as_str().parse()).collect();
-
-    nodes.map_err(|e| {
-        SdkError::StrategyParseError(format!("Failed to compile list as a numeric list: {e}"))
     })
 }
 
-fn harvest_ip_list(node: Pairs<Rule>) -> Vec<IpNetwork> {
-    node.into_iter().filter_map(|n| ip(n).ok()).collect()
+fn custom_strategy_constraint(
+    strategy: Arc<dyn CustomStrategy>,
+    parameters: HashMap<String, String>,
+) -> RuleFragment {
+    Box::new(move |context| strategy.is_enabled(&parameters, context))
 }
 
-fn default_strategy_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let value = node.as_str();
-    let enabled: bool = value.parse().map_err(|e| {
-        SdkError::StrategyParseError(format!("Failed to compile {value} as a boolean value: {e}"))
-    })?;
-    Ok(Box::new(move |_: &Context| enabled))
+fn harvest_ip_list(ranges: &[String]) -> Vec<IpNetwork> {
+    ranges
+        .iter()
+        .filter_map(|range| IpNetwork::from_str(range).ok())
+        .collect()
 }
 
-fn string_fragment_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [context_getter_node, comparator_node, list_node] = drain(node)?;
-
-    let context_getter = context_value(context_getter_node.into_inner())?;
-    let comparator_details = to_string_comparator(comparator_node);
-    let comparator = comparator_details.comparator_type;
-    let ignore_case = comparator_details.ignore_case;
-
-    let mut list = harvest_string_list(list_node.into_inner());
+fn default_strategy_constraint(enabled: bool) -> RuleFragment {
+    Box::new(move |_: &Context| enabled)
+}
 
-    if ignore_case {
-        list = list.into_iter().map(|item| item.to_lowercase()).collect();
+fn string_fragment_constraint(
+    context_getter: ContextValuesResolver,
+    comparator: StringComparator,
+    ignore_case: bool,
+    list: &[String],
+) -> RuleFragment {
+    let list: Vec<String> = if ignore_case {
+        list.iter().map(|item| item.to_lowercase()).collect()
+    } else {
+        list.to_vec()
     };
 
-    Ok(Box::new(move |context: &Context| {
-        let mut value = context_getter(context);
-        if ignore_case {
-            value = value.map(|v| Cow::Owned(v.to_lowercase()));
-        }
-        if let Some(value) = value {
+    Box::new(move |context: &Context| {
+        let Some(values) = context_getter(context) else {
+            return false;
+        };
+        let matched = values.iter().any(|value| {
+            let value = if ignore_case {
+                Cow::Owned(value.to_lowercase())
+            } else {
+                Cow::Borrowed(value)
+            };
             match comparator {
                 StringComparator::Contains => list.iter().any(|item| value.contains(item)),
                 StringComparator::StartsWith => list.iter().any(|item| value.starts_with(item)),
                 StringComparator::EndsWith => list.iter().any(|item| value.ends_with(item)),
             }
-        } else {
-            false
-        }
-    }))
+        });
+        matched
+    })
 }
 
-fn regex_constraint(node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let [context_getter_node, constraint_type_node, regex_pattern_node] = drain(node)?;
-    let context_getter = context_value(context_getter_node.into_inner())?;
-    let regex_pattern = string(regex_pattern_node);
-
-    let mut regex_builder = RegexBuilder::new(&regex_pattern);
+fn regex_constraint(
+    context_getter: ContextValuesResolver,
+    ignore_case: bool,
+    regex_pattern: &str,
+) -> RuleFragment {
+    let mut regex_builder = RegexBuilder::new(regex_pattern);
 
-    if constraint_type_node.as_str() == "matches_regex_ignoring_case" {
+    if ignore_case {
         regex_builder.case_insensitive(true);
     }
 
     let Ok(regex) = regex_builder.build() else {
-        return Ok(Box::new(move |_context: &Context| false));
+        return Box::new(move |_context: &Context| false);
     };
 
-    Ok(Box::new(move |context: &Context| {
-        if let Some(value) = context_getter(context) {
-            regex.is_match(value.as_ref())
-        } else {
-            false
+    Box::new(move |context: &Context| {
+        context_getter(context)
+            .is_some_and(|values| values.iter().any(|value| regex.is_match(value)))
+    })
+}
+
+fn compile_constraint(constraint: &ConstraintAst, rule: String) -> CompileResult<ConstraintNode> {
+    let kind = &constraint.kind;
+    let context_getter = match kind {
+        ConstraintKind::Hostname { .. } => Some(hostname_resolver()),
+        ConstraintKind::ExternalValue(key) => Some(external_value_resolver(key.clone())),
+        _ => kind.context().map(context_display),
+    };
+    let mut rollout = None;
+
+    let fragment = match kind {
+        ConstraintKind::Date {
+            context,
+            comparator,
+            value,
+        } => date_constraint(typed_value(context), *comparator, *value),
+        ConstraintKind::Numeric {
+            context,
+            comparator,
+            value,
+        } => numeric_constraint(typed_value(context), *comparator, *value),
+        ConstraintKind::Semver {
+            context,
+            comparator,
+            value,
+        } => semver_constraint(typed_value(context), *comparator, value.clone()),
+        ConstraintKind::Regex {
+            context,
+            ignore_case,
+            pattern,
+        } => regex_constraint(context_values(context), *ignore_case, pattern),
+        ConstraintKind::Rollout {
+            percentage,
+            stickiness,
+            group_id,
+        } => {
+            if stickiness.is_empty() {
+                return Err(SdkError::StrategyParseError(
+                    "Rollout constraints require a stickiness parameter".into(),
+                ));
+            }
+            let details = RolloutDetails {
+                percentage: *percentage,
+                stickiness_resolver: coalesce_context_property(stickiness),
+                group_id: group_id.clone(),
+            };
+            rollout = Some(details.clone());
+            rollout_constraint(details)
         }
-    }))
+        ConstraintKind::Default(enabled) => default_strategy_constraint(*enabled),
+        ConstraintKind::StringFragment {
+            context,
+            comparator,
+            ignore_case,
+            values,
+        } => string_fragment_constraint(context_values(context), *comparator, *ignore_case, values),
+        ConstraintKind::List {
+            context,
+            comparator,
+            ignore_case,
+            values,
+        } => list_constraint(context_values(context), *comparator, *ignore_case, values),
+        ConstraintKind::Hostname { hostnames } => hostname_constraint(hostnames),
+        ConstraintKind::ExternalValue(key) => external_value(key.clone()),
+        ConstraintKind::Custom {
+            strategy,
+            parameters,
+            ..
+        } => custom_strategy_constraint(strategy.clone(), parameters.clone()),
+        ConstraintKind::Ip { context, ranges } => {
+            ip_matching_constraint(typed_value(context), ranges)
+        }
+    };
+
+    Ok(ConstraintNode {
+        rule,
+        inverted: constraint.inverted,
+        operator: kind.operator(),
+        values: kind.values(),
+        context_getter,
+        rollout,
+        fragment,
+    })
 }
 
-fn constraint(mut node: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    let first = node.next();
-    let second = node.next();
+/// Compiled constraints keyed by their canonical text. Rules compiled through the
+/// same cache share a single compiled node for every identical constraint, which
+/// matters when many states carry the same segments. Entries are weak, so a
+/// constraint is freed once no compiled rule uses it anymore
+#[derive(Default)]
+pub struct ConstraintCache {
+    constraints: HashMap<String, Weak<ConstraintNode>>,
+}
 
-    let (inverted, child) = match (first, second) {
-        (Some(_), Some(second)) => (true, second),
-        (Some(first), None) => (false, first),
-        _ => unreachable!(),
-    };
+impl ConstraintCache {
+    /// The number of distinct compiled constraints still in use
+    pub fn len(&self) -> usize {
+        self.constraints
+            .values()
+            .filter(|constraint| constraint.strong_count() > 0)
+            .count()
+    }
 
-    let constraint = match child.as_rule() {
-        Rule::date_constraint => date_constraint(child.into_inner()),
-        Rule::numeric_constraint => numeric_constraint(child.into_inner()),
-        Rule::regex_constraint => regex_constraint(child.into_inner()),
-        Rule::semver_constraint => semver_constraint(child.into_inner()),
-        Rule::rollout_constraint => rollout_constraint(child.into_inner()), //TODO: Do we need to support inversion here?
-        Rule::default_strategy_constraint => default_strategy_constraint(child.into_inner()),
-        Rule::string_fragment_constraint => string_fragment_constraint(child.into_inner()),
-        Rule::list_constraint => list_constraint(child.into_inner()),
-        Rule::hostname_constraint => hostname_constraint(child.into_inner()),
-        Rule::external_value => external_value(child.into_inner()),
-        Rule::ip_constraint => ip_matching_constraint(child.into_inner()),
-        _ => unreachable!(),
-    }?;
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn prune(&mut self) {
+        self.constraints
+            .retain(|_, constraint| constraint.strong_count() > 0);
+    }
 
-    Ok(Box::new(move |context: &Context| {
-        constraint(context).invert(inverted)
-    }))
+    fn get_or_compile(&mut self, constraint: &ConstraintAst) -> CompileResult<Arc<ConstraintNode>> {
+        let rule = constraint.to_string();
+        // Custom strategies print as the external value they stand in for, which isn't
+        // enough to tell whether two of them share an implementation or parameters
+        if matches!(constraint.kind, ConstraintKind::Custom { .. }) {
+            return Ok(Arc::new(compile_constraint(constraint, rule)?));
+        }
+        if let Some(compiled) = self.constraints.get(&rule).and_then(Weak::upgrade) {
+            return Ok(compiled);
+        }
+        let compiled = Arc::new(compile_constraint(constraint, rule.clone())?);
+        self.constraints.insert(rule, Arc::downgrade(&compiled));
+        Ok(compiled)
+    }
 }
 
-fn eval(expression: Pairs<Rule>) -> CompileResult<RuleFragment> {
-    PRATT_PARSER
-        .map_primary(|primary| match primary.as_rule() {
-            Rule::constraint => constraint(primary.into_inner()),
-            Rule::expr => eval(primary.into_inner()),
-            _ => unreachable!(),
-        })
-        .map_infix(|lhs, op, rhs| match op.as_rule() {
-            Rule::and => Ok(Box::new({
-                let lhs = lhs?;
-                let rhs = rhs?;
-
-                move |context: &Context| -> bool { lhs(context) && rhs(context) }
-            })),
-            Rule::or => Ok(Box::new({
-                let lhs = lhs?;
-                let rhs = rhs?;
-
-                move |context: &Context| -> bool { lhs(context) || rhs(context) }
-            })),
-            _ => unreachable!(),
-        })
-        .parse(expression)
+pub fn compile_rule_ast(rule: &RuleAst) -> CompileResult<RuleNode> {
+    compile_rule_ast_with(rule, None)
 }
 
-pub fn compile_rule(rule: &str) -> CompileResult<RuleFragment> {
-    let nodes = Strategy::parse(Rule::strategy, rule)
-        .map_err(|e| SdkError::StrategyParseError(format!("Failed to parse rule {rule}: {e}")))?;
+pub fn compile_rule_ast_cached(
+    rule: &RuleAst,
+    cache: &mut ConstraintCache,
+) -> CompileResult<RuleNode> {
+    compile_rule_ast_with(rule, Some(cache))
+}
 
-    let [strategy_node] = drain(nodes)?;
-    let rule = eval(strategy_node.into_inner())?;
+pub(crate) fn compile_rule_ast_with(
+    rule: &RuleAst,
+    mut cache: Option<&mut ConstraintCache>,
+) -> CompileResult<RuleNode> {
+    Ok(match rule {
+        RuleAst::Constraint(constraint) => RuleNode::Constraint(match cache {
+            Some(cache) => cache.get_or_compile(constraint)?,
+            None => Arc::new(compile_constraint(constraint, constraint.to_string())?),
+        }),
+        RuleAst::And(lhs, rhs) => RuleNode::And(
+            Box::new(compile_rule_ast_with(lhs, cache.as_deref_mut())?),
+            Box::new(compile_rule_ast_with(rhs, cache)?),
+        ),
+        RuleAst::Or(lhs, rhs) => RuleNode::Or(
+            Box::new(compile_rule_ast_with(lhs, cache.as_deref_mut())?),
+            Box::new(compile_rule_ast_with(rhs, cache)?),
+        ),
+        RuleAst::Not(rule) => RuleNode::Not(Box::new(compile_rule_ast_with(rule, cache)?)),
+    })
+}
 
-    Ok(rule)
+pub fn compile_rule_tree(rule: &str) -> CompileResult<RuleNode> {
+    compile_rule_ast(&parse_rule(rule)?)
+}
+
+pub fn compile_rule(rule: &str) -> CompileResult<RuleFragment> {
+    Ok(compile_rule_tree(rule)?.into_fragment())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::state::{ExternalResultsRef, PropertiesRef};
+    use crate::state::{ExternalResultsRef, ParsedValues, PropertiesRef, StructuredProperties};
 
     use super::*;
     use std::collections::HashMap;
@@ -695,21 +1244,24 @@ mod tests {
 
     fn context_from_user_id(user_id: &str) -> Context<'_> {
         Context {
-            user_id: Some(user_id.into()),
+            user_id: Some(user_id),
             current_time: None,
             properties: None,
             session_id: None,
             environment: None,
             app_name: None,
             remote_address: None,
-            toggle_name: "".into(),
+            toggle_name: "",
             external_results: None,
             runtime_hostname: None,
+            parsed_values: None,
+            clock: None,
+            random_source: None,
         }
     }
 
     impl Context<'_> {
-        fn default() -> Self {
+        pub(crate) fn default() -> Self {
             Context {
                 user_id: None,
                 current_time: None,
@@ -718,9 +1270,12 @@ mod tests {
                 environment: None,
                 app_name: None,
                 remote_address: None,
-                toggle_name: "".into(),
+                toggle_name: "",
                 external_results: None,
                 runtime_hostname: None,
+                parsed_values: None,
+                clock: None,
+                random_source: None,
             }
         }
     }
@@ -852,6 +1407,36 @@ mod tests {
         assert_eq!(rule(&context), expected);
     }
 
+    #[test]
+    fn parsed_values_are_shared_by_every_rule_reading_them() {
+        let parsed_values = ParsedValues::default();
+        let above = compile_rule("user_id > 5").unwrap();
+        let below = compile_rule("user_id < 10").unwrap();
+        let mut context = context_from_user_id("7");
+        context.parsed_values = Some(&parsed_values);
+
+        assert!(above(&context));
+
+        // The cache belongs to a single context, so a field that changes underneath it
+        // isn't parsed again
+        context.user_id = Some("3");
+        assert!(above(&context));
+        assert!(below(&context));
+        assert!(!above(&context_from_user_id("3")));
+    }
+
+    #[test]
+    fn random_values_are_never_cached() {
+        let parsed_values = ParsedValues::default();
+        let rule = compile_rule("random > 50").unwrap();
+        let mut context = context_from_user_id("7");
+        context.parsed_values = Some(&parsed_values);
+
+        let results: HashSet<bool> = (0..200).map(|_| rule(&context)).collect();
+
+        assert_eq!(results.len(), 2);
+    }
+
     #[test_case("100% sticky on random", true)]
     #[test_case("99% sticky on random", true)]
     fn run_rollout_test(rule: &str, expected: bool) {
@@ -871,15 +1456,18 @@ mod tests {
 
         let context = Context {
             current_time: None,
-            user_id: Some("6".into()),
+            user_id: Some("6"),
             properties: Some(PropertiesRef::Strings(&context_property)),
             session_id: None,
             environment: None,
             app_name: None,
             remote_address: None,
-            toggle_name: "".into(),
+            toggle_name: "",
             external_results: None,
             runtime_hostname: None,
+            parsed_values: None,
+            clock: None,
+            random_source: None,
         };
 
         let rule = compile_rule(rule).expect("");
@@ -911,21 +1499,21 @@ mod tests {
     #[test_case("sticky on environment | context[\"lies\"] | context[\"present\"] ", Some("1"); "Respects custom context")]
     #[test_case("sticky on environment | context[\"lies\"]", None; "Falls back to None eventually")]
     fn run_null_coalesce_test(rule: &str, expected: Option<&str>) {
-        let expected = expected.map(|s| Cow::Borrowed(s));
+        let expected = expected.map(Cow::Borrowed);
 
         let mut props = HashMap::new();
         props.insert("present".into(), "1".into());
 
         let context = Context {
-            user_id: Some("42".into()),
-            session_id: Some("7".into()),
+            user_id: Some("42"),
+            session_id: Some("7"),
             properties: Some(PropertiesRef::Strings(&props)),
             ..Context::default()
         };
 
         let mut parse_result = Strategy::parse(Rule::stickiness_param, rule).unwrap();
-        let stickiness_lookup =
-            coalesce_context_property(parse_result.next().unwrap().into_inner()).unwrap();
+        let fields = stickiness_param(parse_result.next().unwrap().into_inner()).unwrap();
+        let stickiness_lookup = coalesce_context_property(&fields);
         let result: Option<Cow<str>> = stickiness_lookup(&context);
 
         assert_eq!(result, expected);
@@ -986,6 +1574,11 @@ mod tests {
     #[test_case("user_id contains_any_ignore_case [\"EMAIL\"]", true)]
     #[test_case("user_id ends_with_any_ignore_case [\".COM\"]", true)]
     #[test_case("user_id starts_with_any_ignore_case [\"SOME\"]", true)]
+    #[test_case("user_id in [\"SOME-EMAIL.COM\"]", false)]
+    #[test_case("user_id in_ignore_case [\"SOME-EMAIL.COM\"]", true)]
+    #[test_case("user_id in_ignore_case [\"Other-Email.com\"]", false)]
+    #[test_case("user_id not_in_ignore_case [\"Some-Email.com\"]", false)]
+    #[test_case("user_id not_in_ignore_case [\"Other-Email.com\"]", true)]
     fn run_string_operators_tests(rule: &str, expected: bool) {
         let rule = compile_rule(rule).expect("");
         let context = context_from_user_id("some-email.com");
@@ -993,6 +1586,78 @@ mod tests {
         assert_eq!(rule(&context), expected);
     }
 
+    fn structured_properties() -> StructuredProperties {
+        serde_json::from_value(serde_json::json!({
+            "roles": ["admin", "Editor"],
+            "versions": ["1.2.0", "2.0.0"],
+            "counts": ["3", "seven"],
+            "org": { "team": "core", "tags": ["alpha", "beta"] },
+            "dotted.key": "exact"
+        }))
+        .unwrap()
+    }
+
+    #[test_case("context[\"roles\"] in [\"admin\"]", true)]
+    #[test_case("context[\"roles\"] in [\"viewer\", \"admin\"]", true)]
+    #[test_case("context[\"roles\"] in [\"viewer\"]", false)]
+    #[test_case("context[\"roles\"] not_in [\"viewer\"]", true)]
+    #[test_case("context[\"roles\"] not_in [\"viewer\", \"Editor\"]", false)]
+    #[test_case("context[\"roles\"] in_ignore_case [\"EDITOR\"]", true)]
+    #[test_case("context[\"roles\"] starts_with_any [\"Ed\"]", true)]
+    #[test_case("context[\"roles\"] ends_with_any_ignore_case [\"MIN\"]", true)]
+    #[test_case("context[\"roles\"] contains_any [\"view\"]", false)]
+    #[test_case("context[\"roles\"] matches_regex \"^ad\"", true)]
+    #[test_case("context[\"versions\"] in [>=2.0.0]", true)]
+    #[test_case("context[\"versions\"] not_in [1.2.0]", false)]
+    #[test_case("context[\"counts\"] in [3]", false; "Numeric lists need every value to parse")]
+    #[test_case("context[\"counts\"] not_in [3]", false; "Numeric lists need every value to parse either way")]
+    #[test_case("context[\"org.team\"] in [\"core\"]", true)]
+    #[test_case("context[\"org.tags\"] in [\"beta\"]", true)]
+    #[test_case("context[\"org.missing\"] in [\"core\"]", false)]
+    #[test_case("context[\"org\"] not_in [\"core\"]", true; "Objects have no value")]
+    #[test_case("context[\"dotted.key\"] in [\"exact\"]", true)]
+    fn structured_properties_match_any_value(rule: &str, expected: bool) {
+        let rule = compile_rule(rule).expect("");
+        let properties = structured_properties();
+        let context = Context {
+            properties: Some(PropertiesRef::Structured(&properties)),
+            ..Context::default()
+        };
+
+        assert_eq!(rule(&context), expected);
+    }
+
+    #[test_case("context[\"versions\"] > 1.0.0", false)]
+    #[test_case("context[\"counts\"] > 1", false)]
+    #[test_case("100% sticky on context[\"roles\"]", false)]
+    #[test_case("100% sticky on context[\"org.team\"]", true)]
+    fn lists_have_no_single_value(rule: &str, expected: bool) {
+        let rule = compile_rule(rule).expect("");
+        let properties = structured_properties();
+        let context = Context {
+            properties: Some(PropertiesRef::Structured(&properties)),
+            ..Context::default()
+        };
+
+        assert_eq!(rule(&context), expected);
+    }
+
+    #[test]
+    fn trace_reports_every_value_of_a_property() {
+        let rule = compile_rule_tree("context[\"roles\"] in [\"admin\"]").unwrap();
+        let properties = structured_properties();
+        let context = Context {
+            properties: Some(PropertiesRef::Structured(&properties)),
+            ..Context::default()
+        };
+
+        let RuleTrace::Constraint(trace) = rule.trace(&context) else {
+            panic!("Expected a constraint");
+        };
+        assert_eq!(trace.context_value.as_deref(), Some("admin, Editor"));
+        assert!(trace.result);
+    }
+
     #[test_case("context[\"cutoff\"] == 2022-01-25T13:00:00.000Z", true)]
     #[test_case("context[\"cutoff\"] == 2022-01-25T12:00:00.000Z", false)]
     #[test_case("context[\"cutoff\"] > 2022-01-25T12:00:00.000Z", true)]
@@ -1051,7 +1716,7 @@ mod tests {
     #[test]
     fn date_constraint_respects_timezones() {
         let context = Context {
-            app_name: Some("2022-01-22T11:30:00.000Z".into()),
+            app_name: Some("2022-01-22T11:30:00.000Z"),
             ..Context::default()
         };
 
@@ -1064,7 +1729,7 @@ mod tests {
     #[test]
     fn inversion_works_on_string_any_rules() {
         let context = Context {
-            app_name: Some("email".into()),
+            app_name: Some("email"),
             ..Context::default()
         };
 
@@ -1079,6 +1744,35 @@ mod tests {
         compile_rule(rule).unwrap();
     }
 
+    #[test_case(r#""Nobody likes \"scare quotes\"""#, "Nobody likes \"scare quotes\"")]
+    #[test_case(r#""C:\\path\\""#, "C:\\path\\")]
+    #[test_case(r#""a\/b""#, "a/b")]
+    #[test_case(r#""\b\f\n\r\t""#, "\u{8}\u{c}\n\r\t")]
+    #[test_case(r#""caf\u00e9""#, "café")]
+    #[test_case(r#""\ud83d\ude00""#, "\u{1F600}"; "surrogate pair")]
+    #[test_case(r#""raw ü and 😀""#, "raw ü and 😀")]
+    fn strings_take_json_escapes(literal: &str, expected: &str) {
+        let ast = parse_rule(&format!("user_id in [{literal}]")).unwrap();
+
+        let RuleAst::Constraint(ConstraintAst {
+            kind: ConstraintKind::List { values, .. },
+            ..
+        }) = ast
+        else {
+            panic!("Expected a list constraint");
+        };
+        assert_eq!(values, ListValues::Strings(vec![expected.to_string()]));
+    }
+
+    #[test_case(r#""\x""#; "unknown escape")]
+    #[test_case(r#""\u12""#; "short unicode escape")]
+    #[test_case(r#""\ud83d""#; "lone surrogate")]
+    #[test_case(r#""\ude00\ud83d""#; "reversed surrogates")]
+    #[test_case(r#""trailing\""#; "trailing backslash")]
+    fn invalid_escapes_are_rejected(literal: &str) {
+        assert!(compile_rule(&format!("user_id in [{literal}]")).is_err());
+    }
+
     #[test]
     fn missing_external_value_produces_false_without_error() {
         let rule = "external_value[\"i_do_not_exist\"]";
@@ -1234,13 +1928,123 @@ mod tests {
         let rule = compile_rule(&rule).unwrap();
 
         let context = Context {
-            remote_address: Some(context_ip.into()),
+            remote_address: Some(context_ip),
             ..Context::default()
         };
 
         assert_eq!(rule(&context), expected_value);
     }
 
+    #[test]
+    fn trace_reports_every_constraint_without_short_circuiting() {
+        let rule = compile_rule_tree("user_id in [\"7\"] and app_name == 5 and !true").unwrap();
+        let context = context_from_user_id("8");
+
+        let RuleTrace::And { result, children } = rule.trace(&context) else {
+            panic!("Expected a flattened and node");
+        };
+
+        assert!(!result);
+        assert_eq!(children.len(), 3);
+
+        let RuleTrace::Constraint(first) = &children[0] else {
+            panic!("Expected a constraint");
+        };
+        assert_eq!(first.rule, "user_id in [\"7\"]");
+        assert_eq!(first.operator.as_deref(), Some("in"));
+        assert_eq!(first.values, vec!["7".to_string()]);
+        assert_eq!(first.context_value.as_deref(), Some("8"));
+        assert!(!first.result);
+
+        let RuleTrace::Constraint(last) = &children[2] else {
+            panic!("Expected a constraint");
+        };
+        assert!(last.inverted);
+        assert!(!last.result);
+    }
+
+    #[test]
+    fn trace_reports_rollout_bucket() {
+        let rule = compile_rule_tree(
+            "55% sticky on user_id with group_id of \"Feature.flexibleRollout.userId.55\"",
+        )
+        .unwrap();
+        let context = context_from_user_id("25");
+
+        let RuleTrace::Constraint(trace) = rule.trace(&context) else {
+            panic!("Expected a constraint");
+        };
+        let rollout = trace.rollout.unwrap();
+
+        assert_eq!(
+            rollout.percentage,
+            Percentage::from_hundredths(5500).unwrap()
+        );
+        assert_eq!(rollout.group_id, "Feature.flexibleRollout.userId.55");
+        assert_eq!(rollout.stickiness_value.as_deref(), Some("25"));
+        assert_eq!(
+            rollout.bucket,
+            normalized_fine_hash("Feature.flexibleRollout.userId.55", "25", 0).ok()
+        );
+        assert_eq!(
+            rollout.bucket.map(|bucket| (bucket - 1) / 100 + 1),
+            normalized_hash("Feature.flexibleRollout.userId.55", "25", 100, 0).ok()
+        );
+        assert_eq!(trace.result, rule.evaluate(&context));
+    }
+
+    #[test_case("0.1%", 10)]
+    #[test_case("0.01%", 1)]
+    #[test_case("12.34%", 1234)]
+    #[test_case("5.5%", 550)]
+    #[test_case("05%", 500)]
+    #[test_case("100.00%", 10000)]
+    fn percentages_take_up_to_two_decimals(percentage: &str, hundredths: u16) {
+        let RuleAst::Constraint(ConstraintAst {
+            kind: ConstraintKind::Rollout { percentage, .. },
+            ..
+        }) = parse_rule(&format!("{percentage} sticky on user_id")).unwrap()
+        else {
+            panic!("Expected a rollout");
+        };
+
+        assert_eq!(percentage.hundredths(), hundredths);
+    }
+
+    #[test_case("100.5%")]
+    #[test_case("101%")]
+    #[test_case("0.125%")]
+    #[test_case(".5%")]
+    #[test_case("5.%")]
+    fn percentages_outside_the_range_or_precision_are_rejected(percentage: &str) {
+        assert!(parse_rule(&format!("{percentage} sticky on user_id")).is_err());
+    }
+
+    #[test]
+    fn fractional_rollouts_include_their_share_of_users() {
+        let rule = compile_rule("0.5% sticky on user_id with group_id of \"canary\"").unwrap();
+
+        let enabled = (0..100_000)
+            .map(|user_id| user_id.to_string())
+            .filter(|user_id| rule(&context_from_user_id(user_id)))
+            .count();
+
+        assert!((400..600).contains(&enabled), "{enabled} users enabled");
+    }
+
+    #[test_case("user_id == 1 and (user_id > 1 or user_id < 1)")]
+    #[test_case("user_id == 9 or !user_id in [\"1\", \"2\"]")]
+    #[test_case("(true and false) or (false or true)")]
+    #[test_case("!(user_id == 1 or user_id == 2) and !55% sticky on user_id")]
+    fn trace_result_matches_evaluation(rule: &str) {
+        let rule = compile_rule_tree(rule).unwrap();
+
+        for user_id in ["1", "2", "9"] {
+            let context = context_from_user_id(user_id);
+            assert_eq!(rule.trace(&context).result(), rule.evaluate(&context));
+        }
+    }
+
     #[test]
     fn remote_address_constraint_never_matches_missing_context() {
         let rule = compile_rule("remote_address in_cidr [\"127.0.0.1\"]").unwrap();
@@ -1248,4 +2052,200 @@ mod tests {
 
         assert!(!rule(&context));
     }
+
+    #[test_case("user_id in [\"1\", \"2\"]")]
+    #[test_case("!context[\"customField\"] not_in [1, 2.5]")]
+    #[test_case("user_id in []")]
+    #[test_case("app_name starts_with_any_ignore_case [\"some\"]")]
+    #[test_case("context[\"email\"] matches_regex \"^.+@example\\\\.com$\"")]
+    #[test_case("current_time > 2022-01-29T13:00:00.000Z")]
+    #[test_case("context[\"version\"] >= 1.2.3-beta.1")]
+    #[test_case("context[\"count\"] < 5")]
+    #[test_case("55% sticky on user_id | session_id | random with group_id of \"toggle\"")]
+    #[test_case("10% sticky on random[50]")]
+    #[test_case("0.05% sticky on user_id")]
+    #[test_case("12.5% sticky on session_id")]
+    #[test_case("remote_address in_cidr [\"192.168.0.0/16\"]")]
+    #[test_case("hostname in [\"box\"]")]
+    #[test_case("external_value[\"customStrategy1\"]")]
+    #[test_case("context[\"email\"] not_in_ignore_case [\"Some@Email.com\"]")]
+    #[test_case("true")]
+    #[test_case("user_id in [1] and (app_name in [\"a\"] or environment in [\"b\"])")]
+    #[test_case("(user_id in [1] and app_name in [\"a\"]) or environment in [\"b\"]")]
+    #[test_case("!(user_id in [1] or app_name in [\"a\"]) and environment in [\"b\"]")]
+    #[test_case("!(!(true and false) or !55% sticky on user_id)")]
+    #[test_case("context[\"version\"] in [1.2.3, >=1.0.0 <2.0.0, ^1.2, ~1.4.0-rc.1, 3.*, *]")]
+    #[test_case("context[\"version\"] in [\">=1.0.0, <2.0.0\", 3.0.0]")]
+    fn display_round_trips_through_the_parser(rule: &str) {
+        let ast = parse_rule(rule).unwrap();
+        let rendered = ast.to_string();
+
+        assert_eq!(parse_rule(&rendered).unwrap(), ast);
+        assert_eq!(parse_rule(&rendered).unwrap().to_string(), rendered);
+    }
+
+    #[test_case(
+        "a and b or c",
+        "user_id in [\"a\"] and (user_id in [\"b\"] or user_id in [\"c\"])"
+    )]
+    #[test_case(
+        "(a and b) or c",
+        "(user_id in [\"a\"] and user_id in [\"b\"]) or user_id in [\"c\"]"
+    )]
+    #[test_case(
+        "a and b and c",
+        "user_id in [\"a\"] and user_id in [\"b\"] and user_id in [\"c\"]"
+    )]
+    fn display_preserves_precedence(shape: &str, expected: &str) {
+        let rule = shape
+            .split(' ')
+            .map(|token| match token.trim_matches(|c| c == '(' || c == ')') {
+                "and" | "or" => token.to_string(),
+                name => token.replace(name, &format!("user_id in [\"{name}\"]")),
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        assert_eq!(parse_rule(&rule).unwrap().to_string(), expected);
+    }
+
+    #[test]
+    fn double_inversion_cancels_out() {
+        let ast = parse_rule("!!user_id in [\"7\"]").unwrap();
+        let RuleAst::Constraint(constraint) = &ast else {
+            panic!("Expected a single constraint");
+        };
+
+        assert!(!constraint.inverted);
+        assert_eq!(ast.to_string(), "user_id in [\"7\"]");
+    }
+
+    #[test_case("!(user_id in [\"7\"] or user_id in [\"8\"])", "7", false)]
+    #[test_case("!(user_id in [\"7\"] or user_id in [\"8\"])", "9", true)]
+    #[test_case("not (user_id in [\"7\"] and true)", "7", false)]
+    #[test_case("not(user_id in [\"7\"])", "9", true)]
+    #[test_case("not user_id in [\"7\"] or false", "7", false)]
+    #[test_case("!(true and !(false or false))", "7", false)]
+    #[test_case("!100% sticky on user_id", "7", false)]
+    #[test_case("not 0% sticky on user_id", "7", true)]
+    fn negation_applies_to_any_term(rule: &str, user_id: &str, expected: bool) {
+        let rule = compile_rule(rule).unwrap();
+
+        assert_eq!(rule(&context_from_user_id(user_id)), expected);
+    }
+
+    #[test]
+    fn negated_groups_bind_tighter_than_boolean_operators() {
+        let ast = parse_rule("!(true or false) and true").unwrap();
+
+        let RuleAst::And(lhs, _) = &ast else {
+            panic!("Expected the negation to sit under the and");
+        };
+        assert!(matches!(lhs.as_ref(), RuleAst::Not(_)));
+        assert!(parse_rule("nottrue").is_err());
+        assert_eq!(
+            parse_rule("not not (true or false)").unwrap(),
+            parse_rule("true or false").unwrap()
+        );
+    }
+
+    #[test]
+    fn trace_reports_negated_groups() {
+        let rule = compile_rule_tree("!(user_id in [\"7\"] or user_id in [\"8\"])").unwrap();
+
+        let RuleTrace::Not { result, child } = rule.trace(&context_from_user_id("8")) else {
+            panic!("Expected a not node");
+        };
+        assert!(!result);
+        assert!(matches!(*child, RuleTrace::Or { result: true, .. }));
+    }
+
+    #[test_case("user_id in [1.2.3, 1.4.0]", "1.4.0", true)]
+    #[test_case("user_id in [1.2.3, 1.4.0]", "1.3.0", false)]
+    #[test_case("user_id in [1.2.3]", "1.2.3+build.5", true; "build metadata is ignored")]
+    #[test_case("user_id in [1.2.3-beta.1]", "1.2.3-beta.1", true)]
+    #[test_case("user_id in [^1.2]", "1.9.1", true)]
+    #[test_case("user_id in [^1.2]", "2.0.0", false)]
+    #[test_case("user_id in [>=1.0.0 <2.0.0]", "1.5.0", true)]
+    #[test_case("user_id in [>=1.0.0 <2.0.0]", "2.0.0", false)]
+    #[test_case("user_id in [~1.2.0, 3.*]", "3.1.0", true)]
+    #[test_case("user_id in [~1.2.0, 3.*]", "1.3.0", false)]
+    #[test_case("user_id not_in [>=1.0.0 <2.0.0]", "2.0.0", true)]
+    #[test_case("user_id not_in [>=1.0.0 <2.0.0]", "1.0.0", false)]
+    #[test_case("user_id in [*]", "not-a-version", false)]
+    #[test_case("user_id not_in [1.2.3]", "not-a-version", false)]
+    #[test_case("user_id in [<1.0.0, ^2.0]", "0.5.0", true; "bound beside a range")]
+    #[test_case("user_id in [<1.0.0, ^2.0]", "1.5.0", false; "outside a bound beside a range")]
+    #[test_case("user_id in [>=1.0.0 <2.0.0, 3.0.0]", "3.0.0", true)]
+    #[test_case("user_id in [>=1.0.0 <2.0.0, 3.0.0]", "0.1.0", false)]
+    fn semver_lists_match_versions_and_ranges(rule: &str, version: &str, expected: bool) {
+        let rule = compile_rule(rule).unwrap();
+
+        assert_eq!(rule(&context_from_user_id(version)), expected);
+    }
+
+    #[test_case("1.0.0", true)]
+    #[test_case("1.9.3", true)]
+    #[test_case("2.0.0", false)]
+    #[test_case("0.9.0", false)]
+    fn quoted_ranges_join_their_comparators(version: &str, expected: bool) {
+        let rule = compile_rule("user_id in [\">=1.0.0, <2.0.0\"]").unwrap();
+
+        assert_eq!(rule(&context_from_user_id(version)), expected);
+    }
+
+    #[test_case("0.5.0", true)]
+    #[test_case("2.1.0", true)]
+    #[test_case("1.0.0", false)]
+    #[test_case("1.9.3", false)]
+    fn separate_bounds_match_outside_a_range(version: &str, expected: bool) {
+        let in_list = compile_rule("user_id in [<1.0.0, >=2.0.0]").unwrap();
+        let not_in_range = compile_rule("user_id not_in [\">=1.0.0, <2.0.0\"]").unwrap();
+        let context = context_from_user_id(version);
+
+        assert_eq!(in_list(&context), expected);
+        assert_eq!(not_in_range(&context), expected);
+    }
+
+    #[test]
+    fn only_lists_with_versions_or_ranges_are_semver_lists() {
+        let values = |rule: &str| match parse_rule(rule).unwrap() {
+            RuleAst::Constraint(ConstraintAst {
+                kind: ConstraintKind::List { values, .. },
+                ..
+            }) => values,
+            _ => panic!("Expected a list constraint"),
+        };
+
+        assert!(matches!(
+            values("user_id in [1, 2.5]"),
+            ListValues::Numeric(_)
+        ));
+        assert!(matches!(
+            values("user_id in [\"1.2.3\"]"),
+            ListValues::Strings(_)
+        ));
+        assert!(matches!(
+            values("user_id in [1.2.3]"),
+            ListValues::Semver(_)
+        ));
+        assert!(parse_rule("user_id in [1, 1.2.3]").is_err());
+        assert!(parse_rule("user_id in [=1.*.3]").is_err());
+    }
+
+    #[test]
+    fn rules_compiled_from_an_ast_match_rules_compiled_from_text() {
+        let rule = "user_id in [\"7\"] and !app_name starts_with_any [\"my\"]";
+        let from_text = compile_rule(rule).unwrap();
+        let from_ast = compile_rule_ast(&parse_rule(rule).unwrap()).unwrap();
+
+        for (user_id, app_name) in [("7", "other"), ("7", "myapp"), ("8", "other")] {
+            let context = Context {
+                user_id: Some(user_id),
+                app_name: Some(app_name),
+                ..Context::default()
+            };
+            assert_eq!(from_text(&context), from_ast.evaluate(&context));
+        }
+    }
 }
@@ -5,13 +5,15 @@ use std::collections::HashMap;
 use unleash_types::client_features::{Constraint, Operator, Segment, Strategy, StrategyVariant};
 
 use crate::state::SdkError;
+use crate::strategy_ast::Percentage;
+use crate::strategy_parsing::{date_literal, numeric_literal, semver_literal};
 
 const DEFAULT_STICKINESS: &str = "user_id | session_id | random[10000]";
 const DEFAULT_RANDOM: &str = "random[10000]";
 
 pub(crate) type RawVariantRule = Vec<(String, Vec<StrategyVariant>, String, String)>;
 
-enum StrategyType {
+pub(crate) enum StrategyType {
     Default,
     UserWithId,
     GradualRolloutUserId,
@@ -26,7 +28,7 @@ enum StrategyType {
     Custom(String),
 }
 
-trait IsCustom {
+pub(crate) trait IsCustom {
     fn is_custom(&self) -> bool;
 }
 
@@ -46,55 +48,88 @@ pub fn upgrade(
     if strategies.is_empty() {
         return Ok("true".into());
     }
-    let mut custom_strat_count = 0;
-    let rule_text = strategies
-        .iter()
-        .map(|strategy| {
-            if strategy.is_custom() {
-                custom_strat_count += 1;
-            }
-            upgrade_strategy(strategy, segment_map, custom_strat_count)
-        })
+    let rule_text = upgrade_strategies(strategies, segment_map)
+        .into_iter()
         .collect::<Result<Vec<String>, SdkError>>()?
         .join(" or ");
     Ok(rule_text)
 }
 
-pub fn build_variant_rules(
+/// Upgrades each strategy to its own rule, in evaluation order. Custom strategies are numbered
+/// by their position among the other custom strategies on the toggle
+pub fn upgrade_strategies(
     strategies: &[Strategy],
     segment_map: &HashMap<i32, Segment>,
-    toggle_name: &str,
-) -> Result<RawVariantRule, SdkError> {
+) -> Vec<Result<String, SdkError>> {
+    number_custom_strategies(strategies)
+        .map(|(strategy, custom_count)| upgrade_strategy(strategy, segment_map, custom_count))
+        .collect()
+}
+
+/// The strategies in the order they're evaluated in, which is by sort order. The sort
+/// is stable and strategies without a sort order go last, so payloads that don't set
+/// one keep their order
+pub(crate) fn sorted_strategies(strategies: &[Strategy]) -> Vec<&Strategy> {
+    let mut sorted: Vec<&Strategy> = strategies.iter().collect();
+    sorted.sort_by_key(|strategy| (strategy.sort_order.is_none(), strategy.sort_order));
+    sorted
+}
+
+/// Pairs each strategy with the number of custom strategies seen so far, including
+/// itself, in evaluation order
+pub(crate) fn number_custom_strategies(
+    strategies: &[Strategy],
+) -> impl Iterator<Item = (&Strategy, usize)> {
     let mut custom_strat_count = 0;
 
-    strategies
-        .iter()
-        .map(|strategy| {
+    sorted_strategies(strategies)
+        .into_iter()
+        .map(move |strategy| {
             if strategy.is_custom() {
                 custom_strat_count += 1;
             }
+            (strategy, custom_strat_count)
+        })
+}
 
-            Ok((
-                upgrade_strategy(strategy, segment_map, custom_strat_count)?,
-                strategy.variants.clone().unwrap_or_default(),
-                strategy
-                    .parameters
-                    .as_ref()
-                    .and_then(|params| params.get("stickiness"))
-                    .cloned()
-                    .unwrap_or_else(|| "default".to_string()),
-                strategy
-                    .parameters
-                    .as_ref()
-                    .and_then(|params| params.get("groupId"))
-                    .cloned()
-                    .unwrap_or_else(|| toggle_name.to_owned()),
-            ))
+/// The external value key a custom strategy is looked up under
+pub(crate) fn custom_strategy_key(strategy_count: usize) -> String {
+    format!("customStrategy{strategy_count}")
+}
+
+pub fn build_variant_rules(
+    strategies: &[Strategy],
+    segment_map: &HashMap<i32, Segment>,
+    toggle_name: &str,
+) -> Result<RawVariantRule, SdkError> {
+    sorted_strategies(strategies)
+        .into_iter()
+        .zip(upgrade_strategies(strategies, segment_map))
+        .map(|(strategy, rule)| {
+            let (variants, stickiness, group_id) = variant_parameters(strategy, toggle_name);
+            Ok((rule?, variants, stickiness, group_id))
         })
         .collect()
 }
 
-trait PropResolver {
+pub(crate) fn variant_parameters(
+    strategy: &Strategy,
+    toggle_name: &str,
+) -> (Vec<StrategyVariant>, String, String) {
+    (
+        strategy.variants.clone().unwrap_or_default(),
+        strategy
+            .get_param("stickiness")
+            .cloned()
+            .unwrap_or_else(|| "default".to_string()),
+        strategy
+            .get_param("groupId")
+            .cloned()
+            .unwrap_or_else(|| toggle_name.to_owned()),
+    )
+}
+
+pub(crate) trait PropResolver {
     fn get_param(&self, key: &str) -> Option<&String>;
 }
 
@@ -137,9 +172,24 @@ fn upgrade_strategy(
         StrategyType::FlexibleRollout => upgrade_flexible_rollout_strategy(strategy),
         StrategyType::RemoteAddress => upgrade_remote_address(strategy),
         StrategyType::ApplicationHostname => upgrade_hostname(strategy),
-        StrategyType::Custom(_) => format!("external_value[\"customStrategy{strategy_count}\"]"),
+        StrategyType::Custom(_) => format!(
+            "external_value[\"{}\"]",
+            custom_strategy_key(strategy_count)
+        ),
     };
 
+    let constraints = upgrade_constraints(resolve_constraints(strategy, segment_map)?)?;
+    match constraints {
+        Some(constraints) => Ok(format!("({strategy_rule} and ({constraints}))")),
+        None => Ok(strategy_rule),
+    }
+}
+
+/// The strategy's own constraints followed by the constraints of every segment it references
+pub(crate) fn resolve_constraints(
+    strategy: &Strategy,
+    segment_map: &HashMap<i32, Segment>,
+) -> Result<Vec<Constraint>, SdkError> {
     let segments = strategy
         .segments
         .as_ref()
@@ -162,18 +212,19 @@ fn upgrade_strategy(
     raw_constraints.append(&mut strategy.constraints.clone().unwrap_or_default());
     raw_constraints.append(&mut segment_constraints);
 
-    let constraints = upgrade_constraints(raw_constraints)?;
-    match constraints {
-        Some(constraints) => Ok(format!("({strategy_rule} and ({constraints}))")),
-        None => Ok(strategy_rule),
-    }
+    Ok(raw_constraints)
 }
 
 fn upgrade_flexible_rollout_strategy(strategy: &Strategy) -> String {
-    let rollout = get_rollout_target(strategy, "rollout");
+    let rollout = get_rollout_percentage(strategy, "rollout");
 
     match rollout {
         Some(rollout) => {
+            // Decimals that aren't percentages are left as they are for the parser to reject
+            let rollout = match rollout {
+                Ok(rollout) => rollout.to_string(),
+                Err(_) => strategy.get_param("rollout").cloned().unwrap_or_default(),
+            };
             let mut rule: String = format!("{rollout}%");
 
             rule = format!(
@@ -182,7 +233,7 @@ fn upgrade_flexible_rollout_strategy(strategy: &Strategy) -> String {
             );
 
             if let Some(group_id) = strategy.get_param("groupId") {
-                rule = format!("{rule} with group_id of \"{group_id}\"");
+                rule = format!("{rule} with group_id of \"{}\"", escape_string(group_id));
             }
 
             rule
@@ -199,7 +250,7 @@ fn upgrade_user_id_strategy(strategy: &Strategy) -> String {
                 //The escaping of quotes is to tolerate a legacy validation in bug in the Unleash server,
                 // which would save an incorrect parameter set for the strategy. This will still likely
                 // cause Yggdrasil to evalute the strategy as off for most cases, but it will allow the rule to compile
-                .map(|id| format!("\"{}\"", escape_quotes(id.trim())))
+                .map(|id| format!("\"{}\"", escape_string(id.trim())))
                 .collect::<Vec<String>>()
                 .join(",");
             format!("user_id in [{user_ids}]")
@@ -216,7 +267,7 @@ fn upgrade_remote_address(strategy: &Strategy) -> String {
                 .collect::<Vec<&str>>()
                 .iter()
                 .map(|x| x.trim())
-                .map(|x| format!("\"{x}\""))
+                .map(|x| format!("\"{}\"", escape_string(x)))
                 .collect::<Vec<String>>()
                 .join(", ");
             format!("remote_address in_cidr [{ips}]")
@@ -231,7 +282,10 @@ fn upgrade_session_id_rollout_strategy(strategy: &Strategy) -> String {
     let group_id = strategy.get_param("groupId");
     match (percentage, group_id) {
         (Some(percentage), Some(group_id)) => {
-            format!("{percentage}% sticky on session_id with group_id of \"{group_id}\"")
+            format!(
+                "{percentage}% sticky on session_id with group_id of \"{}\"",
+                escape_string(group_id)
+            )
         }
         _ => "false".into(),
     }
@@ -243,7 +297,10 @@ fn upgrade_user_id_rollout_strategy(strategy: &Strategy) -> String {
     let group_id = strategy.get_param("groupId");
     match (percentage, group_id) {
         (Some(percentage), Some(group_id)) => {
-            format!("{percentage}% sticky on user_id with group_id of \"{group_id}\"")
+            format!(
+                "{percentage}% sticky on user_id with group_id of \"{}\"",
+                escape_string(group_id)
+            )
         }
         _ => "false".into(),
     }
@@ -260,7 +317,7 @@ fn upgrade_hostname(strategy: &Strategy) -> String {
         .collect::<Vec<&str>>()
         .iter()
         .map(|x| x.trim())
-        .map(|x| format!("\"{x}\""))
+        .map(|x| format!("\"{}\"", escape_string(x)))
         .collect::<Vec<String>>()
         .join(", ");
     format!("hostname in [{hosts}]")
@@ -275,7 +332,20 @@ fn upgrade_random(strategy: &Strategy) -> String {
     }
 }
 
-fn get_rollout_target(strategy: &Strategy, target_property: &str) -> Option<usize> {
+/// Fractional rollouts have up to two decimals. A parameter that isn't a decimal
+/// number is None and never matches, while a decimal that isn't a percentage with at
+/// most two decimals is an error
+pub(crate) fn get_rollout_percentage(
+    strategy: &Strategy,
+    target_property: &str,
+) -> Option<Result<Percentage, SdkError>> {
+    let value = strategy.get_param(target_property)?;
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    (is_digits(whole) && is_digits(fraction)).then(|| value.parse())
+}
+
+pub(crate) fn get_rollout_target(strategy: &Strategy, target_property: &str) -> Option<usize> {
     strategy
         .get_param(target_property)
         .map(|value| value.parse::<usize>())
@@ -294,7 +364,7 @@ fn upgrade_constraints(constraints: Vec<Constraint>) -> Result<Option<String>, S
     Ok(Some(squashed_rules))
 }
 
-fn is_stringy(op: &Operator) -> bool {
+pub(crate) fn is_stringy(op: &Operator) -> bool {
     matches!(
         op,
         Operator::NotIn
@@ -306,8 +376,25 @@ fn is_stringy(op: &Operator) -> bool {
     )
 }
 
-fn escape_quotes(stringy_operator: &str) -> String {
-    stringy_operator.replace('\"', "\\\"")
+/// Escapes a value so it can be written between the quotes of a DSL string. The
+/// escapes are the same as JSON's, control characters are escaped and everything
+/// else, including non ASCII characters, is written as is
+pub fn escape_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 fn upgrade_constraint(constraint: &Constraint) -> Result<String, SdkError> {
@@ -325,7 +412,7 @@ fn upgrade_constraint(constraint: &Constraint) -> Result<String, SdkError> {
             .map(|values| {
                 values
                     .iter()
-                    .map(|x| format!("\"{}\"", escape_quotes(x)))
+                    .map(|x| format!("\"{}\"", escape_string(x)))
                     .collect::<Vec<String>>()
                     .join(", ")
             })
@@ -334,7 +421,7 @@ fn upgrade_constraint(constraint: &Constraint) -> Result<String, SdkError> {
     } else if constraint.operator == Operator::RegexMatch {
         format!(
             "\"{}\"",
-            escape_quotes(constraint.value.as_ref().unwrap_or(&"".to_string()))
+            escape_string(constraint.value.as_ref().unwrap_or(&"".to_string()))
         )
     } else {
         if constraint.operator == Operator::SemverEq
@@ -358,9 +445,15 @@ fn upgrade_constraint(constraint: &Constraint) -> Result<String, SdkError> {
                 return Ok("false".into());
             }
         }
-        constraint.value.clone().ok_or_else(|| {
+        let value = constraint.value.clone().ok_or_else(|| {
             SdkError::StrategyParseError("Failed to resolve constraint value".into())
-        })?
+        })?;
+        // Scalar values go into the rule unquoted, so anything other than a single
+        // literal could add terms of its own to the rule
+        if date_literal(&value).is_err() && semver_literal(&value).is_err() {
+            numeric_literal(&value)?;
+        }
+        value
     };
 
     Ok(format!("{inversion}{context_name} {op} {value}"))
@@ -368,8 +461,20 @@ fn upgrade_constraint(constraint: &Constraint) -> Result<String, SdkError> {
 
 fn upgrade_operator(op: &Operator, case_insensitive: bool) -> Option<String> {
     match op {
-        Operator::In => Some("in".into()),
-        Operator::NotIn => Some("not_in".into()),
+        Operator::In => {
+            if case_insensitive {
+                Some("in_ignore_case".into())
+            } else {
+                Some("in".into())
+            }
+        }
+        Operator::NotIn => {
+            if case_insensitive {
+                Some("not_in_ignore_case".into())
+            } else {
+                Some("not_in".into())
+            }
+        }
         Operator::StrEndsWith => {
             if case_insensitive {
                 Some("ends_with_any_ignore_case".into())
@@ -416,15 +521,24 @@ fn upgrade_operator(op: &Operator, case_insensitive: bool) -> Option<String> {
 }
 
 fn upgrade_stickiness(stickiness_param: Option<&String>) -> String {
-    if let Some(stickiness_param) = stickiness_param {
-        match stickiness_param.as_ref() {
+    stickiness_chain(stickiness_param.map(String::as_str))
+        .map(|stickiness| match stickiness {
             "random" => DEFAULT_RANDOM.into(),
             "default" => DEFAULT_STICKINESS.into(),
-            _ => upgrade_context_name(stickiness_param),
-        }
-    } else {
-        DEFAULT_STICKINESS.into()
-    }
+            _ => upgrade_context_name(stickiness),
+        })
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+// Stickiness parameters name a single field or a chain of them separated by `|`,
+// with no parameter at all meaning default
+pub(crate) fn stickiness_chain(stickiness_param: Option<&str>) -> impl Iterator<Item = &str> {
+    stickiness_param
+        .unwrap_or("default")
+        .split('|')
+        .map(str::trim)
+        .filter(|stickiness| !stickiness.is_empty())
 }
 
 fn upgrade_context_name(context_name: &str) -> String {
@@ -435,7 +549,7 @@ fn upgrade_context_name(context_name: &str) -> String {
         "environment" => "environment".into(),
         "appName" => "app_name".into(),
         "remoteAddress" => "remote_address".into(),
-        _ => format!("context[\"{context_name}\"]"),
+        _ => format!("context[\"{}\"]", escape_string(context_name)),
     }
 }
 
@@ -633,6 +747,54 @@ mod tests {
         );
     }
 
+    #[test_case("0.5", "0.5%")]
+    #[test_case("12.50", "12.5%")]
+    #[test_case("0.05", "0.05%")]
+    #[test_case("050", "50%")]
+    #[test_case("0.125", "0.125%"; "left for the parser to reject")]
+    fn upgrades_fractional_flexible_rollouts(rollout: &str, expected: &str) {
+        let strategy = Strategy {
+            name: "flexibleRollout".into(),
+            parameters: Some(HashMap::from([
+                ("rollout".into(), rollout.into()),
+                ("stickiness".into(), "userId".into()),
+            ])),
+            constraints: None,
+            segments: None,
+            sort_order: Some(1),
+            variants: None,
+        };
+
+        let output = upgrade(&[strategy], &HashMap::new()).unwrap();
+        assert_eq!(output, format!("{expected} sticky on user_id"));
+    }
+
+    #[test_case(
+        "userId|deviceId|sessionId",
+        "user_id | context[\"deviceId\"] | session_id"
+    )]
+    #[test_case(
+        " deviceId | default ",
+        "context[\"deviceId\"] | user_id | session_id | random[10000]"
+    )]
+    #[test_case("deviceId|random", "context[\"deviceId\"] | random[10000]")]
+    fn upgrades_stickiness_chains(stickiness: &str, expected: &str) {
+        let strategy = Strategy {
+            name: "flexibleRollout".into(),
+            parameters: Some(HashMap::from([
+                ("rollout".into(), "50".into()),
+                ("stickiness".into(), stickiness.into()),
+            ])),
+            constraints: None,
+            segments: None,
+            sort_order: Some(1),
+            variants: None,
+        };
+
+        let output = upgrade(&[strategy], &HashMap::new()).unwrap();
+        assert_eq!(output, format!("50% sticky on {expected}"));
+    }
+
     #[test]
     fn upgrades_flexible_rollout_without_group_id() {
         let mut parameters = HashMap::new();
@@ -778,6 +940,12 @@ mod tests {
         true,
         "user_id contains_any_ignore_case [\"some\", \"thing\"]"
     )]
+    #[test_case(Operator::In, true, "user_id in_ignore_case [\"some\", \"thing\"]")]
+    #[test_case(
+        Operator::NotIn,
+        true,
+        "user_id not_in_ignore_case [\"some\", \"thing\"]"
+    )]
     fn upgrades_string_list_operator(op: Operator, case_insensitive: bool, expected: &str) {
         let constraint = Constraint {
             context_name: "userId".into(),
@@ -920,6 +1088,33 @@ mod tests {
         )
     }
 
+    #[test]
+    fn strategies_are_upgraded_in_sort_order() {
+        let strategy = |name: &str, sort_order| Strategy {
+            name: name.into(),
+            parameters: None,
+            constraints: None,
+            segments: None,
+            sort_order,
+            variants: None,
+        };
+
+        let output = upgrade(
+            &[
+                strategy("unsorted", None),
+                strategy("default", Some(3)),
+                strategy("second", Some(2)),
+                strategy("first", Some(1)),
+            ],
+            &HashMap::new(),
+        )
+        .expect("Failed to upgrade strategy");
+        assert_eq!(
+            output.as_str(),
+            "external_value[\"customStrategy1\"] or external_value[\"customStrategy2\"] or true or external_value[\"customStrategy3\"]"
+        )
+    }
+
     #[test]
     fn correctly_escapes_free_quotes_in_string_operators() {
         let constraint = Constraint {
@@ -0,0 +1,507 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use ipnetwork::IpNetwork;
+use regex::Regex;
+use serde::Serialize;
+use unleash_types::client_features::{
+    ClientFeature, ClientFeatures, Constraint, Operator, Segment, Strategy,
+};
+
+use crate::strategy_parsing::{date_literal, numeric_literal, semver_literal};
+use crate::strategy_upgrade::{
+    get_rollout_percentage, sorted_strategies, PropResolver, StrategyType,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ValidationCode {
+    UnknownOperator,
+    UnresolvableSegment,
+    NonNumericRollout,
+    RolloutOutOfRange,
+    InvalidSemver,
+    InvalidDate,
+    InvalidNumber,
+    InvalidRegex,
+    InvalidCidr,
+    DuplicateToggleName,
+    ZeroWeightVariants,
+}
+
+/// A single problem in a feature set. Problems found in a segment's constraints are
+/// reported against every strategy that uses the segment, with `segment_id` set and
+/// `constraint_index` counting within the segment rather than the strategy
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationIssue {
+    pub code: ValidationCode,
+    pub toggle_name: String,
+    pub strategy_index: Option<usize>,
+    pub constraint_index: Option<usize>,
+    pub segment_id: Option<i32>,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn has(&self, code: ValidationCode) -> bool {
+        self.issues.iter().any(|issue| issue.code == code)
+    }
+}
+
+/// Checks a feature set for problems that would otherwise only show up as toggles that
+/// fail to compile, or that compile but silently never match
+pub fn validate(features: &ClientFeatures) -> ValidationReport {
+    let segments: HashMap<i32, &Segment> = features
+        .segments
+        .iter()
+        .flatten()
+        .map(|segment| (segment.id, segment))
+        .collect();
+    let mut issues = vec![];
+    let mut seen = HashSet::new();
+
+    for toggle in &features.features {
+        if !seen.insert(toggle.name.as_str()) {
+            issues.push(toggle_issue(
+                toggle,
+                ValidationCode::DuplicateToggleName,
+                "Toggle name is used more than once, only one of them will be used".into(),
+            ));
+        }
+        validate_toggle(toggle, &segments, &mut issues);
+    }
+
+    ValidationReport { issues }
+}
+
+fn validate_toggle(
+    toggle: &ClientFeature,
+    segments: &HashMap<i32, &Segment>,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    if zero_weight(toggle.variants.iter().flatten().map(|v| v.weight)) {
+        issues.push(toggle_issue(
+            toggle,
+            ValidationCode::ZeroWeightVariants,
+            "Variants have no weight, only overrides can select them".into(),
+        ));
+    }
+
+    // Strategies are indexed in evaluation order, the same as in evaluation reasons
+    let strategies = sorted_strategies(toggle.strategies.as_deref().unwrap_or_default());
+    for (strategy_index, strategy) in strategies.into_iter().enumerate() {
+        let mut report = |code, constraint_index, segment_id, message| {
+            issues.push(ValidationIssue {
+                code,
+                toggle_name: toggle.name.clone(),
+                strategy_index: Some(strategy_index),
+                constraint_index,
+                segment_id,
+                message,
+            })
+        };
+
+        if zero_weight(strategy.variants.iter().flatten().map(|v| v.weight)) {
+            report(
+                ValidationCode::ZeroWeightVariants,
+                None,
+                None,
+                "Strategy variants have no weight, the strategy will never select one".into(),
+            );
+        }
+
+        for (code, message) in strategy_issues(strategy) {
+            report(code, None, None, message);
+        }
+
+        for (constraint_index, constraint) in strategy.constraints.iter().flatten().enumerate() {
+            for (code, message) in constraint_issues(constraint) {
+                report(code, Some(constraint_index), None, message);
+            }
+        }
+
+        for segment_id in strategy.segments.iter().flatten() {
+            let Some(segment) = segments.get(segment_id) else {
+                report(
+                    ValidationCode::UnresolvableSegment,
+                    None,
+                    Some(*segment_id),
+                    format!("Segment {segment_id} does not exist, the toggle will always be off"),
+                );
+                continue;
+            };
+            for (constraint_index, constraint) in segment.constraints.iter().enumerate() {
+                for (code, message) in constraint_issues(constraint) {
+                    report(code, Some(constraint_index), Some(*segment_id), message);
+                }
+            }
+        }
+    }
+}
+
+fn zero_weight(weights: impl Iterator<Item = i32>) -> bool {
+    let mut weights = weights.peekable();
+    weights.peek().is_some() && weights.all(|weight| weight <= 0)
+}
+
+fn toggle_issue(toggle: &ClientFeature, code: ValidationCode, message: String) -> ValidationIssue {
+    ValidationIssue {
+        code,
+        toggle_name: toggle.name.clone(),
+        strategy_index: None,
+        constraint_index: None,
+        segment_id: None,
+        message,
+    }
+}
+
+fn strategy_issues(strategy: &Strategy) -> Vec<(ValidationCode, String)> {
+    let mut issues = vec![];
+
+    match StrategyType::from(strategy.name.as_str()) {
+        StrategyType::FlexibleRollout => issues.extend(fractional_rollout_issue(strategy)),
+        StrategyType::GradualRolloutUserId
+        | StrategyType::GradualRolloutSessionId
+        | StrategyType::GradualRolloutRandom => issues.extend(whole_rollout_issue(strategy)),
+        StrategyType::RemoteAddress => {
+            if let Some(addresses) = strategy.get_param("IPs") {
+                issues.extend(cidr_issues(addresses.split(',').map(str::trim)));
+            }
+        }
+        _ => {}
+    };
+
+    issues
+}
+
+fn whole_rollout_issue(strategy: &Strategy) -> Option<(ValidationCode, String)> {
+    let rollout = strategy.get_param("percentage")?;
+    match rollout.parse::<usize>() {
+        Ok(rollout) if rollout > 100 => Some((
+            ValidationCode::RolloutOutOfRange,
+            format!("Rollout {rollout} is over 100%, the toggle will always be off"),
+        )),
+        Ok(_) => None,
+        Err(_) => Some((
+            ValidationCode::NonNumericRollout,
+            format!("Rollout {rollout} is not a whole number, the strategy will never match"),
+        )),
+    }
+}
+
+fn fractional_rollout_issue(strategy: &Strategy) -> Option<(ValidationCode, String)> {
+    let rollout = strategy.get_param("rollout")?;
+    match get_rollout_percentage(strategy, "rollout") {
+        Some(Ok(_)) => None,
+        Some(Err(_)) => Some((
+            ValidationCode::RolloutOutOfRange,
+            format!(
+                "Rollout {rollout} is not a percentage with at most two decimals, the toggle will always be off"
+            ),
+        )),
+        None => Some((
+            ValidationCode::NonNumericRollout,
+            format!("Rollout {rollout} is not a number, the strategy will never match"),
+        )),
+    }
+}
+
+fn constraint_issues(constraint: &Constraint) -> Vec<(ValidationCode, String)> {
+    let value = constraint.value.as_deref().unwrap_or_default();
+
+    let issue = match &constraint.operator {
+        Operator::Unknown(operator) => Some((
+            ValidationCode::UnknownOperator,
+            format!("Operator {operator} is not supported, the toggle will always be off"),
+        )),
+        Operator::InCidr => {
+            return cidr_issues(constraint.values.iter().flatten().map(String::as_str));
+        }
+        Operator::RegexMatch => Regex::new(value)
+            .err()
+            .map(|e| (ValidationCode::InvalidRegex, format!("Invalid regex: {e}"))),
+        Operator::DateAfter | Operator::DateBefore => date_literal(value).is_err().then(|| {
+            (
+                ValidationCode::InvalidDate,
+                format!("{value} is not a date"),
+            )
+        }),
+        Operator::NumEq
+        | Operator::NumGt
+        | Operator::NumGte
+        | Operator::NumLt
+        | Operator::NumLte => numeric_literal(value).is_err().then(|| {
+            (
+                ValidationCode::InvalidNumber,
+                format!("{value} is not a number"),
+            )
+        }),
+        Operator::SemverEq
+        | Operator::SemverLt
+        | Operator::SemverGt
+        | Operator::SemverLte
+        | Operator::SemverGte => {
+            (value.starts_with('v') || semver_literal(value).is_err()).then(|| {
+                (
+                    ValidationCode::InvalidSemver,
+                    format!("{value} is not a semver version"),
+                )
+            })
+        }
+        Operator::In
+        | Operator::NotIn
+        | Operator::StrEndsWith
+        | Operator::StrStartsWith
+        | Operator::StrContains => None,
+    };
+
+    issue.into_iter().collect()
+}
+
+// Ranges that don't parse are dropped when the constraint is compiled
+fn cidr_issues<'a>(ranges: impl Iterator<Item = &'a str>) -> Vec<(ValidationCode, String)> {
+    ranges
+        .filter(|range| IpNetwork::from_str(range).is_err())
+        .map(|range| {
+            (
+                ValidationCode::InvalidCidr,
+                format!("{range} is not an IP address or CIDR range, it will never match"),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn report(features: serde_json::Value) -> ValidationReport {
+        validate(
+            &serde_json::from_value(json!({
+                "version": 2,
+                "features": features,
+                "segments": [{
+                    "id": 1,
+                    "constraints": [
+                        { "contextName": "userId", "operator": "IN", "values": ["1"] },
+                        { "contextName": "version", "operator": "SEMVER_EQ", "value": "one" }
+                    ]
+                }]
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn constrained(operator: &str, value: serde_json::Value) -> serde_json::Value {
+        let mut constraint = json!({ "contextName": "property", "operator": operator });
+        let key = if value.is_array() { "values" } else { "value" };
+        constraint[key] = value;
+        json!([{
+            "name": "toggle",
+            "enabled": true,
+            "strategies": [
+                { "name": "default" },
+                {
+                    "name": "default",
+                    "constraints": [
+                        { "contextName": "userId", "operator": "IN", "values": ["1"] },
+                        constraint
+                    ]
+                }
+            ]
+        }])
+    }
+
+    #[test_case("NOT_AN_OPERATOR", json!("1"), ValidationCode::UnknownOperator)]
+    #[test_case("SEMVER_GT", json!("1.2"), ValidationCode::InvalidSemver)]
+    #[test_case("SEMVER_GT", json!("v1.2.3"), ValidationCode::InvalidSemver; "v prefixed semver")]
+    #[test_case("DATE_AFTER", json!("tomorrow"), ValidationCode::InvalidDate)]
+    #[test_case("NUM_LT", json!("ten"), ValidationCode::InvalidNumber)]
+    #[test_case("REGEX", json!("[a-"), ValidationCode::InvalidRegex)]
+    #[test_case("IN_CIDR", json!(["10.0.0.0/8", "10.0.0.0/99"]), ValidationCode::InvalidCidr)]
+    fn reports_invalid_constraints_where_they_are(
+        operator: &str,
+        value: serde_json::Value,
+        code: ValidationCode,
+    ) {
+        let report = report(constrained(operator, value));
+
+        assert_eq!(report.issues.len(), 1, "{report:?}");
+        let issue = &report.issues[0];
+        assert_eq!(issue.code, code);
+        assert_eq!(issue.toggle_name, "toggle");
+        assert_eq!(issue.strategy_index, Some(1));
+        assert_eq!(issue.constraint_index, Some(1));
+        assert_eq!(issue.segment_id, None);
+    }
+
+    #[test_case("SEMVER_GT", json!("1.2.3"))]
+    #[test_case("DATE_AFTER", json!("2024-01-01T00:00:00.000Z"))]
+    #[test_case("NUM_LT", json!("10.5"))]
+    #[test_case("REGEX", json!("^a+$"))]
+    #[test_case("IN_CIDR", json!(["10.0.0.0/8", "192.168.0.1", "::1"]))]
+    #[test_case("STR_CONTAINS", json!(["anything"]))]
+    fn valid_constraints_are_not_reported(operator: &str, value: serde_json::Value) {
+        assert!(report(constrained(operator, value)).is_valid());
+    }
+
+    #[test]
+    fn reports_segment_problems_against_the_strategy_using_them() {
+        let report = report(json!([{
+            "name": "toggle",
+            "enabled": true,
+            "strategies": [
+                { "name": "default", "segments": [1] },
+                { "name": "default", "segments": [2] }
+            ]
+        }]));
+
+        let issues: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| {
+                (
+                    issue.code,
+                    issue.strategy_index,
+                    issue.constraint_index,
+                    issue.segment_id,
+                )
+            })
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (ValidationCode::InvalidSemver, Some(0), Some(1), Some(1)),
+                (ValidationCode::UnresolvableSegment, Some(1), None, Some(2)),
+            ]
+        );
+    }
+
+    #[test_case(
+        "flexibleRollout",
+        "rollout",
+        "fifty",
+        Some(ValidationCode::NonNumericRollout)
+    )]
+    #[test_case("flexibleRollout", "rollout", "50.5", None; "fractional rollout")]
+    #[test_case("flexibleRollout", "rollout", "50.555", Some(ValidationCode::RolloutOutOfRange); "overly precise rollout")]
+    #[test_case("gradualRolloutUserId", "percentage", "50.5", Some(ValidationCode::NonNumericRollout); "fractional gradual rollout")]
+    #[test_case(
+        "flexibleRollout",
+        "rollout",
+        "150",
+        Some(ValidationCode::RolloutOutOfRange)
+    )]
+    #[test_case(
+        "gradualRolloutRandom",
+        "percentage",
+        "half",
+        Some(ValidationCode::NonNumericRollout)
+    )]
+    #[test_case("gradualRolloutUserId", "percentage", "50", None)]
+    #[test_case(
+        "remoteAddress",
+        "IPs",
+        "10.0.0.1, nonsense",
+        Some(ValidationCode::InvalidCidr)
+    )]
+    fn reports_strategy_parameters(
+        strategy: &str,
+        parameter: &str,
+        value: &str,
+        expected: Option<ValidationCode>,
+    ) {
+        let report = report(json!([{
+            "name": "toggle",
+            "enabled": true,
+            "strategies": [{
+                "name": strategy,
+                "parameters": { parameter: value, "groupId": "toggle" }
+            }]
+        }]));
+
+        let codes: Vec<_> = report.issues.iter().map(|issue| issue.code).collect();
+        assert_eq!(codes, Vec::from_iter(expected));
+        assert!(report
+            .issues
+            .iter()
+            .all(|issue| issue.strategy_index == Some(0) && issue.constraint_index.is_none()));
+    }
+
+    #[test]
+    fn reports_duplicate_toggles_and_weightless_variants() {
+        let report = report(json!([
+            { "name": "toggle", "enabled": true },
+            {
+                "name": "toggle",
+                "enabled": false,
+                "variants": [
+                    { "name": "a", "weight": 0 },
+                    { "name": "b", "weight": 0 }
+                ]
+            },
+            {
+                "name": "strategy-variants",
+                "enabled": true,
+                "strategies": [
+                    { "name": "default" },
+                    { "name": "default", "variants": [{ "name": "a", "weight": 0 }] }
+                ]
+            },
+            {
+                "name": "weighted",
+                "enabled": true,
+                "variants": [{ "name": "a", "weight": 1000 }]
+            }
+        ]));
+
+        let codes: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.code, issue.toggle_name.as_str(), issue.strategy_index))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                (ValidationCode::DuplicateToggleName, "toggle", None),
+                (ValidationCode::ZeroWeightVariants, "toggle", None),
+                (
+                    ValidationCode::ZeroWeightVariants,
+                    "strategy-variants",
+                    Some(1)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_serialize_with_machine_readable_codes() {
+        let report = report(constrained("NUM_LT", json!("ten")));
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "issues": [{
+                    "code": "INVALID_NUMBER",
+                    "toggleName": "toggle",
+                    "strategyIndex": 1,
+                    "constraintIndex": 1,
+                    "segmentId": null,
+                    "message": "ten is not a number"
+                }]
+            })
+        );
+    }
+}
@@ -1,5 +1,55 @@
+use std::collections::HashMap;
+
 use proptest::prelude::*;
-use unleash_yggdrasil::strategy_parsing::compile_rule;
+use unleash_types::client_features::{Constraint, Operator, Strategy as ToggleStrategy};
+use unleash_yggdrasil::custom_strategy::CustomStrategies;
+use unleash_yggdrasil::state::EnrichedContext;
+use unleash_yggdrasil::strategy_ast::{ConstraintKind, ContextField, Percentage, RuleAst};
+use unleash_yggdrasil::strategy_lowering::lower;
+use unleash_yggdrasil::strategy_parsing::{
+    compile_rule, normalized_fine_hash, normalized_hash, parse_rule,
+};
+use unleash_yggdrasil::strategy_upgrade::{escape_string, upgrade};
+use unleash_yggdrasil::Context;
+
+fn constraint() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just("true".to_string()),
+        Just("false".to_string()),
+        "[1-5]".prop_map(|user_id| format!("user_id in [\"{user_id}\"]")),
+        "[ab]".prop_map(|prefix| format!("app_name starts_with_any [\"{prefix}\"]")),
+        (0..=100u8).prop_map(|percentage| format!("{percentage}% sticky on user_id")),
+        (0..=10000u16).prop_map(|hundredths| format!(
+            "{}% sticky on user_id",
+            Percentage::from_hundredths(hundredths).unwrap()
+        )),
+    ]
+}
+
+// Arbitrary rules with every compound wrapped in parentheses, so they can be dropped
+// into a larger rule as a single term
+fn rule() -> impl Strategy<Value = String> {
+    constraint().prop_recursive(3, 16, 2, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(lhs, rhs)| format!("({lhs} and {rhs})")),
+            (inner.clone(), inner.clone()).prop_map(|(lhs, rhs)| format!("({lhs} or {rhs})")),
+            inner.prop_map(|rule| format!("!({rule})")),
+        ]
+    })
+}
+
+fn context() -> impl Strategy<Value = Context> {
+    ("[1-5]", "[ab][a-z]?").prop_map(|(user_id, app_name)| Context {
+        user_id: Some(user_id),
+        app_name: Some(app_name),
+        ..Context::default()
+    })
+}
+
+fn evaluate(rule: &str, context: &Context) -> bool {
+    let rule = compile_rule(rule).unwrap();
+    rule(&EnrichedContext::from(context, "toggle", None))
+}
 
 proptest! {
     #[test]
@@ -9,4 +59,110 @@ proptest! {
         let result = compile_rule(&rule);
         prop_assert!(result.is_ok());
     }
+
+    #[test]
+    fn lowering_matches_upgraded_text(
+        values in prop::collection::vec(any::<String>(), 1..4),
+        context_name in "[a-zA-Z]{1,10}",
+        inverted in any::<bool>(),
+        operator in prop_oneof![
+            Just(Operator::In),
+            Just(Operator::NotIn),
+            Just(Operator::StrStartsWith),
+            Just(Operator::StrContains),
+        ],
+    ) {
+        let strategy = ToggleStrategy {
+            name: "default".into(),
+            parameters: None,
+            constraints: Some(vec![Constraint {
+                context_name,
+                operator,
+                case_insensitive: false,
+                inverted,
+                values: Some(values),
+                value: None,
+            }]),
+            segments: None,
+            sort_order: None,
+            variants: None,
+        };
+
+        let strategies = [strategy];
+        let from_text = upgrade(&strategies, &HashMap::new()).and_then(|rule| parse_rule(&rule));
+        let lowered = lower(&strategies, &HashMap::new(), &CustomStrategies::default());
+        prop_assert_eq!(from_text.ok(), lowered.ok());
+    }
+
+    #[test]
+    fn negated_or_is_and_of_negations(lhs in rule(), rhs in rule(), context in context()) {
+        prop_assert_eq!(
+            evaluate(&format!("!({lhs} or {rhs})"), &context),
+            evaluate(&format!("!{lhs} and !{rhs}"), &context)
+        );
+    }
+
+    #[test]
+    fn negated_and_is_or_of_negations(lhs in rule(), rhs in rule(), context in context()) {
+        prop_assert_eq!(
+            evaluate(&format!("not ({lhs} and {rhs})"), &context),
+            evaluate(&format!("not {lhs} or not {rhs}"), &context)
+        );
+    }
+
+    #[test]
+    fn double_negation_cancels_out(rule in rule(), context in context()) {
+        prop_assert_eq!(evaluate(&format!("!not {rule}"), &context), evaluate(&rule, &context));
+    }
+
+    #[test]
+    fn negated_rules_round_trip_through_display(rule in rule()) {
+        let ast = parse_rule(&rule).unwrap();
+        prop_assert_eq!(parse_rule(&ast.to_string()).unwrap(), ast);
+    }
+
+    #[test]
+    fn escaped_strings_parse_back_to_the_same_value(value in any::<String>()) {
+        let rule = format!("context[\"{0}\"] matches_regex \"{0}\"", escape_string(&value));
+
+        let expected = RuleAst::constraint(ConstraintKind::Regex {
+            context: ContextField::Property(value.clone()),
+            ignore_case: false,
+            pattern: value,
+        });
+        prop_assert_eq!(parse_rule(&rule).unwrap(), expected.clone());
+        prop_assert_eq!(parse_rule(&expected.to_string()).unwrap(), expected);
+    }
+
+    #[test]
+    fn escaped_strings_are_valid_json_strings(value in any::<String>()) {
+        let json = format!("\"{}\"", escape_string(&value));
+        prop_assert_eq!(serde_json::from_str::<String>(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn whole_percent_buckets_follow_normalized_hash(group in any::<String>(), identifier in any::<String>()) {
+        let bucket = normalized_fine_hash(&group, &identifier, 0).unwrap();
+        prop_assert!((1..=10000).contains(&bucket));
+        prop_assert_eq!((bucket - 1) / 100 + 1, normalized_hash(&group, &identifier, 100, 0).unwrap());
+    }
+
+    #[test]
+    fn whole_percent_rollouts_keep_their_users(
+        group_id in "[a-zA-Z0-9.]{1,20}",
+        user_id in any::<String>(),
+        percentage in 0..=100u32,
+    ) {
+        let rule = format!("{percentage}% sticky on user_id with group_id of \"{group_id}\"");
+        let context = Context { user_id: Some(user_id.clone()), ..Context::default() };
+
+        let included = normalized_hash(&group_id, &user_id, 100, 0).unwrap() <= percentage;
+        prop_assert_eq!(evaluate(&rule, &context), included);
+    }
+
+    #[test]
+    fn percentages_round_trip_through_display(hundredths in 0..=10000u16) {
+        let percentage = Percentage::from_hundredths(hundredths).unwrap();
+        prop_assert_eq!(percentage.to_string().parse::<Percentage>().unwrap(), percentage);
+    }
 }